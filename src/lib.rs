@@ -1,349 +1,8079 @@
-use std::{thread, time::Duration};
+use std::{
+    error::Error,
+    fs,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
 use sdl2::{
-    event::Event,
+    event::{Event, WindowEvent},
     gfx::primitives::DrawRenderer,
-    keyboard::Keycode,
-    pixels::Color,
+    keyboard::{Keycode, Mod},
+    mouse::MouseButton,
+    pixels::{Color, PixelFormatEnum},
     rect::{Point, Rect},
-    render::WindowCanvas,
+    render::{BlendMode, Canvas, RenderTarget, Texture, TextureCreator, WindowCanvas},
+    surface::{Surface, SurfaceContext},
+    sys::SDL_RendererFlags,
+    ttf::{Font, Sdl2TtfContext},
+    video::{FullscreenType, Window, WindowContext, WindowPos},
+};
+use serde::{Deserialize, Serialize};
+
+pub mod physics;
+
+pub use physics::{
+    Bounds, ChargedParticle, Comb, ContourSegment, Dipole, FieldLine, GroundPlane, LineCharge,
+    Multipole, Preset, Scene, TestCharge,
 };
+use physics::{
+    CHARGE_STEP, COULUMBS_CONST, DEFAULT_COMB_LINES, DEFAULT_DIPOLE_SEPARATION,
+    DEFAULT_LINES_PER_CHARGE, DEFAULT_METERS_PER_PIXEL, LINE_CHARGE_WIDTH, MAX_COMB_LINES,
+    MAX_LINE_ITERS, PARTICLE_RADIUS, TEST_CHARGE_TRAIL_LENGTH, VACUUM_PERMITTIVITY,
+};
+
+// Set from --verbose, which prints feedback on every action to the terminal on top of the
+// status bar
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+// Set while the frames are piped to stdout, where any text would end up between them
+static PIPING: AtomicBool = AtomicBool::new(false);
+
+// Like println, but to stderr while the frames are piped to stdout
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if PIPING.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+
+// Like say, but only with --verbose. Anything the user has to see, like where a file was
+// saved or why something failed, goes through Game::notify instead.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        if VERBOSE.load(Ordering::Relaxed) {
+            say!($($arg)*);
+        }
+    };
+}
+
+// Setting this environment variable prints field line tracing times
+const TIMING_ENV_VAR: &str = "EFIELD_TIMING";
+// Field lines are cut off this far outside the play area, in pixels
+const FIELD_LINE_MARGIN: f64 = 20.0;
+// Spacing of the grid placement snaps to, and the closest its dots get drawn on screen
+const GRID_PITCH: f64 = 25.0;
+const MIN_GRID_DOT_SPACING: f64 = 4.0;
+// Resolution of the grid the field is sampled on for CSV export
+const CSV_COLUMNS: usize = 100;
+const CSV_ROWS: usize = 75;
+// Spacing of the grid the potential is sampled on for equipotentials, in pixels, and the color
+// they're drawn in
+const EQUIPOTENTIAL_CELL_SIZE: f64 = 8.0;
+const EQUIPOTENTIAL_COLOR: Color = Color::RGB(0, 200, 0);
+// Size of the square blocks of pixels that share one heatmap sample
+const HEATMAP_BLOCK_SIZE: u32 = 4;
+// Size of the heatmap's color scale along the right of the play area, how far it is from the
+// edges and how close to its ends a click grabs them, in pixels
+const LEGEND_WIDTH: u32 = 16;
+const LEGEND_HEIGHT: u32 = 200;
+const LEGEND_MARGIN: i32 = 30;
+const LEGEND_GRAB_DISTANCE: i32 = 6;
+// Narrowest the heatmap's range can be dragged to, in decades of field strength
+const MIN_HEATMAP_RANGE: f64 = 0.1;
+// Distance between the sample points of the arrow grid, in pixels
+const ARROW_GRID_SPACING: u32 = 40;
+// Range of lengths of the net force arrows drawn on particles, in pixels
+const FORCE_ARROW_MIN_LENGTH: f64 = 15.0;
+const FORCE_ARROW_MAX_LENGTH: f64 = 60.0;
+const FORCE_ARROW_COLOR: Color = Color::RGB(255, 140, 0);
+// Color of the arrow grid of the multipole expansion drawn over the exact one
+const MULTIPOLE_ARROW_COLOR: Color = Color::RGB(255, 80, 255);
+// Where the multipole moments are listed, and the spacing of their lines without a font
+const MOMENTS_PANEL_POS: (i32, i32) = (8, 24);
+const LABEL_FONT_SIZE: u16 = 14;
+// Height of the strip at the bottom of the toolbar showing the placement charge, and the size
+// of the ticks in it, in pixels
+const CHARGE_INDICATOR_HEIGHT: u32 = 60;
+// Height of the status line along the bottom of the window, below the play area and toolbar
+const STATUS_BAR_HEIGHT: u32 = 24;
+const STATUS_BAR_PADDING: i32 = 6;
+// How long a notice like a saved file's path replaces the status line, and the color of
+// notices about failures
+const NOTICE_DURATION: Duration = Duration::from_secs(4);
+const NOTICE_ERROR_COLOR: Color = Color::RGB(235, 90, 80);
+// Outline of a placement preview where a click would place the charge
+const PLACEMENT_OK_COLOR: Color = Color::RGB(90, 200, 110);
+const TICK_HEIGHT: i32 = 10;
+const TICK_SPACING: i32 = 6;
+// Arc length between the arrowheads along field lines on screen, and the arrowhead size, in
+// pixels
+const FIELD_ARROW_SPACING: f64 = 80.0;
+const FIELD_ARROW_SIZE: f64 = 5.0;
+// Thickness of the ground plane in pixels
+const GROUND_PLANE_WIDTH: u32 = 6;
+// Gap between a selected particle and the ring drawn around it, in pixels
+const SELECTION_RING_GAP: i32 = 4;
+// Spacing of the rings colorblind themes draw inside particles, in pixels
+const PARTICLE_RING_INSET: i32 = 2;
+// Length of the dashes and the gaps between them along dashed lines like the measuring line, in
+// pixels
+const DASH_LENGTH: f64 = 8.0;
+// How far outside the window segments drawn with gfx are clipped, in pixels, enough to hide the
+// ends of the thickest lines
+const GFX_CLIP_MARGIN: f64 = 16.0;
+// Opacity of the mirror mode axes
+const MIRROR_AXIS_ALPHA: u8 = 90;
+// Radius test charges are drawn with, in pixels
+const TEST_CHARGE_RADIUS: i16 = 3;
+// Samples along each side of the Gaussian surface when integrating the flux through it
+const GAUSS_SAMPLES_PER_SIDE: usize = 300;
+// Length of the arrow drawn at the cursor, in pixels
+const PROBE_ARROW_LENGTH: f64 = 30.0;
+// GIFs are kept at most this wide by downscaling
+const GIF_MAX_WIDTH: u32 = 640;
+// NeuQuant sampling factor for GIF palettes, 1 is best quality and 30 is fastest
+const GIF_QUANTIZE_SPEED: i32 = 20;
+// Longest two left clicks on the same particle can be apart to flip its charge, in
+// milliseconds
+const DOUBLE_CLICK_MS: u32 = 400;
+// Most edits kept around for Ctrl+Z, the oldest are dropped first
+const MAX_UNDO_STEPS: usize = 100;
+// Time step of trajectory previews in seconds, the most steps one is integrated for, and how
+// they're drawn. Dots are every step and ticks every `prediction_tick` seconds.
+const PREDICTION_STEP: f64 = 1.0 / 60.0;
+const MAX_PREDICTION_STEPS: usize = 3600;
+const PREDICTION_COLOR: Color = Color::RGB(120, 200, 255);
+const PREDICTION_TICK_SIZE: f64 = 5.0;
+// Combs and the lines they seed, which are drawn apart from the charges' lines
+const COMB_COLOR: Color = Color::RGB(0, 170, 190);
+// Screen pixels of the dots marking a comb's seeds, and world pixels of the handles on its ends
+// that the move tool drags
+const COMB_SEED_RADIUS: i16 = 2;
+const COMB_HANDLE_RADIUS: f64 = 6.0;
+// Every key and mouse binding, as listed by the help overlay
+const KEYBINDINGS: &[(&str, &str)] = &[
+    ("H", "Show or hide this help"),
+    ("Left click", "Use the selected tool"),
+    ("Right click", "Erase the charge under the cursor"),
+    (
+        "Double click",
+        "Flip a particle's charge with the place or move tools, neutral ones are left as they are",
+    ),
+    ("Middle drag", "Pan the view"),
+    (
+        "Scroll",
+        "Zoom, or change the charge of the particle or the lines of the comb under the cursor",
+    ),
+    ("Shift+Scroll", "Change the charge of a particle faster"),
+    ("= / -", "Step the selected or placement charge up or down"),
+    ("Shift/Ctrl+= / -", "Step the charge by 10e or 0.1e"),
+    ("1 - 9", "Select the toolbar tools from the top down"),
+    ("N", "Make the selected or placement charge neutral"),
+    (
+        "C",
+        "Type the selected or placement charge in e, Enter sets it. For a selected dipole type \
+         its charge, separation in m and angle in degrees separated by commas",
+    ),
+    ("Delete", "Remove the selected particle or dipole"),
+    ("Ctrl+C", "Copy the selected particle"),
+    ("Ctrl+Shift+C", "Clear every charge from the scene"),
+    ("Ctrl+V", "Click to paste the copied particle"),
+    ("Ctrl+Z", "Undo the last change to the charges"),
+    (
+        "Alt (held)",
+        "Preview where the selected particle would go if it were let go",
+    ),
+    (
+        "F1 - F5",
+        "Load the dipole, quadrupole, plates, ring or atomic hydrogen preset",
+    ),
+    ("Space", "Start or pause dynamics"),
+    ("E", "Show or hide equipotentials"),
+    (
+        "Shift+click",
+        "Add a particle to the selection of the move tool, E then edits them in a table",
+    ),
+    ("M", "Show or hide the field strength heatmap"),
+    (
+        "Click legend",
+        "Lock the heatmap's range or let it follow the field, drag its ends to set the range",
+    ),
+    ("V", "Show or hide the field arrow grid"),
+    (
+        "L",
+        "Toggle drawing each positive charge's field lines in a color of its own",
+    ),
+    ("F", "Show or hide the net force on each particle"),
+    ("A", "Toggle drawing field lines growing out of the charges"),
+    ("G", "Toggle snapping to the grid"),
+    (
+        "X",
+        "Cycle mirroring placements across the vertical, horizontal or both axes",
+    ),
+    (
+        "Shift+X",
+        "Toggle giving mirrored copies the opposite charge",
+    ),
+    (
+        "Arrow keys",
+        "Strengthen the uniform external field in that direction",
+    ),
+    ("0", "Turn off the external field"),
+    ("B", "Toggle periodic boundaries"),
+    ("T", "Switch between the dark and light theme"),
+    ("K", "Toggle the colorblind-friendly palette"),
+    (
+        "O",
+        "Cycle the multipole order drawn and compared at the cursor",
+    ),
+    ("Shift+O", "Heatmap the error of the multipole expansion"),
+    ("I", "Show and print the multipole moments"),
+    (
+        "S",
+        "Save a screenshot, Shift leaves out the toolbar and status bar",
+    ),
+    ("R", "Start or stop recording a GIF"),
+    ("Ctrl+S / Ctrl+O", "Save or load the scene"),
+    (
+        "Ctrl+Shift+O",
+        "Add the charges of the --merge scene, or the scene file, around the cursor",
+    ),
+    ("Ctrl+E", "Export the field to CSV"),
+    ("Ctrl+Shift+S", "Export the scene as an SVG"),
+    (
+        "P",
+        "Click to drop a test charge, or clear them all if pressed again",
+    ),
+    (
+        "Escape",
+        "Deselect, cancel pasting, dismiss the Gaussian surface and measurement and clear \
+         test charges",
+    ),
+    ("F11", "Toggle fullscreen"),
+    ("Q", "Quit"),
+];
+// Opacity of the help overlay and the offset of the descriptions from the keys, in pixels
+const HELP_ALPHA: u8 = 220;
+const HELP_KEY_COLUMN_WIDTH: i32 = 140;
+// Layout of the batch edit table in pixels. Rows are a line of text high, or the fallback
+// height without a font.
+const BATCH_EDIT_MARGIN: i32 = 20;
+const BATCH_EDIT_COLUMN_WIDTH: i32 = 110;
+const BATCH_EDIT_ROW_HEIGHT: i32 = 20;
+const BATCH_EDIT_PADDING: i32 = 4;
+// Fonts tried in order for on-canvas text, text is skipped if none of them can be loaded
+const FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "/Library/Fonts/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+// Frame rate aimed for when the display doesn't pace presents with vsync
+pub const DEFAULT_TARGET_FPS: u32 = 60;
+// Points added to every field line per frame while they're drawn growing out of the charges
+pub const DEFAULT_LINE_GROWTH: u32 = 20;
+// How far ahead trajectory previews look and the time between their ticks, in seconds
+pub const DEFAULT_PREDICTION_HORIZON: f64 = 2.0;
+pub const DEFAULT_PREDICTION_TICK: f64 = 0.25;
+// Every this many frames drawn, one is recorded to the GIF
+pub const DEFAULT_RECORD_STRIDE: u32 = 4;
+// Frame rate while the window is visible but not focused
+const UNFOCUSED_FPS: u32 = 10;
+// Longest frame fed to the simulation, so a stall like dragging the window doesn't make
+// everything jump
+const MAX_FRAME_DT: f64 = 0.1;
+// Seconds an orbit of the hydrogen preset takes on screen, which sets its time scale
+const HYDROGEN_ORBIT_SECONDS: f64 = 3.0;
+// Shown while the hydrogen preset's atomic scale is in use
+const CLASSICAL_NOTE: &str = "Classical model: the electron orbits like a planet, there's no \
+                              quantum mechanics or radiation";
+// SI prefixes format_si picks from, by the power of ten they stand for
+const SI_PREFIXES: [(f64, &str); 11] = [
+    (1e-18, "a"),
+    (1e-15, "f"),
+    (1e-12, "p"),
+    (1e-9, "n"),
+    (1e-6, "\u{b5}"),
+    (1e-3, "m"),
+    (1.0, ""),
+    (1e3, "k"),
+    (1e6, "M"),
+    (1e9, "G"),
+    (1e12, "T"),
+];
+// How long to block waiting for events while minimized
+const MINIMIZED_WAIT_MS: u32 = 250;
+const MIN_ZOOM: f64 = 0.1;
+const MAX_ZOOM: f64 = 10.0;
+// Charge steps per scroll wheel tick over a particle while holding Shift
+const FAST_CHARGE_STEPS: f64 = 5.0;
+// Charge steps per press of = or - while holding Shift or Ctrl
+const COARSE_CHARGE_STEPS: f64 = 10.0;
+const FINE_CHARGE_STEPS: f64 = 0.1;
+// Charges smaller than half the finest step are rounding error left over from stepping and
+// are snapped to zero
+const CHARGE_SNAP: f64 = 0.5 * FINE_CHARGE_STEPS * CHARGE_STEP;
+// Each arrow key press changes the external field by the field of an elementary charge this
+// many pixels away, so the step looks the same at any scale
+const EXTERNAL_FIELD_STEP_DISTANCE: f64 = 100.0;
+// Zoom factor applied per scroll wheel tick
+const ZOOM_STEP: f64 = 1.1;
+
+// Colors of the scene and the toolbar. Overlays like the probe and the heatmap keep their own
+// colors in every theme.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Theme {
+    background: Color,
+    line: Color,
+    positive: Color,
+    negative: Color,
+    neutral: Color,
+    toolbar: Color,
+    // Plus and minus signs drawn on particles
+    glyph: Color,
+    // Charge labels, the selected tool outline and the move tool icon
+    text: Color,
+    dark: bool,
+    // Marks the sign of particles by shape as well, so it can be read without telling the
+    // colors apart
+    colorblind: bool,
+}
+
+impl Theme {
+    const DARK: Theme = Theme {
+        background: Color::BLACK,
+        line: Color::WHITE,
+        positive: Color::RGB(255, 0, 0),
+        negative: Color::RGB(0, 0, 255),
+        neutral: Color::RGB(128, 128, 128),
+        toolbar: Color::GRAY,
+        glyph: Color::WHITE,
+        text: Color::WHITE,
+        dark: true,
+        colorblind: false,
+    };
+
+    // Particles are lighter so the dark glyphs on them stand out
+    const LIGHT: Theme = Theme {
+        background: Color::RGB(245, 245, 245),
+        line: Color::RGB(40, 40, 40),
+        positive: Color::RGB(255, 110, 110),
+        negative: Color::RGB(110, 140, 255),
+        neutral: Color::RGB(190, 190, 190),
+        toolbar: Color::RGB(200, 200, 200),
+        glyph: Color::BLACK,
+        text: Color::BLACK,
+        dark: false,
+        colorblind: false,
+    };
+
+    // Orange and sky blue from the Okabe-Ito palette, with dark glyphs for contrast on them
+    const DARK_COLORBLIND: Theme = Theme {
+        positive: Color::RGB(230, 159, 0),
+        negative: Color::RGB(86, 180, 233),
+        glyph: Color::BLACK,
+        colorblind: true,
+        ..Theme::DARK
+    };
+
+    // Okabe-Ito's vermillion and blue, which are dark enough for white glyphs
+    const LIGHT_COLORBLIND: Theme = Theme {
+        positive: Color::RGB(213, 94, 0),
+        negative: Color::RGB(0, 114, 178),
+        glyph: Color::WHITE,
+        colorblind: true,
+        ..Theme::LIGHT
+    };
+
+    fn new(dark: bool, colorblind: bool) -> Self {
+        match (dark, colorblind) {
+            (true, false) => Theme::DARK,
+            (false, false) => Theme::LIGHT,
+            (true, true) => Theme::DARK_COLORBLIND,
+            (false, true) => Theme::LIGHT_COLORBLIND,
+        }
+    }
+
+    fn particle_color(&self, particle: Particle) -> Color {
+        match particle {
+            Particle::Positive => self.positive,
+            Particle::Negative => self.negative,
+            Particle::Neutral => self.neutral,
+        }
+    }
+
+    fn toggled(self) -> Self {
+        Theme::new(!self.dark, self.colorblind)
+    }
+
+    fn colorblind_toggled(self) -> Self {
+        Theme::new(self.dark, !self.colorblind)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::DARK
+    }
+}
+
+// Charge added or removed by one press of = or -, Shift makes it coarser and Ctrl finer
+fn charge_key_step(keymod: Mod) -> f64 {
+    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        COARSE_CHARGE_STEPS * CHARGE_STEP
+    } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+        FINE_CHARGE_STEPS * CHARGE_STEP
+    } else {
+        CHARGE_STEP
+    }
+}
+
+// Toolbar entry picked by a number key, 1 being the top one
+fn tool_key_index(keycode: Keycode) -> Option<usize> {
+    let index = match keycode {
+        Keycode::Num1 | Keycode::Kp1 => 0,
+        Keycode::Num2 | Keycode::Kp2 => 1,
+        Keycode::Num3 | Keycode::Kp3 => 2,
+        Keycode::Num4 | Keycode::Kp4 => 3,
+        Keycode::Num5 | Keycode::Kp5 => 4,
+        Keycode::Num6 | Keycode::Kp6 => 5,
+        Keycode::Num7 | Keycode::Kp7 => 6,
+        Keycode::Num8 | Keycode::Kp8 => 7,
+        Keycode::Num9 | Keycode::Kp9 => 8,
+        _ => return None,
+    };
+
+    Some(index)
+}
+
+// Character a key types into the charge entry, if it's one a number can be made of or the
+// comma between a dipole's numbers
+fn charge_entry_char(keycode: Keycode) -> Option<char> {
+    let typed = match keycode {
+        Keycode::Num0 | Keycode::Kp0 => '0',
+        Keycode::Comma => ',',
+        Keycode::Minus | Keycode::KpMinus => '-',
+        Keycode::Period | Keycode::KpPeriod => '.',
+        _ => char::from_digit(tool_key_index(keycode)? as u32 + 1, 10)?,
+    };
+
+    Some(typed)
+}
+
+// Anything the scene can be drawn onto, a window or an off-screen surface for headless renders.
+// SDL only offers texture creators on the concrete canvas types, so this brings them under one
+// bound.
+trait SceneTarget: RenderTarget + Sized {
+    fn texture_creator(canvas: &Canvas<Self>) -> TextureCreator<Self::Context>;
+}
+
+impl SceneTarget for Window {
+    fn texture_creator(canvas: &Canvas<Self>) -> TextureCreator<WindowContext> {
+        canvas.texture_creator()
+    }
+}
 
-const PARTICLE_RADIUS: i32 = 10;
-const CHARGE_STEP: f64 = 1.602176634e-19;
-const COULUMBS_CONST: f64 = 8.9875517923e9;
-const MAX_LINE_ITERS: usize = 4096;
+impl<'s> SceneTarget for Surface<'s> {
+    fn texture_creator(canvas: &Canvas<Self>) -> TextureCreator<SurfaceContext<'s>> {
+        canvas.texture_creator()
+    }
+}
+
+// Draws a lowercase n the size of a particle's plus and minus glyphs, as a left stem joined to
+// a shorter right stem by an arch. Positions are kept in i32 until they're handed to gfx, so
+// particles near the ends of the i16 range don't wrap around.
+fn draw_n_glyph<T: SceneTarget>(
+    canvas: &mut Canvas<T>,
+    color: Color,
+    x: i32,
+    y: i32,
+    radius: i32,
+) -> Result<(), String> {
+    let thickness = (radius / 5).max(1);
+    let top = y - radius / 2;
+    let left = x - radius / 2;
+    let right = x + radius / 2 - thickness;
+
+    // Centerline of the arch, which ends in the middle of both stems
+    let arch_radius = ((right - left) / 2).max(1);
+    let arch_y = top + arch_radius + thickness / 2;
+
+    canvas.set_draw_color(color);
+    canvas.fill_rect(Rect::new(left, top, thickness as u32, radius as u32))?;
+    canvas.fill_rect(Rect::new(
+        right,
+        arch_y,
+        thickness as u32,
+        (top + radius - arch_y).max(1) as u32,
+    ))?;
+
+    let (Ok(arch_x), Ok(arch_y)) = (
+        i16::try_from(left + thickness / 2 + arch_radius),
+        i16::try_from(arch_y),
+    ) else {
+        return Ok(());
+    };
+
+    // gfx arcs are one pixel wide, so the stroke is built up from concentric ones. Angles go
+    // clockwise from the right, so 180 to 360 is the upper half.
+    for r in arch_radius - thickness / 2..arch_radius - thickness / 2 + thickness {
+        canvas.arc(arch_x, arch_y, r.max(0) as i16, 180, 360, color)?;
+    }
+
+    Ok(())
+}
+
+fn draw_particle<T: SceneTarget>(
+    canvas: &mut Canvas<T>,
+    theme: &Theme,
+    particle: Particle,
+    x: i16,
+    y: i16,
+    radius: i32,
+) -> Result<(), String> {
+    // Colorblind themes draw the glyphs thicker
+    let thickness = if theme.colorblind {
+        radius / 3
+    } else {
+        radius / 5
+    };
 
-fn draw_particle(canvas: &mut WindowCanvas, particle: Particle, x: i16, y: i16) {
     match particle {
         // Positive charged particle, draw red circle with plus in it
         Particle::Positive => {
-            canvas
-                .filled_circle(x, y, PARTICLE_RADIUS as i16, Color::RGB(255, 0, 0))
-                .unwrap();
-            canvas.set_draw_color(Color::WHITE);
-            canvas
-                .fill_rect(Rect::new(
-                    x as i32 - PARTICLE_RADIUS / 10,
-                    y as i32 - PARTICLE_RADIUS / 2,
-                    (PARTICLE_RADIUS / 5) as u32,
-                    PARTICLE_RADIUS as u32,
-                ))
-                .unwrap();
-            canvas
-                .fill_rect(Rect::new(
-                    x as i32 - PARTICLE_RADIUS / 2,
-                    y as i32 - PARTICLE_RADIUS / 10,
-                    PARTICLE_RADIUS as u32,
-                    (PARTICLE_RADIUS / 5) as u32,
-                ))
-                .unwrap();
-        }
-        // Negatively charged particle, draw blue circle with plus in it
+            canvas.filled_circle(x, y, radius as i16, theme.particle_color(particle))?;
+            canvas.set_draw_color(theme.glyph);
+            canvas.fill_rect(Rect::new(
+                x as i32 - thickness / 2,
+                y as i32 - radius / 2,
+                thickness as u32,
+                radius as u32,
+            ))?;
+            canvas.fill_rect(Rect::new(
+                x as i32 - radius / 2,
+                y as i32 - thickness / 2,
+                radius as u32,
+                thickness as u32,
+            ))?;
+        }
+        // Negatively charged particle, draw blue circle with minus in it
         Particle::Negative => {
-            canvas
-                .filled_circle(x, y, PARTICLE_RADIUS as i16, Color::RGB(0, 0, 255))
-                .unwrap();
-            canvas.set_draw_color(Color::WHITE);
-            canvas
-                .fill_rect(Rect::new(
-                    x as i32 - PARTICLE_RADIUS / 2,
-                    y as i32 - PARTICLE_RADIUS / 10,
-                    PARTICLE_RADIUS as u32,
-                    (PARTICLE_RADIUS / 5) as u32,
-                ))
-                .unwrap();
+            canvas.filled_circle(x, y, radius as i16, theme.particle_color(particle))?;
+            canvas.set_draw_color(theme.glyph);
+            canvas.fill_rect(Rect::new(
+                x as i32 - radius / 2,
+                y as i32 - thickness / 2,
+                radius as u32,
+                thickness as u32,
+            ))?;
         }
         // Neutral particle, draw gray circle with an `n` in it
         Particle::Neutral => {
-            canvas
-                .filled_circle(x, y, PARTICLE_RADIUS as i16, Color::RGB(50, 50, 50))
-                .unwrap();
+            canvas.filled_circle(x, y, radius as i16, theme.particle_color(particle))?;
+            draw_n_glyph(canvas, theme.glyph, x as i32, y as i32, radius)?;
         }
     }
-}
 
-#[derive(PartialEq, Copy, Clone)]
-enum Particle {
-    Positive,
-    Negative,
-    Neutral,
+    // Colorblind themes also ring positive particles twice and negative ones once, just inside
+    // their edge
+    if theme.colorblind {
+        for ring in 0..get_ring_count(particle) {
+            let ring_radius = radius - PARTICLE_RING_INSET * (ring + 1);
+
+            if ring_radius > 0 {
+                canvas.aa_circle(x, y, ring_radius as i16, theme.glyph)?;
+            }
+        }
+    }
+
+    Ok(())
 }
 
-struct Toolbar {
-    selected_part: Option<Particle>,
-    choices: Vec<Particle>,
+// Rings drawn inside particles of each sign by the colorblind themes
+fn get_ring_count(particle: Particle) -> i32 {
+    match particle {
+        Particle::Positive => 2,
+        Particle::Negative => 1,
+        Particle::Neutral => 0,
+    }
 }
 
-impl Default for Toolbar {
-    fn default() -> Self {
-        Self {
-            selected_part: None,
-            choices: vec![Particle::Positive, Particle::Negative, Particle::Neutral],
+fn draw_tool(
+    canvas: &mut WindowCanvas,
+    theme: &Theme,
+    tool: Tool,
+    x: i16,
+    y: i16,
+    radius: i32,
+) -> Result<(), String> {
+    let r = radius as i16;
+
+    match tool {
+        Tool::Place(particle) => draw_particle(canvas, theme, particle, x, y, radius)?,
+        // Line charge, draw a thick red diagonal
+        Tool::Line => {
+            canvas.thick_line(
+                x - r,
+                y + r,
+                x + r,
+                y - r,
+                5,
+                theme.particle_color(Particle::Positive),
+            )?;
+        }
+        // Eraser, draw a red X
+        Tool::Erase => {
+            for (dx, dy) in [(r, r), (r, -r)] {
+                canvas.thick_line(x - dx, y - dy, x + dx, y + dy, 3, Color::RGB(200, 0, 0))?;
+            }
+        }
+        // Gaussian surface, draw a square outline
+        Tool::Gauss => {
+            canvas.rectangle(x - r, y - r, x + r, y + r, theme.text)?;
+        }
+        // Measure, draw a ruler with ticks along its edge
+        Tool::Measure => {
+            canvas.rectangle(x - r, y - r / 3, x + r, y + r / 3, theme.text)?;
+            for i in 1..4 {
+                let tick_x = x - r + i * r / 2;
+                canvas.vline(
+                    tick_x,
+                    y - r / 3,
+                    y - r / 3 + r / 3 * (1 + i % 2) / 2,
+                    theme.text,
+                )?;
+            }
+        }
+        // Ground plane, draw the ground symbol of three shrinking bars
+        Tool::Ground => {
+            canvas.thick_line(x, y - r, x, y - r / 3, 2, theme.neutral)?;
+            for (i, half_width) in [r, 2 * r / 3, r / 3].into_iter().enumerate() {
+                let bar_y = y - r / 3 + i as i16 * r / 2;
+                canvas.thick_line(
+                    x - half_width,
+                    bar_y,
+                    x + half_width,
+                    bar_y,
+                    3,
+                    theme.neutral,
+                )?;
+            }
+        }
+        // Comb, draw a bar with field lines coming off it both ways
+        Tool::Comb => {
+            canvas.thick_line(x, y - r, x, y + r, 2, COMB_COLOR)?;
+            for i in -1..=1 {
+                let tooth_y = y + i * r / 2;
+                canvas.aa_line(x - r, tooth_y, x + r, tooth_y, COMB_COLOR)?;
+            }
+        }
+        // Dipole, draw a small positive and negative particle joined by a bar
+        Tool::Dipole => {
+            canvas.thick_line(x - r, y + r / 2, x + r, y - r / 2, 2, theme.neutral)?;
+            draw_particle(
+                canvas,
+                theme,
+                Particle::Positive,
+                x + r,
+                y - r / 2,
+                radius / 2,
+            )?;
+            draw_particle(
+                canvas,
+                theme,
+                Particle::Negative,
+                x - r,
+                y + r / 2,
+                radius / 2,
+            )?;
+        }
+        // Move, draw a cross with an arrowhead on each end
+        Tool::Move => {
+            let head = r / 2;
+
+            canvas.thick_line(x - r, y, x + r, y, 2, theme.text)?;
+            canvas.thick_line(x, y - r, x, y + r, 2, theme.text)?;
+
+            for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let tip = (x + dx * (r + head), y + dy * (r + head));
+
+                canvas.filled_trigon(
+                    tip.0,
+                    tip.1,
+                    x + dx * r - dy * head,
+                    y + dy * r - dx * head,
+                    x + dx * r + dy * head,
+                    y + dy * r + dx * head,
+                    theme.text,
+                )?;
+            }
         }
     }
-}
 
-impl Toolbar {
-    fn get_option_rect(&self, canvas: &WindowCanvas, index: usize) -> Rect {
-        let top_left = (canvas.output_size().unwrap().0 * 9 / 10, 0);
-        let bottom_right = canvas.output_size().unwrap();
+    Ok(())
+}
 
-        let y_inc = (bottom_right.1 - top_left.1) / self.choices.len() as u32;
-        let opt_top_left = (top_left.0, top_left.1 + (index as u32) * y_inc);
+// Categorical colors for telling the lines of up to ten positive particles apart, Paul Tol's
+// muted scheme, which stays distinguishable with the common kinds of color blindness
+const LINE_PALETTE: [Color; 10] = [
+    Color::RGB(204, 102, 119),
+    Color::RGB(51, 34, 136),
+    Color::RGB(221, 204, 119),
+    Color::RGB(17, 119, 51),
+    Color::RGB(136, 204, 238),
+    Color::RGB(136, 34, 85),
+    Color::RGB(68, 170, 153),
+    Color::RGB(153, 153, 51),
+    Color::RGB(170, 68, 153),
+    Color::RGB(221, 221, 221),
+];
 
-        Rect::new(
-            opt_top_left.0 as i32,
-            opt_top_left.1 as i32,
-            bottom_right.0 - top_left.0,
-            y_inc,
-        )
+// Color `line` is drawn in: the comb color for the lines of combs, the hue of the positive
+// particle it came from when coloring by source, and the theme's line color otherwise. Negative
+// particles only take lines in, so theirs stay neutral.
+fn get_line_color(scene: &Scene, theme: &Theme, by_source: bool, line: &FieldLine) -> Color {
+    if line.comb.is_some() {
+        return COMB_COLOR;
     }
 
-    fn get_selected_option(&self) -> Option<Particle> {
-        self.selected_part
+    line.seed
+        .filter(|_| by_source)
+        .and_then(|seed| scene.particles.get(seed))
+        .filter(|particle| particle.charge > 0.0)
+        .and_then(|particle| particle.hue)
+        .map_or(theme.line, |hue| LINE_PALETTE[hue % LINE_PALETTE.len()])
+}
+
+// Color as an SVG attribute value, leaving out the alpha
+fn svg_color(color: Color) -> String {
+    format!("rgb({},{},{})", color.r, color.g, color.b)
+}
+
+// Tail and tip of an arrow, in screen coordinates
+type Arrow = ((f64, f64), (f64, f64));
+
+// Draws a straight arrow in the current draw color, with a head 0.3 times its length
+fn draw_arrow<T: SceneTarget>(
+    canvas: &mut Canvas<T>,
+    tail: (f64, f64),
+    tip: (f64, f64),
+) -> Result<(), String> {
+    let angle = (tip.1 - tail.1).atan2(tip.0 - tail.0);
+    let head_length = 0.3 * (tip.0 - tail.0).hypot(tip.1 - tail.1);
+    let tip_point = Point::new(tip.0 as i32, tip.1 as i32);
+
+    canvas.draw_line(Point::new(tail.0 as i32, tail.1 as i32), tip_point)?;
+
+    for side in [-1.0, 1.0] {
+        let head_angle = angle + side * 5.0 * std::f64::consts::PI / 6.0;
+
+        canvas.draw_line(
+            tip_point,
+            Point::new(
+                (tip.0 + head_length * head_angle.cos()) as i32,
+                (tip.1 + head_length * head_angle.sin()) as i32,
+            ),
+        )?;
     }
 
-    fn handle_mouse_down(&mut self, canvas: &mut WindowCanvas, x: i32, y: i32) {
-        for i in 0..self.choices.len() {
-            let opt_rect = self.get_option_rect(canvas, i);
+    Ok(())
+}
 
-            if x >= opt_rect.x
-                && x <= opt_rect.x + opt_rect.w
-                && y >= opt_rect.y
-                && y <= opt_rect.y + opt_rect.h
-            {
-                self.selected_part = Some(self.choices[i]);
-                break;
+// gfx takes i16 coordinates, which positions far off screen don't fit in
+fn to_gfx_point(pos: (f64, f64)) -> Option<(i16, i16)> {
+    Some((
+        i16::try_from(pos.0.round() as i64).ok()?,
+        i16::try_from(pos.1.round() as i64).ok()?,
+    ))
+}
+
+// Part of the segment from `start` to `end` inside the `size` screen area grown by
+// GFX_CLIP_MARGIN on every side, in gfx coordinates, or None if it misses the area. Long or far
+// away segments would otherwise have their ends clamped to the i16 range and be drawn at the
+// wrong angle.
+fn clip_segment(
+    start: (f64, f64),
+    end: (f64, f64),
+    size: (u32, u32),
+) -> Option<((i16, i16), (i16, i16))> {
+    let (min_x, min_y) = (-GFX_CLIP_MARGIN, -GFX_CLIP_MARGIN);
+    let (max_x, max_y) = (
+        size.0 as f64 + GFX_CLIP_MARGIN,
+        size.1 as f64 + GFX_CLIP_MARGIN,
+    );
+    let delta = (end.0 - start.0, end.1 - start.1);
+
+    // Liang-Barsky, narrowing the range of the segment's parameter inside each edge in turn
+    let (mut from, mut to) = (0.0_f64, 1.0_f64);
+    for (p, q) in [
+        (-delta.0, start.0 - min_x),
+        (delta.0, max_x - start.0),
+        (-delta.1, start.1 - min_y),
+        (delta.1, max_y - start.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
             }
+        } else if p < 0.0 {
+            from = from.max(q / p);
+        } else {
+            to = to.min(q / p);
         }
+    }
 
-        self.on_update(canvas);
+    if from > to {
+        return None;
     }
 
-    fn on_update(&self, canvas: &mut WindowCanvas) {
-        let output_size = canvas.output_size().unwrap();
+    let along = |t: f64| to_gfx_point((start.0 + t * delta.0, start.1 + t * delta.1));
+    Some((along(from)?, along(to)?))
+}
 
-        canvas.set_draw_color(Color::GRAY);
-        canvas
-            .fill_rect(Rect::new(
-                (output_size.0 * 9 / 10) as i32,
-                0,
-                output_size.0 / 10,
-                output_size.1,
-            ))
-            .unwrap();
+// Draws a line from `start` to `end` in screen pixels, in dashes DASH_LENGTH long, with the
+// current draw color
+fn draw_dashed_line<T: SceneTarget>(
+    canvas: &mut Canvas<T>,
+    start: (f64, f64),
+    end: (f64, f64),
+) -> Result<(), String> {
+    let length = (end.0 - start.0).hypot(end.1 - start.1);
+    let along = |t: f64| {
+        Point::new(
+            (start.0 + t * (end.0 - start.0)) as i32,
+            (start.1 + t * (end.1 - start.1)) as i32,
+        )
+    };
 
-        for (i, c) in self.choices.iter().enumerate() {
-            let opt_rect = self.get_option_rect(canvas, i);
+    let dashes = (length / (2.0 * DASH_LENGTH)).ceil() as usize;
+    for i in 0..dashes {
+        let from = 2.0 * i as f64 * DASH_LENGTH / length;
+        let to = ((2 * i + 1) as f64 * DASH_LENGTH / length).min(1.0);
 
-            println!("{opt_rect:?}");
+        canvas.draw_line(along(from), along(to))?;
+    }
 
-            if Some(*c) == self.selected_part {
-                canvas.set_draw_color(Color::WHITE);
-                canvas.draw_rect(opt_rect).unwrap();
-            }
+    Ok(())
+}
 
-            draw_particle(
-                canvas,
-                *c,
-                (opt_rect.x + opt_rect.w / 2) as i16,
-                (opt_rect.y + opt_rect.h / 2) as i16,
-            );
-        }
+// Switches the window between windowed and desktop fullscreen. Everything is laid out again
+// when SDL reports the new size. Not every platform puts the window back where it was, so
+// the windowed size and position are kept in `windowed` and restored by hand.
+fn toggle_fullscreen(window: &mut Window, windowed: &mut Option<Rect>) -> Result<(), String> {
+    if window.fullscreen_state() == FullscreenType::Off {
+        let (x, y) = window.position();
+        let (width, height) = window.size();
+        *windowed = Some(Rect::new(x, y, width, height));
+
+        return window.set_fullscreen(FullscreenType::Desktop);
+    }
+
+    window.set_fullscreen(FullscreenType::Off)?;
+
+    if let Some(rect) = windowed.take() {
+        window
+            .set_size(rect.width(), rect.height())
+            .map_err(|e| e.to_string())?;
+        window.set_position(WindowPos::Positioned(rect.x), WindowPos::Positioned(rect.y));
     }
+
+    Ok(())
 }
 
-#[derive(Default)]
-struct Game {
-    particles: Vec<(f64, f64, f64)>,
-    current_selected_charge: f64,
+// Text is optional, so a missing SDL_ttf just means no labels
+fn init_ttf() -> Option<Sdl2TtfContext> {
+    sdl2::ttf::init()
+        .map_err(|e| eprintln!("Couldn't initialize SDL_ttf: {e}"))
+        .ok()
 }
 
-impl Game {
-    fn get_field_strength(&self, x: f64, y: f64) -> (f64, f64) {
-        let mut total_strength = (0.0, 0.0);
+fn load_font(ttf_context: &Sdl2TtfContext, size: u16) -> Option<Font<'_, 'static>> {
+    let font = FONT_PATHS
+        .iter()
+        .find_map(|path| ttf_context.load_font(path, size).ok());
 
-        for (part_x, part_y, charge) in self.particles.iter() {
-            let direct_vec = (x - part_x, y - part_y);
-            let direct_mag = direct_vec.0.hypot(direct_vec.1);
+    if font.is_none() {
+        eprintln!("Couldn't load a font, text labels will be skipped");
+    }
 
-            let force_mag = COULUMBS_CONST * charge / (direct_mag * direct_mag);
+    font
+}
 
-            total_strength.0 += force_mag * direct_vec.0 / direct_mag;
-            total_strength.1 += force_mag * direct_vec.1 / direct_mag;
-        }
+// Draws `text` with its top left corner at (`x`, `y`)
+fn draw_text<T: SceneTarget>(
+    canvas: &mut Canvas<T>,
+    font: &Font,
+    text: &str,
+    x: i32,
+    y: i32,
+    color: Color,
+) -> Result<(), String> {
+    let surface = font
+        .render(text)
+        .blended(color)
+        .map_err(|e| e.to_string())?;
+    let texture_creator = T::texture_creator(canvas);
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
 
-        total_strength
-    }
+    canvas.copy(
+        &texture,
+        None,
+        Rect::new(x, y, surface.width(), surface.height()),
+    )?;
 
-    fn handle_mouse_down(&mut self, canvas: &mut WindowCanvas, x: i32, y: i32) {
-        self.particles
-            .push((x as f64, y as f64, self.current_selected_charge));
-        println!(
-            "Added particle with charge {} at ({x}, {y})",
-            self.current_selected_charge
-        );
+    Ok(())
+}
 
-        self.on_update(canvas);
+// Positions and unit tangents of arrowheads every `spacing` along the polyline through `points`,
+// or a single one halfway along if it's shorter than that. The tangents point along the
+// polyline, which isn't necessarily the direction of the field.
+fn get_arrowheads(points: &[(f64, f64)], spacing: f64) -> Vec<((f64, f64), (f64, f64))> {
+    let lengths: Vec<f64> = points
+        .windows(2)
+        .map(|pair| (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1))
+        .collect();
+    let total: f64 = lengths.iter().sum();
+
+    if total == 0.0 {
+        return vec![];
     }
 
-    fn handle_keydown(&mut self, _canvas: &mut WindowCanvas, keycode: Keycode) {
-        match keycode {
-            Keycode::Equals => {
-                self.current_selected_charge += CHARGE_STEP;
-            }
-            Keycode::Minus => {
-                self.current_selected_charge -= CHARGE_STEP;
-            }
-            Keycode::N => {
-                self.current_selected_charge = 0.0;
+    let mut targets = if total < spacing {
+        vec![total / 2.0]
+    } else {
+        (1..=(total / spacing) as usize)
+            .map(|i| i as f64 * spacing)
+            .collect()
+    }
+    .into_iter()
+    .peekable();
+
+    let mut arrowheads = vec![];
+    let mut walked = 0.0;
+
+    for (pair, length) in points.windows(2).zip(lengths) {
+        while let Some(target) = targets.next_if(|target| *target <= walked + length) {
+            if length > 0.0 {
+                let t = (target - walked) / length;
+                let tangent = (
+                    (pair[1].0 - pair[0].0) / length,
+                    (pair[1].1 - pair[0].1) / length,
+                );
+
+                arrowheads.push((
+                    (
+                        pair[0].0 + t * (pair[1].0 - pair[0].0),
+                        pair[0].1 + t * (pair[1].1 - pair[0].1),
+                    ),
+                    tangent,
+                ));
             }
-            _ => {}
         }
 
-        if self.current_selected_charge.abs() < 1e-30 {
-            self.current_selected_charge = 0.0;
-        }
+        walked += length;
+    }
+
+    arrowheads
+}
+
+// Formats a charge in units of e, with one decimal place for fractional charges
+fn format_charge(charge: f64) -> String {
+    let elementary = (charge / CHARGE_STEP * 10.0).round() / 10.0;
+    let sign = if elementary > 0.0 { "+" } else { "\u{2212}" };
 
-        println!("Current charge: {}", self.current_selected_charge);
+    if elementary == 0.0 {
+        "0".to_string()
+    } else if elementary.fract() == 0.0 {
+        format!("{sign}{}e", elementary.abs())
+    } else {
+        format!("{sign}{:.1}e", elementary.abs())
     }
+}
 
-    fn handle_keyup(&mut self, _canvas: &mut WindowCanvas, _keycode: Keycode) {}
+// Formats `value` in `unit` with the SI prefix that puts it between 1 and 1000, to three
+// significant figures, so lengths and times read the same from atoms to rooms
+fn format_si(value: f64, unit: &str) -> String {
+    if value == 0.0 || !value.is_finite() {
+        return format!("{value} {unit}");
+    }
 
-    fn on_update(&self, canvas: &mut WindowCanvas) {
-        canvas.set_draw_color(Color::BLACK);
-        canvas.clear();
+    let (scale, prefix) = SI_PREFIXES
+        .iter()
+        .rev()
+        .find(|(scale, _)| value.abs() >= *scale)
+        .unwrap_or(&SI_PREFIXES[0]);
+    let scaled = value / scale;
+    let decimals = match scaled.abs() {
+        magnitude if magnitude >= 100.0 => 0,
+        magnitude if magnitude >= 10.0 => 1,
+        _ => 2,
+    };
 
-        for (x, y, charge) in self.particles.iter() {
-            let (rnded_x, rnded_y, charge) = (*x as i16, *y as i16, *charge);
+    format!("{scaled:.decimals$} {prefix}{unit}")
+}
 
-            let particle_type = if charge > 0.0 {
-                Particle::Positive
-            } else if charge < 0.0 {
-                Particle::Negative
-            } else {
-                Particle::Neutral
-            };
+// Bottom edge of the play area and toolbar, which leave room for the status bar below them
+fn get_toolbar_bottom(output_size: (u32, u32)) -> u32 {
+    output_size.1.saturating_sub(STATUS_BAR_HEIGHT)
+}
 
-            draw_particle(canvas, particle_type, rnded_x, rnded_y);
-        }
+// Reads the current canvas contents to be written to a timestamped PNG, optionally leaving out
+// the toolbar and status bar
+fn take_screenshot(canvas: &WindowCanvas, crop_toolbar: bool) -> Result<ExportJob, String> {
+    let (mut width, mut height) = canvas.output_size()?;
+    if crop_toolbar {
+        height = get_toolbar_bottom((width, height));
+        width = width * 9 / 10;
+    }
 
-        // Now we create 8 protruding lines from each positive particle
-        for (x, y, charge) in self.particles.iter().filter(|(_, _, charge)| *charge > 0.0) {
-            let (x, y, _charge) = (*x, *y, *charge);
+    let pixels = canvas.read_pixels(Rect::new(0, 0, width, height), PixelFormatEnum::RGB24)?;
 
-            for i in 0..16 {
-                let mut line_points = vec![];
+    let path = PathBuf::from(
+        chrono::Local::now()
+            .format("field-%Y-%m-%dT%H-%M-%S.png")
+            .to_string(),
+    );
 
-                let starting_angle = i as f64 * std::f64::consts::PI / 8.0;
-                let mut current_pos = (
-                    x + (PARTICLE_RADIUS as f64 * 1.1) * starting_angle.cos(),
-                    y + (PARTICLE_RADIUS as f64 * 1.1) * starting_angle.sin(),
-                );
+    Ok(ExportJob::Png {
+        path,
+        width,
+        height,
+        pixels,
+    })
+}
 
-                line_points.push(Point::new(current_pos.0 as i32, current_pos.1 as i32));
-
-                for _ in 0..MAX_LINE_ITERS {
-                    let field_strength = self.get_field_strength(current_pos.0, current_pos.1);
-                    let field_strength_mag = field_strength.0.hypot(field_strength.1);
-
-                    current_pos.0 += field_strength.0 / field_strength_mag;
-                    current_pos.1 += field_strength.1 / field_strength_mag;
-
-                    if self
-                        .particles
-                        .iter()
-                        .filter(|(_, _, charge)| *charge < 0.0)
-                        .find(|(x, y, _)| {
-                            (current_pos.0 - *x).hypot(current_pos.1 - *y)
-                                <= PARTICLE_RADIUS as f64 * 1.1
-                        })
-                        .is_some()
-                    {
-                        break;
-                    }
+// Writes RGB24 rows of `width` pixels to a PNG at `path`
+fn write_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels)?;
 
-                    line_points.push(Point::new(current_pos.0 as i32, current_pos.1 as i32));
-                }
+    Ok(())
+}
+
+// Animated GIF being written one frame at a time while recording, so memory use doesn't grow
+// with the length of the recording
+struct Recorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    path: PathBuf,
+    // Every `downscale`th pixel of every `downscale`th row is kept
+    downscale: u32,
+    // One in every `stride` frames drawn is captured, counted by `skipped`
+    stride: u32,
+    skipped: u32,
+    // Size of the GIF, fixed by the window size when the recording started
+    size: (usize, usize),
+    frames: usize,
+    // Last captured frame, only written once the next one shows how long it was on screen
+    pending: Option<gif::Frame<'static>>,
+    // Seconds since the pending frame was captured, and between the last two captures
+    since_capture: f64,
+    interval: f64,
+}
+
+impl Recorder {
+    fn start(canvas: &WindowCanvas, stride: u32) -> Result<Self, Box<dyn Error>> {
+        let (width, height) = canvas.output_size()?;
+        let downscale = width.div_ceil(GIF_MAX_WIDTH).max(1);
+
+        let path = PathBuf::from(
+            chrono::Local::now()
+                .format("field-%Y-%m-%dT%H-%M-%S.gif")
+                .to_string(),
+        );
+
+        let size = ((width / downscale) as usize, (height / downscale) as usize);
+
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(File::create(&path)?),
+            size.0 as u16,
+            size.1 as u16,
+            &[],
+        )?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        Ok(Self {
+            encoder,
+            path,
+            downscale,
+            stride,
+            skipped: 0,
+            size,
+            frames: 0,
+            pending: None,
+            since_capture: 0.0,
+            interval: 0.0,
+        })
+    }
+
+    // Called once per frame drawn, appends the current canvas contents downscaled to the size
+    // the recording started at on every `stride`th call. `dt` is the time since the last call,
+    // which the frames' delays are taken from.
+    fn capture(&mut self, canvas: &WindowCanvas, dt: f64) -> Result<(), Box<dyn Error>> {
+        self.since_capture += dt;
+        if self.pending.is_some() {
+            self.skipped += 1;
+            if self.skipped < self.stride {
+                return Ok(());
+            }
+        }
+        self.skipped = 0;
+
+        let (width, height) = canvas.output_size()?;
+        let pixels = canvas.read_pixels(Rect::new(0, 0, width, height), PixelFormatEnum::RGB24)?;
 
-                canvas.set_draw_color(Color::WHITE);
-                canvas.draw_lines(&line_points[..]).unwrap();
+        let (gif_width, gif_height) = self.size;
+        let step = self.downscale as usize;
+
+        // The window can be resized while recording, whatever falls outside the first frame's
+        // size is cut off and anything missing is left black
+        let mut scaled = vec![0; gif_width * gif_height * 3];
+        for y in 0..gif_height.min(height as usize / step) {
+            for x in 0..gif_width.min(width as usize / step) {
+                let src = (y * step * width as usize + x * step) * 3;
+                let dst = (y * gif_width + x) * 3;
+                scaled[dst..dst + 3].copy_from_slice(&pixels[src..src + 3]);
             }
         }
+
+        let frame = gif::Frame::from_rgb_speed(
+            gif_width as u16,
+            gif_height as u16,
+            &scaled,
+            GIF_QUANTIZE_SPEED,
+        );
+
+        if let Some(previous) = self.pending.replace(frame) {
+            self.interval = self.since_capture;
+            self.write_pending(previous)?;
+        } else {
+            self.since_capture = 0.0;
+        }
+
+        Ok(())
     }
-}
 
-pub fn run() -> Result<(), Box<dyn std::error::Error>> {
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
+    // Writes a frame that was shown for `since_capture` seconds
+    fn write_pending(&mut self, mut frame: gif::Frame<'static>) -> Result<(), Box<dyn Error>> {
+        // GIF delays are in hundredths of a second, whatever is rounded off is carried over
+        let delay = (self.since_capture * 100.0).round();
+        frame.delay = delay as u16;
+        self.since_capture -= delay / 100.0;
 
-    let window = video_subsystem
-        .window("Electric Field Visualizer", 800, 600)
-        .position_centered()
-        .build()
-        .unwrap();
+        self.encoder.write_frame(&frame)?;
+        self.frames += 1;
 
-    let mut event_pump = sdl_context.event_pump()?;
-    let mut game = Game::default();
-    let mut toolbar = Toolbar::default();
-    let mut canvas = window.into_canvas().build()?;
+        Ok(())
+    }
 
-    let mut _frame_num = 0;
+    // Writes the end of the GIF, returning its path and number of frames
+    fn finish(mut self) -> Result<(PathBuf, usize), Box<dyn Error>> {
+        // The last frame is shown as long as the one before it
+        if let Some(last) = self.pending.take() {
+            self.since_capture = self.since_capture.max(self.interval);
+            self.write_pending(last)?;
+        }
 
-    game.on_update(&mut canvas);
-    toolbar.on_update(&mut canvas);
+        self.encoder.into_inner()?.into_inner()?;
 
-    'main_loop: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => {
-                    break 'main_loop;
-                }
-                Event::MouseMotion { x, y, .. } => {
-                    let (x_comp, y_comp) = game.get_field_strength(x as f64, y as f64);
-                    println!(
-                        "Total field strength @ ({x}, {y}): {}",
-                        x_comp.hypot(y_comp)
-                    );
-                }
-                Event::MouseButtonDown { x, y, .. } => {
-                    if (x as u32) < canvas.output_size().unwrap().0 * 9 / 10 {
-                        game.handle_mouse_down(&mut canvas, x, y);
-                    } else {
-                        toolbar.handle_mouse_down(&mut canvas, x, y);
-                    }
+        Ok((self.path, self.frames))
+    }
+}
 
-                    toolbar.on_update(&mut canvas);
-                }
-                Event::KeyDown {
-                    keycode: Some(keycode),
-                    ..
-                } => {
-                    if let Keycode::Q = keycode {
-                        break 'main_loop;
-                    }
+// Radius `particle` is drawn with on screen, in pixels
+fn get_screen_radius(scene: &Scene, view: View, particle: &ChargedParticle) -> f64 {
+    (scene.particle_radius(particle) * view.scale).max(1.0)
+}
 
-                    game.handle_keydown(&mut canvas, keycode);
-                }
-                Event::KeyUp {
-                    keycode: Some(keycode),
-                    ..
-                } => {
-                    game.handle_keyup(&mut canvas, keycode);
-                }
-                _ => {}
+// Equipotentials over a play area of `play_area` screen pixels shown through `view`, sampled
+// every `cell_size` screen pixels and returned in world coordinates
+fn get_visible_equipotentials(
+    scene: &Scene,
+    view: View,
+    play_area: (f64, f64),
+    cell_size: f64,
+) -> Vec<ContourSegment> {
+    scene.get_equipotential_segments(
+        view.offset,
+        cell_size / view.scale,
+        (play_area.0 / cell_size) as usize + 1,
+        (play_area.1 / cell_size) as usize + 1,
+    )
+}
+
+// What an SVG of the play area is drawn from, copied from the game so it can be written on the
+// export worker
+struct SvgSnapshot {
+    scene: Scene,
+    view: View,
+    theme: Theme,
+    play_area: (f64, f64),
+    field_lines: Vec<FieldLine>,
+    color_lines_by_source: bool,
+    show_equipotentials: bool,
+}
+
+impl SvgSnapshot {
+    // Writes the play area as it's drawn on screen as an SVG, in screen coordinates. Field
+    // lines are the ones traced for the last redraw, and equipotentials are only included if
+    // they're shown.
+    fn write_svg(&self, mut writer: impl Write) -> io::Result<()> {
+        let (width, height) = self.play_area;
+
+        writeln!(
+            writer,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\">"
+        )?;
+        writeln!(
+            writer,
+            "<rect width=\"{width}\" height=\"{height}\" fill=\"{}\"/>",
+            svg_color(self.theme.background)
+        )?;
+
+        if self.show_equipotentials {
+            write!(
+                writer,
+                "<path fill=\"none\" stroke=\"{}\" d=\"",
+                svg_color(EQUIPOTENTIAL_COLOR)
+            )?;
+            for (_, start, end) in get_visible_equipotentials(
+                &self.scene,
+                self.view,
+                self.play_area,
+                EQUIPOTENTIAL_CELL_SIZE,
+            ) {
+                let (start, end) = (self.view.to_screen(start), self.view.to_screen(end));
+                write!(
+                    writer,
+                    "M{:.2} {:.2}L{:.2} {:.2}",
+                    start.0, start.1, end.0, end.1
+                )?;
             }
+            writeln!(writer, "\"/>")?;
         }
 
-        canvas.set_draw_color(Color::GRAY);
+        let line_width = (LINE_CHARGE_WIDTH * self.view.scale).max(1.0);
 
-        canvas.present();
+        for line in self.scene.lines.iter() {
+            let (start, end) = (
+                self.view.to_screen(line.start),
+                self.view.to_screen(line.end),
+            );
 
-        thread::sleep(Duration::from_millis(16));
+            writeln!(
+                writer,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"{}\" \
+                 stroke-width=\"{line_width:.2}\"/>",
+                start.0,
+                start.1,
+                end.0,
+                end.1,
+                svg_color(
+                    self.theme
+                        .particle_color(Particle::from_charge(line.charge))
+                )
+            )?;
+        }
 
-        _frame_num += 1;
-    }
+        for comb in self.scene.combs.iter() {
+            let (start, end) = (
+                self.view.to_screen(comb.start),
+                self.view.to_screen(comb.end),
+            );
 
-    Ok(())
+            writeln!(
+                writer,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"{}\" \
+                 stroke-dasharray=\"4 4\"/>",
+                start.0,
+                start.1,
+                end.0,
+                end.1,
+                svg_color(COMB_COLOR)
+            )?;
+        }
+
+        for dipole in self.scene.dipoles.iter() {
+            let [start, end] = dipole
+                .point_charges()
+                .map(|charge| self.view.to_screen(charge.pos()));
+
+            writeln!(
+                writer,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"{}\" \
+                 stroke-width=\"2\"/>",
+                start.0,
+                start.1,
+                end.0,
+                end.1,
+                svg_color(self.theme.neutral)
+            )?;
+        }
+
+        // Dipoles' charges are drawn the same as particles
+        for particle in self.scene.point_charges() {
+            let (x, y) = self.view.to_screen(particle.pos());
+            let radius = get_screen_radius(&self.scene, self.view, &particle);
+            let kind = Particle::from_charge(particle.charge);
+            let half = radius / 2.0;
+
+            writeln!(
+                writer,
+                "<circle cx=\"{x:.2}\" cy=\"{y:.2}\" r=\"{radius:.2}\" fill=\"{}\"/>",
+                svg_color(self.theme.particle_color(kind))
+            )?;
+
+            // The same plus, minus and n glyphs as on screen, as strokes
+            let glyph = match kind {
+                Particle::Positive => format!(
+                    "M{:.2} {y:.2}H{:.2}M{x:.2} {:.2}V{:.2}",
+                    x - half,
+                    x + half,
+                    y - half,
+                    y + half
+                ),
+                Particle::Negative => format!("M{:.2} {y:.2}H{:.2}", x - half, x + half),
+                Particle::Neutral => format!(
+                    "M{:.2} {:.2}V{:.2}A{:.2} {:.2} 0 0 1 {:.2} {:.2}V{:.2}",
+                    x - 0.8 * half,
+                    y + half,
+                    y - 0.2 * half,
+                    0.8 * half,
+                    0.8 * half,
+                    x + 0.8 * half,
+                    y - 0.2 * half,
+                    y + half
+                ),
+            };
+
+            let thickness = if self.theme.colorblind {
+                radius / 3.0
+            } else {
+                radius / 5.0
+            };
+            writeln!(
+                writer,
+                "<path fill=\"none\" stroke=\"{}\" stroke-width=\"{thickness:.2}\" \
+                 d=\"{glyph}\"/>",
+                svg_color(self.theme.glyph)
+            )?;
+
+            if self.theme.colorblind {
+                for ring in 0..get_ring_count(kind) {
+                    writeln!(
+                        writer,
+                        "<circle cx=\"{x:.2}\" cy=\"{y:.2}\" r=\"{:.2}\" fill=\"none\" \
+                         stroke=\"{}\"/>",
+                        radius - (PARTICLE_RING_INSET * (ring + 1)) as f64,
+                        svg_color(self.theme.glyph)
+                    )?;
+                }
+            }
+        }
+
+        // Lines seeded from a particle say which one, by its index in the scene file
+        for line in self.field_lines.iter() {
+            let color = get_line_color(&self.scene, &self.theme, self.color_lines_by_source, line);
+            let source = line
+                .seed
+                .map_or(String::new(), |seed| format!(" data-particle=\"{seed}\""));
+
+            write!(
+                writer,
+                "<polyline fill=\"none\" stroke=\"{}\"{source} points=\"",
+                svg_color(color)
+            )?;
+            for (i, pos) in line.points.iter().enumerate() {
+                let (x, y) = self.view.to_screen(*pos);
+                let separator = if i == 0 { "" } else { " " };
+                write!(writer, "{separator}{x:.2},{y:.2}")?;
+            }
+            writeln!(writer, "\"/>")?;
+        }
+
+        if let Some(plane) = self.scene.ground_plane {
+            let (x, y, plane_width, plane_height) = match plane {
+                GroundPlane::Horizontal(y) => (
+                    0.0,
+                    self.view.to_screen((0.0, y)).1 - GROUND_PLANE_WIDTH as f64 / 2.0,
+                    width,
+                    GROUND_PLANE_WIDTH as f64,
+                ),
+                GroundPlane::Vertical(x) => (
+                    self.view.to_screen((x, 0.0)).0 - GROUND_PLANE_WIDTH as f64 / 2.0,
+                    0.0,
+                    GROUND_PLANE_WIDTH as f64,
+                    height,
+                ),
+            };
+
+            writeln!(
+                writer,
+                "<rect x=\"{x:.2}\" y=\"{y:.2}\" width=\"{plane_width:.2}\" \
+                 height=\"{plane_height:.2}\" fill=\"{}\"/>",
+                svg_color(self.theme.neutral)
+            )?;
+        }
+
+        writeln!(writer, "</svg>")
+    }
+}
+
+// A file to write on the export worker. Everything it's written from is copied in as plain
+// data, so the worker never touches the game or SDL.
+enum ExportJob {
+    Png {
+        path: PathBuf,
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
+    Svg {
+        path: PathBuf,
+        snapshot: SvgSnapshot,
+    },
+    Csv {
+        path: PathBuf,
+        scene: Scene,
+        bounds: Bounds,
+    },
+    // Writing the end of a recording
+    Gif(Recorder),
+}
+
+impl ExportJob {
+    // Writes the file, returning the notice to show for it
+    fn run(self) -> Result<String, String> {
+        match self {
+            ExportJob::Png {
+                path,
+                width,
+                height,
+                pixels,
+            } => write_png(&path, width, height, &pixels)
+                .map(|()| format!("Saved screenshot to {}", path.display()))
+                .map_err(|e| format!("Failed to save screenshot: {e}")),
+            ExportJob::Svg { path, snapshot } => {
+                let written = File::create(&path).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    snapshot.write_svg(&mut writer)?;
+                    writer.flush()
+                });
+
+                written
+                    .map(|()| format!("Exported SVG to {}", path.display()))
+                    .map_err(|e| format!("Failed to export SVG: {e}"))
+            }
+            ExportJob::Csv {
+                path,
+                scene,
+                bounds,
+            } => File::create(&path)
+                .and_then(|file| {
+                    scene.write_field_csv(BufWriter::new(file), bounds, CSV_COLUMNS, CSV_ROWS)
+                })
+                .map(|()| format!("Exported field to {}", path.display()))
+                .map_err(|e| format!("Failed to export field: {e}")),
+            ExportJob::Gif(recorder) => recorder
+                .finish()
+                .map(|(path, frames)| {
+                    format!("Saved {frames} frame recording to {}", path.display())
+                })
+                .map_err(|e| format!("Failed to finish recording: {e}")),
+        }
+    }
+}
+
+// Thread writing exports one at a time in the order they're queued, so encoding a big file
+// doesn't hold up the frame. It's started with the first export, and each one's notice comes
+// back through `finished`.
+#[derive(Default)]
+struct Exporter {
+    jobs: Option<mpsc::Sender<ExportJob>>,
+    results: Option<mpsc::Receiver<Result<String, String>>>,
+    worker: Option<thread::JoinHandle<()>>,
+    // Exports queued that haven't reported back yet
+    pending: usize,
+}
+
+impl Exporter {
+    fn queue(&mut self, job: ExportJob) -> Result<(), String> {
+        if self.jobs.is_none() {
+            let (jobs, queue) = mpsc::channel::<ExportJob>();
+            let (report, results) = mpsc::channel();
+            let worker = thread::Builder::new()
+                .name("export".to_string())
+                .spawn(move || {
+                    for job in queue {
+                        if report.send(job.run()).is_err() {
+                            break;
+                        }
+                    }
+                })
+                .map_err(|e| format!("Failed to start the export thread: {e}"))?;
+
+            self.jobs = Some(jobs);
+            self.results = Some(results);
+            self.worker = Some(worker);
+        }
+
+        // Sending only fails if the worker is gone, which it only is after panicking
+        self.jobs
+            .as_ref()
+            .and_then(|jobs| jobs.send(job).ok())
+            .ok_or("The export thread stopped, exports can't be written")?;
+        self.pending += 1;
+
+        Ok(())
+    }
+
+    // Results of the exports that finished since the last call
+    fn finished(&mut self) -> Vec<Result<String, String>> {
+        let results: Vec<_> = self
+            .results
+            .as_ref()
+            .map(|results| results.try_iter().collect())
+            .unwrap_or_default();
+        self.pending -= results.len();
+
+        results
+    }
+
+    // Waits for every queued export to be written, returning the results that weren't picked up
+    // with `finished` yet
+    fn join(&mut self) -> Vec<Result<String, String>> {
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                eprintln!("The export thread panicked");
+            }
+        }
+
+        self.finished()
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Particle {
+    Positive,
+    Negative,
+    Neutral,
+}
+
+impl Particle {
+    fn from_charge(charge: f64) -> Self {
+        if charge > 0.0 {
+            Particle::Positive
+        } else if charge < 0.0 {
+            Particle::Negative
+        } else {
+            Particle::Neutral
+        }
+    }
+}
+
+// What a left click in the play area does
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Tool {
+    Place(Particle),
+    // Press and release set the two ends of a line charge
+    Line,
+    Erase,
+    Move,
+    // Dragging out a rectangle shows the flux through it next to the charge it encloses
+    Gauss,
+    // Dragging places a grounded plane along the drag, clicking without dragging removes it
+    Ground,
+    // Two clicks measure the distance, potential difference and force between the points
+    Measure,
+    // A click places a dipole of the placement charge's magnitude
+    Dipole,
+    // Press and release set the two ends of a comb seeding field lines along it
+    Comb,
+}
+
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Tool::Place(Particle::Positive) => "Place positive",
+            Tool::Place(Particle::Negative) => "Place negative",
+            Tool::Place(Particle::Neutral) => "Place neutral",
+            Tool::Line => "Line charge",
+            Tool::Erase => "Erase",
+            Tool::Move => "Move",
+            Tool::Gauss => "Gaussian surface",
+            Tool::Ground => "Ground plane",
+            Tool::Measure => "Measure",
+            Tool::Dipole => "Dipole",
+            Tool::Comb => "Field line comb",
+        }
+    }
+
+    // Double clicks only flip charges with the tools whose single clicks are safe to undo or
+    // harmless, so measuring or erasing the same particle twice still works
+    fn flips_on_double_click(self) -> bool {
+        matches!(self, Tool::Place(_) | Tool::Move)
+    }
+}
+
+// Something in the scene under the cursor. The positive charge of a dipole is the handle that
+// rotates and stretches it, the rest of it moves it. Combs are stretched by either end, with
+// `true` for the second one, and moved by the rest.
+#[derive(PartialEq, Copy, Clone)]
+enum Hit {
+    Particle(usize),
+    Line(usize),
+    Dipole(usize),
+    DipoleHandle(usize),
+    Comb(usize),
+    CombEnd(usize, bool),
+}
+
+// One end of a measurement, which follows a particle around if it was put on one
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum MeasurePoint {
+    Particle(usize),
+    Fixed((f64, f64)),
+}
+
+// Charges of a scene, as they were before an edit for undoing it or as read from a file for
+// merging them in
+#[derive(Clone, Default)]
+struct Snapshot {
+    particles: Vec<ChargedParticle>,
+    lines: Vec<LineCharge>,
+    dipoles: Vec<Dipole>,
+    combs: Vec<Comb>,
+    // Whether the hydrogen preset's atomic scale was in use, which undoing past puts back the
+    // configured one
+    atomic_scale: bool,
+}
+
+impl Snapshot {
+    // Reads the charges saved in the scene file at `path`. The rest of what's saved with them,
+    // like the placement charge and the period, is left out.
+    fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let loaded: Game = serde_json::from_str(&fs::read_to_string(path)?)?;
+
+        Ok(Self {
+            particles: loaded.scene.particles,
+            lines: loaded.scene.lines,
+            dipoles: loaded.scene.dipoles,
+            combs: loaded.scene.combs,
+            atomic_scale: false,
+        })
+    }
+
+    // Middle of the bounding box of the particles, line charge ends and dipole charges, None if
+    // it's empty
+    fn get_center(&self) -> Option<(f64, f64)> {
+        let points = self
+            .particles
+            .iter()
+            .map(|particle| particle.pos())
+            .chain(self.lines.iter().flat_map(|line| [line.start, line.end]))
+            .chain(
+                self.dipoles
+                    .iter()
+                    .flat_map(|dipole| dipole.point_charges().map(|charge| charge.pos())),
+            );
+
+        let ((min_x, min_y), (max_x, max_y)) = points.fold(
+            (
+                (f64::INFINITY, f64::INFINITY),
+                (f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |((min_x, min_y), (max_x, max_y)), (x, y)| {
+                ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+            },
+        );
+
+        (min_x <= max_x).then(|| ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0))
+    }
+}
+
+// Model generation each thing derived from the model was last computed from, None if it never
+// has been. Nothing here needs to know why the model changed, only that it did.
+#[derive(Default)]
+struct Consumers {
+    // The cached scene texture, which is also redrawn for view changes through `dirty`
+    scene: Option<u64>,
+    field_tree: Option<u64>,
+    moments: Option<u64>,
+    title: Option<u64>,
+}
+
+// Trajectory preview of particle `index`, integrated at `generation`
+#[derive(Clone)]
+struct Prediction {
+    generation: u64,
+    index: usize,
+    path: Vec<(f64, f64)>,
+}
+
+// Whether `seen` is behind `generation`, bringing it up to date if so since the caller is about to
+// recompute what it tracks
+fn take_stale(seen: &mut Option<u64>, generation: u64) -> bool {
+    let stale = *seen != Some(generation);
+    *seen = Some(generation);
+
+    stale
+}
+
+// Range of log10 of the magnitude the heatmap's colors span. Unlocked it follows the samples;
+// locked it stays put, so heatmaps of different edits can be compared. It's saved with the
+// scene.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct HeatmapRange {
+    locked: bool,
+    min: f64,
+    max: f64,
+}
+
+impl Default for HeatmapRange {
+    fn default() -> Self {
+        Self {
+            locked: false,
+            min: 0.0,
+            max: 1.0,
+        }
+    }
+}
+
+// What the heatmap samples depend on besides the color range: the generation, the view, the
+// play area and whether the multipole error is shown
+type HeatmapKey = (u64, View, (f64, f64), Option<usize>, bool);
+
+// log10 of the heatmap magnitude in each block of the play area, a row of `columns` at a time,
+// and the range they cover. Cells inside a particle are None. Changing the color range reuses
+// them instead of sampling the field again.
+struct HeatmapSamples {
+    key: HeatmapKey,
+    columns: u32,
+    rows: u32,
+    magnitudes: Vec<Option<f64>>,
+    range: (f64, f64),
+}
+
+// Blue to red color of a heatmap cell holding `value` when the colors span `range`, clamping
+// values outside it to the end colors. Cells inside particles get the hottest color.
+fn heatmap_color(value: Option<f64>, range: (f64, f64)) -> [u8; 3] {
+    let (min, max) = range;
+    let t = value.map_or(1.0, |v| {
+        ((v - min) / (max - min).max(f64::EPSILON)).clamp(0.0, 1.0)
+    });
+
+    [(255.0 * t) as u8, 0, (255.0 * (1.0 - t)) as u8]
+}
+
+// Which part of the heatmap legend a drag holds
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum LegendGrab {
+    Top,
+    Bottom,
+    Middle,
+}
+
+// Message shown in the status bar for NOTICE_DURATION after it's posted
+struct Notice {
+    text: String,
+    error: bool,
+    posted: Instant,
+}
+
+// Headings of the columns of the batch edit table, whose cells hold a particle's position in
+// world pixels, its charge in e and whether it's pinned
+const BATCH_EDIT_COLUMNS: [&str; 4] = ["x (px)", "y (px)", "q (e)", "pinned (0/1)"];
+const BATCH_EDIT_PINNED: usize = 3;
+
+// Table editing several particles at once, opened with E. Row 0 is the header, whose cells
+// act on their whole column, and row `i + 1` is particle `rows[i]`. The scene doesn't change
+// until the table is committed, and then only in the cells that were edited.
+struct BatchEdit {
+    rows: Vec<usize>,
+    cells: Vec<[String; 4]>,
+    original: Vec<[String; 4]>,
+    header: [String; 4],
+    // Row and column of the cell being edited
+    cursor: (usize, usize),
+    // The next character typed replaces the cell instead of adding to it, like in a spreadsheet
+    replace: bool,
+}
+
+// Rounded so charges don't show up as 0.9999999999 e. Cells that aren't edited aren't parsed
+// back, so nothing is lost.
+fn format_cell(value: f64) -> String {
+    ((value * 1000.0).round() / 1000.0 + 0.0).to_string()
+}
+
+impl BatchEdit {
+    fn new(particles: &[ChargedParticle], rows: Vec<usize>) -> Self {
+        let cells: Vec<[String; 4]> = rows
+            .iter()
+            .map(|&index| {
+                let particle = particles[index];
+
+                [
+                    format_cell(particle.x),
+                    format_cell(particle.y),
+                    format_cell(particle.charge / CHARGE_STEP),
+                    u8::from(particle.pinned).to_string(),
+                ]
+            })
+            .collect();
+
+        Self {
+            rows,
+            original: cells.clone(),
+            cells,
+            header: Default::default(),
+            cursor: (1, 0),
+            replace: true,
+        }
+    }
+
+    // What a cell in `column` holding `text` sets, the number itself except for pinned, which
+    // is 0 or 1
+    fn parse(column: usize, text: &str) -> Option<f64> {
+        let value = text
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())?;
+
+        (column != BATCH_EDIT_PINNED || value == 0.0 || value == 1.0).then_some(value)
+    }
+
+    fn is_valid(&self, row: usize, column: usize) -> bool {
+        Self::parse(column, &self.cells[row][column]).is_some()
+    }
+
+    fn invalid_cells(&self) -> usize {
+        (0..self.cells.len())
+            .flat_map(|row| (0..BATCH_EDIT_COLUMNS.len()).map(move |column| (row, column)))
+            .filter(|&(row, column)| !self.is_valid(row, column))
+            .count()
+    }
+
+    fn cell_mut(&mut self) -> &mut String {
+        let (row, column) = self.cursor;
+
+        match row {
+            0 => &mut self.header[column],
+            row => &mut self.cells[row - 1][column],
+        }
+    }
+
+    // Moves the cursor `delta` cells along the rows, wrapping around past either end
+    fn move_by(&mut self, delta: isize) {
+        let columns = BATCH_EDIT_COLUMNS.len();
+        let count = ((self.rows.len() + 1) * columns) as isize;
+        let current = (self.cursor.0 * columns + self.cursor.1) as isize;
+        let next = (current + delta).rem_euclid(count) as usize;
+
+        self.cursor = (next / columns, next % columns);
+        self.replace = true;
+    }
+
+    fn type_char(&mut self, typed: char) {
+        if std::mem::take(&mut self.replace) {
+            self.cell_mut().clear();
+        }
+        self.cell_mut().push(typed);
+    }
+
+    fn backspace(&mut self) {
+        self.replace = false;
+        self.cell_mut().pop();
+    }
+
+    // Fills `column` from its header cell: a number sets every cell to it, and one after a +
+    // is added to every cell that holds a number. Returns whether the header made sense.
+    fn apply_header(&mut self, column: usize) -> bool {
+        let header = self.header[column].trim().to_string();
+        let (adding, number) = match header.strip_prefix('+') {
+            Some(number) if column != BATCH_EDIT_PINNED => (true, number),
+            Some(_) => return false,
+            None => (false, header.as_str()),
+        };
+        let Some(value) = Self::parse(column, number) else {
+            return false;
+        };
+
+        for row in self.cells.iter_mut() {
+            if !adding {
+                row[column] = format_cell(value);
+            } else if let Some(current) = Self::parse(column, &row[column]) {
+                row[column] = format_cell(current + value);
+            }
+        }
+        self.header[column].clear();
+
+        true
+    }
+
+    // The edited particles as they'd be after committing, or None while any cell is invalid.
+    // Only cells that were changed are read, so the others keep their exact values.
+    fn changes(&self, particles: &[ChargedParticle]) -> Option<Vec<(usize, ChargedParticle)>> {
+        if self.invalid_cells() > 0 {
+            return None;
+        }
+
+        let changes = self
+            .rows
+            .iter()
+            .zip(self.cells.iter().zip(&self.original))
+            .filter(|(_, (cells, original))| cells != original)
+            .map(|(&index, (cells, original))| {
+                let mut particle = particles[index];
+                let edited = |column: usize| {
+                    (cells[column] != original[column])
+                        .then(|| Self::parse(column, &cells[column]).unwrap())
+                };
+
+                if let Some(x) = edited(0) {
+                    particle.x = x;
+                }
+                if let Some(y) = edited(1) {
+                    particle.y = y;
+                }
+                if let Some(elementary) = edited(2) {
+                    particle.charge = elementary * CHARGE_STEP;
+                    if particle.charge.abs() < CHARGE_SNAP {
+                        particle.charge = 0.0;
+                    }
+                }
+                if let Some(pinned) = edited(BATCH_EDIT_PINNED) {
+                    particle.pinned = pinned == 1.0;
+                }
+
+                (index, particle)
+            })
+            .collect();
+
+        Some(changes)
+    }
+
+    // Keeps the rows on the same particles after particle `index` is removed, returning whether
+    // any are left
+    fn after_removal(&mut self, index: usize) -> bool {
+        if let Some(row) = self.rows.iter().position(|&row| row == index) {
+            self.rows.remove(row);
+            self.cells.remove(row);
+            self.original.remove(row);
+            self.cursor = (self.cursor.0.min(self.rows.len()), self.cursor.1);
+        }
+        for row in self.rows.iter_mut().filter(|row| **row > index) {
+            *row -= 1;
+        }
+
+        !self.rows.is_empty()
+    }
+}
+
+// Axes through the middle of the play area that placements are mirrored across in mirror mode
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum MirrorAxes {
+    Vertical,
+    Horizontal,
+    Both,
+}
+
+impl MirrorAxes {
+    fn has_vertical(self) -> bool {
+        matches!(self, MirrorAxes::Vertical | MirrorAxes::Both)
+    }
+
+    fn has_horizontal(self) -> bool {
+        matches!(self, MirrorAxes::Horizontal | MirrorAxes::Both)
+    }
+}
+
+impl MeasurePoint {
+    // Keeps pointing at the same particle after the one at `index` is removed, or stays where
+    // the removed particle was if it was this one
+    fn after_removal(self, index: usize, removed: &ChargedParticle) -> Self {
+        match self {
+            MeasurePoint::Particle(i) if i == index => MeasurePoint::Fixed(removed.pos()),
+            MeasurePoint::Particle(i) if i > index => MeasurePoint::Particle(i - 1),
+            point => point,
+        }
+    }
+}
+
+// Readout of the measure tool, in world pixels, volts and newtons
+#[derive(Debug, PartialEq)]
+struct Measurement {
+    start: (f64, f64),
+    end: (f64, f64),
+    distance: f64,
+    // V(start) - V(end)
+    potential_difference: f64,
+    // Coulomb force between the two particles, positive if they repel. Only there if both ends
+    // are on distinct particles that aren't on top of each other.
+    force: Option<f64>,
+}
+
+struct Toolbar {
+    selected_tool: Option<Tool>,
+    choices: Vec<Tool>,
+    // Placement charge shown by the indicator at the bottom of the toolbar
+    charge: f64,
+}
+
+impl Default for Toolbar {
+    fn default() -> Self {
+        Self {
+            selected_tool: None,
+            choices: vec![
+                Tool::Place(Particle::Positive),
+                Tool::Place(Particle::Negative),
+                Tool::Place(Particle::Neutral),
+                Tool::Line,
+                Tool::Erase,
+                Tool::Move,
+                Tool::Gauss,
+                Tool::Ground,
+                Tool::Measure,
+                Tool::Dipole,
+                Tool::Comb,
+            ],
+            charge: 0.0,
+        }
+    }
+}
+
+impl Toolbar {
+    // The options share the toolbar strip above the charge indicator. Fails if the size of the
+    // canvas can't be read, which can happen while the window is being resized.
+    fn get_option_rect(&self, canvas: &WindowCanvas, index: usize) -> Result<Rect, String> {
+        let output_size = canvas.output_size()?;
+        let top_left = (output_size.0 * 9 / 10, 0);
+        let bottom_right = (
+            output_size.0,
+            get_toolbar_bottom(output_size).saturating_sub(CHARGE_INDICATOR_HEIGHT),
+        );
+
+        let y_inc = (bottom_right.1 - top_left.1) / self.choices.len() as u32;
+        let opt_top_left = (top_left.0, top_left.1 + (index as u32) * y_inc);
+
+        Ok(Rect::new(
+            opt_top_left.0 as i32,
+            opt_top_left.1 as i32,
+            bottom_right.0 - top_left.0,
+            y_inc,
+        ))
+    }
+
+    fn get_selected_option(&self) -> Option<Tool> {
+        self.selected_tool
+    }
+
+    // Follows a change of the placement charge's sign, unless a tool other than placing is
+    // selected
+    fn set_selected_particle(&mut self, particle: Particle) {
+        if let None | Some(Tool::Place(_)) = self.selected_tool {
+            self.selected_tool = Some(Tool::Place(particle));
+        }
+    }
+
+    // Selects the tool at `index` in the toolbar, leaving the selection alone if there's no tool
+    // there
+    fn select_index(&mut self, index: usize) -> Option<Tool> {
+        let tool = *self.choices.get(index)?;
+        self.selected_tool = Some(tool);
+
+        Some(tool)
+    }
+
+    fn set_charge(&mut self, charge: f64) {
+        self.charge = charge;
+    }
+
+    // Draws one tick per elementary charge of the placement charge, red for positive and blue
+    // for negative, or a gray dash if it's neutral. Ticks that don't fit are left out.
+    fn draw_charge_indicator(
+        &self,
+        canvas: &mut WindowCanvas,
+        theme: &Theme,
+    ) -> Result<(), String> {
+        let output_size = canvas.output_size()?;
+        let area = Rect::new(
+            (output_size.0 * 9 / 10) as i32,
+            get_toolbar_bottom(output_size).saturating_sub(CHARGE_INDICATOR_HEIGHT) as i32,
+            output_size.0 / 10,
+            CHARGE_INDICATOR_HEIGHT,
+        );
+
+        if self.charge == 0.0 {
+            canvas.set_draw_color(theme.neutral);
+            canvas.fill_rect(Rect::new(
+                area.x + area.w / 4,
+                area.y + area.h / 2 - 2,
+                area.w as u32 / 2,
+                4,
+            ))?;
+            return Ok(());
+        }
+
+        let ticks = ((self.charge.abs() / CHARGE_STEP).round() as i32).max(1);
+        let per_row = ((area.w - TICK_SPACING) / TICK_SPACING).max(1);
+        let rows = ((area.h - TICK_SPACING) / (TICK_HEIGHT + TICK_SPACING)).max(1);
+
+        canvas.set_draw_color(theme.particle_color(Particle::from_charge(self.charge)));
+
+        for i in 0..ticks.min(per_row * rows) {
+            canvas.fill_rect(Rect::new(
+                area.x + TICK_SPACING + (i % per_row) * TICK_SPACING,
+                area.y + TICK_SPACING + (i / per_row) * (TICK_HEIGHT + TICK_SPACING),
+                TICK_SPACING as u32 / 2,
+                TICK_HEIGHT as u32,
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_mouse_down(&mut self, canvas: &WindowCanvas, x: i32, y: i32) -> Result<(), String> {
+        for i in 0..self.choices.len() {
+            let opt_rect = self.get_option_rect(canvas, i)?;
+
+            if x >= opt_rect.x
+                && x <= opt_rect.x + opt_rect.w
+                && y >= opt_rect.y
+                && y <= opt_rect.y + opt_rect.h
+            {
+                self.selected_tool = Some(self.choices[i]);
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_update(&self, canvas: &mut WindowCanvas, theme: &Theme) -> Result<(), String> {
+        let output_size = canvas.output_size()?;
+
+        canvas.set_draw_color(theme.toolbar);
+        canvas.fill_rect(Rect::new(
+            (output_size.0 * 9 / 10) as i32,
+            0,
+            output_size.0 / 10,
+            get_toolbar_bottom(output_size),
+        ))?;
+
+        for (i, c) in self.choices.iter().enumerate() {
+            let opt_rect = self.get_option_rect(canvas, i)?;
+
+            if Some(*c) == self.selected_tool {
+                canvas.set_draw_color(theme.text);
+                canvas.draw_rect(opt_rect)?;
+            }
+
+            // gfx takes i16 coordinates, so icons that would be past their range are left out
+            // rather than wrapping around to the other side of the screen
+            let (Ok(x), Ok(y)) = (
+                i16::try_from(opt_rect.x + opt_rect.w / 2),
+                i16::try_from(opt_rect.y + opt_rect.h / 2),
+            ) else {
+                continue;
+            };
+
+            draw_tool(canvas, theme, *c, x, y, PARTICLE_RADIUS as i32)?;
+        }
+
+        self.draw_charge_indicator(canvas, theme)?;
+
+        Ok(())
+    }
+}
+
+// Maps world coordinates, which particles and the physics live in, to screen pixels and back
+#[derive(Clone, Copy, PartialEq)]
+struct View {
+    // World position shown at the top left corner of the screen
+    offset: (f64, f64),
+    scale: f64,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            offset: (0.0, 0.0),
+            scale: 1.0,
+        }
+    }
+}
+
+impl View {
+    fn to_screen(self, pos: (f64, f64)) -> (f64, f64) {
+        (
+            (pos.0 - self.offset.0) * self.scale,
+            (pos.1 - self.offset.1) * self.scale,
+        )
+    }
+
+    fn to_world(self, pos: (f64, f64)) -> (f64, f64) {
+        (
+            pos.0 / self.scale + self.offset.0,
+            pos.1 / self.scale + self.offset.1,
+        )
+    }
+
+    fn to_screen_point(self, pos: (f64, f64)) -> Point {
+        let (x, y) = self.to_screen(pos);
+        Point::new(x as i32, y as i32)
+    }
+
+    // Zooms by `factor` while keeping the world position under `screen_pos` in place
+    fn zoom_at(&mut self, screen_pos: (f64, f64), factor: f64) {
+        let world_pos = self.to_world(screen_pos);
+
+        self.scale = (self.scale * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = (
+            world_pos.0 - screen_pos.0 / self.scale,
+            world_pos.1 - screen_pos.1 / self.scale,
+        );
+    }
+}
+
+// Only the particles and the placement charge are saved to scene files, everything else is
+// session state
+#[derive(Default, Serialize, Deserialize)]
+struct Game<'ttf> {
+    #[serde(flatten)]
+    scene: Scene,
+    current_selected_charge: f64,
+    // Size of the play area in screen pixels
+    #[serde(skip)]
+    play_area: (f64, f64),
+    // Order of the multipole expansion compared against the exact field, if any. While it's set
+    // the expansion's field is drawn as a second arrow grid, and the heatmap can show how far
+    // it is off from the exact field instead of the field itself.
+    #[serde(skip)]
+    multipole_order: Option<usize>,
+    #[serde(skip)]
+    show_multipole_error: bool,
+    // Lists the multipole moments in the corner of the play area
+    #[serde(skip)]
+    show_moments: bool,
+    #[serde(skip)]
+    scene_path: PathBuf,
+    // Scene file Ctrl+Shift+O merges into this one
+    #[serde(skip)]
+    merge_path: PathBuf,
+    // Charges before each undoable edit, the latest last
+    #[serde(skip)]
+    history: Vec<Snapshot>,
+    #[serde(skip)]
+    show_equipotentials: bool,
+    #[serde(skip)]
+    show_heatmap: bool,
+    #[serde(default)]
+    heatmap_range: HeatmapRange,
+    #[serde(skip)]
+    heatmap_samples: Option<HeatmapSamples>,
+    // End of the legend being dragged, where the drag is at in screen pixels and whether it
+    // has moved, a click that didn't toggling the lock instead
+    #[serde(skip)]
+    legend_drag: Option<(LegendGrab, i32, bool)>,
+    #[serde(skip)]
+    show_arrow_grid: bool,
+    #[serde(skip)]
+    show_forces: bool,
+    #[serde(skip)]
+    font: Option<Font<'ttf, 'static>>,
+    #[serde(skip)]
+    view: View,
+    #[serde(skip)]
+    theme: Theme,
+    // Colors from the config file, which stay on top of the theme as it's switched
+    #[serde(skip)]
+    color_overrides: ColorOverrides,
+    #[serde(skip)]
+    simulating: bool,
+    // Meters per pixel the hydrogen preset's atomic scale replaced and whether dynamics were
+    // running, put back along with the particle mass and time scale when another scene, an
+    // undo or clearing replaces it
+    #[serde(skip)]
+    replaced_scale: Option<(f64, bool)>,
+    // Particle picked with the move tool, which the charge keys edit instead of the placement
+    // charge
+    #[serde(skip)]
+    selected: Option<usize>,
+    // Dipole picked with the move tool, whose charge, separation and angle C edits
+    #[serde(skip)]
+    selected_dipole: Option<usize>,
+    // Particles picked by Shift+clicking them with the move tool, which E edits together in the
+    // batch edit table while it's open
+    #[serde(skip)]
+    multi_selection: Vec<usize>,
+    #[serde(skip)]
+    batch_edit: Option<BatchEdit>,
+    // Charge being typed in after pressing C, in units of e, which every key goes to until it's
+    // committed with Enter or cancelled with Escape
+    #[serde(skip)]
+    charge_entry: Option<String>,
+    // Axes each placement is mirrored across, if any, and whether the copies get the opposite
+    // charge of the particle they mirror. The copies are independent particles once placed.
+    #[serde(skip)]
+    mirror: Option<MirrorAxes>,
+    #[serde(skip)]
+    mirror_antisymmetric: bool,
+    // Particle under the last left click, the event timestamp and its charge before the click,
+    // for telling double clicks apart
+    #[serde(skip)]
+    last_click: Option<(usize, u32, f64)>,
+    // Particles copied with Ctrl+C, relative to the cursor they're pasted at, and whether the
+    // next click pastes them
+    #[serde(skip)]
+    clipboard: Vec<ChargedParticle>,
+    #[serde(skip)]
+    pasting: bool,
+    // What's being moved with the move tool, and the world position it was last dragged to
+    #[serde(skip)]
+    dragging: Option<Hit>,
+    #[serde(skip)]
+    drag_anchor: (f64, f64),
+    #[serde(skip)]
+    snap_to_grid: bool,
+    // First end of the line charge being placed with the line tool
+    #[serde(skip)]
+    line_start: Option<(f64, f64)>,
+    // First end of the comb being placed with the comb tool, and how many lines new ones seed
+    #[serde(skip)]
+    comb_start: Option<(f64, f64)>,
+    #[serde(skip)]
+    comb_lines: usize,
+    // First corner of the Gaussian surface being dragged out, and the surface shown until it's
+    // dismissed
+    #[serde(skip)]
+    gauss_start: Option<(f64, f64)>,
+    #[serde(skip)]
+    gauss_rect: Option<Bounds>,
+    // Where the drag placing a ground plane started
+    #[serde(skip)]
+    ground_start: Option<(f64, f64)>,
+    // First point clicked with the measure tool, and the two ends of the measurement shown
+    // until the tool is used again or it's dismissed
+    #[serde(skip)]
+    measure_start: Option<MeasurePoint>,
+    #[serde(skip)]
+    measure_ends: Option<(MeasurePoint, MeasurePoint)>,
+    // Test charges move every frame, so they're drawn over the cached scene rather than into it
+    #[serde(skip)]
+    test_charges: Vec<TestCharge>,
+    // Whether the next click in the play area drops a test charge
+    #[serde(skip)]
+    placing_test_charge: bool,
+    #[serde(skip)]
+    show_help: bool,
+    // Set whenever what's drawn changes without the model changing, like the view or what's
+    // shown, so the cached scene texture gets redrawn
+    #[serde(skip)]
+    dirty: bool,
+    // Bumped by model_changed whenever the charges or anything else that changes the field does,
+    // and what it was at when the scene was last saved or loaded
+    #[serde(skip)]
+    generation: u64,
+    #[serde(skip)]
+    saved_generation: u64,
+    // The generation everything derived from the model was last brought up to date with
+    #[serde(skip)]
+    consumers: Consumers,
+    // Moments of the charges, kept up to date by refresh_model_consumers
+    #[serde(skip)]
+    moments: Multipole,
+    // Window title, with a star while there are unsaved changes
+    #[serde(skip)]
+    title: String,
+    // Screenshot to take after the next frame is drawn, whether to leave out the toolbar
+    #[serde(skip)]
+    pending_screenshot: Option<bool>,
+    #[serde(skip)]
+    exports: Exporter,
+    // Outcome of the last action that has one worth reporting, like saving a file
+    #[serde(skip)]
+    notice: Option<Notice>,
+    // Problems found in the config file, listed over the play area until a click or key press
+    #[serde(skip)]
+    config_problems: Vec<String>,
+    // Print how long each redraw spends tracing field lines
+    #[serde(skip)]
+    print_timing: bool,
+    // Field lines traced for the last redraw, in world coordinates
+    #[serde(skip)]
+    field_lines: Vec<FieldLine>,
+    // Draws the lines of each positive particle in its own hue from LINE_PALETTE
+    #[serde(skip)]
+    color_lines_by_source: bool,
+    // While animating, lines are left out of the cached scene and drawn on top of it every
+    // frame up to `line_progress` points, which grows by `line_growth` each frame
+    #[serde(skip)]
+    animate_lines: bool,
+    #[serde(skip)]
+    line_progress: usize,
+    #[serde(skip)]
+    line_growth: usize,
+    // Alt is held, previewing the trajectory of the selected particle
+    #[serde(skip)]
+    predicting: bool,
+    #[serde(skip)]
+    prediction_horizon: f64,
+    #[serde(skip)]
+    prediction_tick: f64,
+    #[serde(skip)]
+    prediction: Option<Prediction>,
+}
+
+impl<'ttf> Game<'ttf> {
+    fn new(config: &Config, ttf_context: Option<&'ttf Sdl2TtfContext>) -> Self {
+        let font = ttf_context.and_then(|ttf_context| load_font(ttf_context, LABEL_FONT_SIZE));
+
+        Game {
+            // Without a font they can only be printed, which loading the config already did
+            config_problems: match font {
+                Some(_) => config.problems.clone(),
+                None => Vec::new(),
+            },
+            font,
+            scene_path: config
+                .scene_path
+                .clone()
+                .unwrap_or_else(|| "scene.json".into()),
+            merge_path: config
+                .merge_path
+                .clone()
+                .or_else(|| config.scene_path.clone())
+                .unwrap_or_else(|| "scene.json".into()),
+            scene: Scene {
+                meters_per_pixel: config.meters_per_pixel,
+                particle_radius: config.particle_radius,
+                lines_per_charge: config.lines_per_charge,
+                max_line_iters: config.max_line_iters,
+                ..Default::default()
+            },
+            print_timing: std::env::var_os(TIMING_ENV_VAR).is_some(),
+            line_growth: config.line_growth as usize,
+            comb_lines: config.comb_lines,
+            prediction_horizon: config.prediction_horizon,
+            prediction_tick: config.prediction_tick,
+            theme: config.colors.apply(Theme::new(true, config.colorblind)),
+            color_overrides: config.colors,
+            ..Default::default()
+        }
+    }
+
+    // Reports `text` in the status bar. Without a font the status bar can't show it, so it's
+    // printed instead.
+    fn notify(&mut self, text: String) {
+        if self.font.is_none() {
+            say!("{text}");
+        } else {
+            log!("{text}");
+        }
+
+        self.notice = Some(Notice {
+            text,
+            error: false,
+            posted: Instant::now(),
+        });
+    }
+
+    // Like notify, but for failures, which always go to stderr too
+    fn notify_error(&mut self, text: String) {
+        eprintln!("{text}");
+
+        self.notice = Some(Notice {
+            text,
+            error: true,
+            posted: Instant::now(),
+        });
+    }
+
+    // What the heatmap shows at world position `pos`: the field magnitude, or with the error
+    // shown the magnitude of the difference between the exact field and the expansion of
+    // `moments`
+    fn get_heatmap_magnitude(&self, pos: (f64, f64), moments: &Multipole) -> f64 {
+        let (x_comp, y_comp) = self.scene.get_field_strength(pos.0, pos.1);
+
+        match self.multipole_order.filter(|_| self.show_multipole_error) {
+            Some(order) => {
+                let approx = self.scene.get_expansion_field(moments, pos.0, pos.1, order);
+                (approx.0 - x_comp).hypot(approx.1 - y_comp)
+            }
+            None => x_comp.hypot(y_comp),
+        }
+    }
+
+    fn get_heatmap_key(&self) -> HeatmapKey {
+        (
+            self.generation,
+            self.view,
+            self.play_area,
+            self.multipole_order,
+            self.show_multipole_error,
+        )
+    }
+
+    // Samples the heatmap again if the field or what's shown of it changed since the last time,
+    // or drops the samples while it's hidden
+    fn sample_heatmap(&mut self) {
+        let show_error = self.show_multipole_error && self.multipole_order.is_some();
+        if !(self.show_heatmap || show_error) || self.scene.particles.is_empty() {
+            self.heatmap_samples = None;
+            return;
+        }
+
+        let key = self.get_heatmap_key();
+        if self
+            .heatmap_samples
+            .as_ref()
+            .is_none_or(|samples| samples.key != key)
+        {
+            self.heatmap_samples = Some(self.get_heatmap_samples(HEATMAP_BLOCK_SIZE, key));
+        }
+    }
+
+    // Samples log10 of the field magnitude once per `block` x `block` pixels of the play area
+    fn get_heatmap_samples(&self, block: u32, key: HeatmapKey) -> HeatmapSamples {
+        let columns = (self.play_area.0 as u32).div_ceil(block);
+        let rows = (self.play_area.1 as u32).div_ceil(block);
+
+        // Cells inside a particle are left as None and shown with the hottest color
+        let magnitudes: Vec<Option<f64>> = (0..rows)
+            .flat_map(|j| (0..columns).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let (x, y) = self.view.to_world((
+                    (i as f64 + 0.5) * block as f64,
+                    (j as f64 + 0.5) * block as f64,
+                ));
+
+                if self.scene.particles.iter().any(|particle| {
+                    particle.distance_to((x, y)) <= self.scene.particle_radius(particle)
+                }) {
+                    return None;
+                }
+
+                Some(self.get_heatmap_magnitude((x, y), &self.moments).log10())
+                    .filter(|v| v.is_finite())
+            })
+            .collect();
+
+        let range = magnitudes
+            .iter()
+            .flatten()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+                (min.min(*v), max.max(*v))
+            });
+
+        HeatmapSamples {
+            key,
+            columns,
+            rows,
+            magnitudes,
+            range,
+        }
+    }
+
+    // Range the heatmap's colors span, the locked one or else what the samples cover
+    fn get_heatmap_range(&self, samples: &HeatmapSamples) -> (f64, f64) {
+        if self.heatmap_range.locked {
+            (self.heatmap_range.min, self.heatmap_range.max)
+        } else {
+            samples.range
+        }
+    }
+
+    // Colors the heatmap samples, returning RGB24 rows of `columns` pixels
+    fn get_heatmap_pixels(&self, samples: &HeatmapSamples) -> Vec<u8> {
+        let range = self.get_heatmap_range(samples);
+
+        samples
+            .magnitudes
+            .iter()
+            .flat_map(|magnitude| heatmap_color(*magnitude, range))
+            .collect()
+    }
+
+    // Where the heatmap legend is drawn, along the right of the play area
+    fn get_legend_rect(&self) -> Rect {
+        let height = LEGEND_HEIGHT.min((self.play_area.1 as i32 - 2 * LEGEND_MARGIN).max(1) as u32);
+
+        Rect::new(
+            self.play_area.0 as i32 - LEGEND_MARGIN - LEGEND_WIDTH as i32,
+            LEGEND_MARGIN,
+            LEGEND_WIDTH,
+            height,
+        )
+    }
+
+    // Whether the heatmap legend is shown and (`x`, `y`) is close enough to it to grab it
+    fn is_over_legend(&self, x: i32, y: i32) -> bool {
+        let rect = self.get_legend_rect();
+        let grab_area = Rect::new(
+            rect.x(),
+            rect.y() - LEGEND_GRAB_DISTANCE,
+            rect.width(),
+            rect.height() + 2 * LEGEND_GRAB_DISTANCE as u32,
+        );
+
+        self.heatmap_samples.is_some() && grab_area.contains_point((x, y))
+    }
+
+    // Starts dragging an end of the heatmap legend, or clicking its middle, if it's shown and
+    // under the cursor. Returns whether it was.
+    fn begin_legend_drag(&mut self, x: i32, y: i32) -> bool {
+        if !self.is_over_legend(x, y) {
+            return false;
+        }
+        let Some(samples) = self.heatmap_samples.as_ref() else {
+            return false;
+        };
+        let rect = self.get_legend_rect();
+
+        let grab = if (y - rect.top()).abs() <= LEGEND_GRAB_DISTANCE {
+            LegendGrab::Top
+        } else if (y - rect.bottom()).abs() <= LEGEND_GRAB_DISTANCE {
+            LegendGrab::Bottom
+        } else {
+            LegendGrab::Middle
+        };
+
+        // Dragging an end locks the range, starting from the one shown
+        if grab != LegendGrab::Middle && !self.heatmap_range.locked {
+            let (min, max) = samples.range;
+            self.heatmap_range = HeatmapRange {
+                locked: true,
+                min,
+                max,
+            };
+        }
+        self.legend_drag = Some((grab, y, false));
+
+        true
+    }
+
+    // Moves the dragged end of the legend to `y`, keeping the range from closing up. Only the
+    // colors change, the samples are reused.
+    fn handle_legend_drag(&mut self, y: i32) {
+        let Some((grab, last_y, _)) = self.legend_drag else {
+            return;
+        };
+        self.legend_drag = Some((grab, y, true));
+
+        let height = self.get_legend_rect().height() as f64;
+        let range = &mut self.heatmap_range;
+        let decades_per_pixel = (range.max - range.min) / height;
+        let shift = -(y - last_y) as f64 * decades_per_pixel;
+
+        match grab {
+            LegendGrab::Top => range.max = (range.max + shift).max(range.min + MIN_HEATMAP_RANGE),
+            LegendGrab::Bottom => {
+                range.min = (range.min + shift).min(range.max - MIN_HEATMAP_RANGE)
+            }
+            LegendGrab::Middle => return,
+        }
+
+        self.dirty = true;
+    }
+
+    // Locks the heatmap's range to the one shown now, or goes back to following the samples
+    fn toggle_heatmap_lock(&mut self) {
+        if self.heatmap_range.locked {
+            self.heatmap_range.locked = false;
+        } else if let Some(samples) = self.heatmap_samples.as_ref() {
+            let (min, max) = samples.range;
+            self.heatmap_range = HeatmapRange {
+                locked: true,
+                min,
+                max,
+            };
+        }
+        log!("Heatmap range locked: {}", self.heatmap_range.locked);
+
+        self.dirty = true;
+    }
+
+    // Draws the heatmap's color scale with the field strengths at its ends, noting whether the
+    // range is locked
+    fn draw_heatmap_legend<T: SceneTarget>(
+        &self,
+        canvas: &mut Canvas<T>,
+        samples: &HeatmapSamples,
+    ) -> Result<(), String> {
+        let rect = self.get_legend_rect();
+        let (min, max) = self.get_heatmap_range(samples);
+
+        // Hottest at the top
+        for row in 0..rect.height() as i32 {
+            let t = 1.0 - row as f64 / (rect.height() - 1).max(1) as f64;
+            let [r, g, b] = heatmap_color(Some(min + t * (max - min)), (min, max));
+
+            canvas.set_draw_color(Color::RGB(r, g, b));
+            canvas.draw_line(
+                (rect.left(), rect.top() + row),
+                (rect.right() - 1, rect.top() + row),
+            )?;
+        }
+        canvas.set_draw_color(self.theme.text);
+        canvas.draw_rect(rect)?;
+
+        let Some(font) = self.font.as_ref() else {
+            return Ok(());
+        };
+        let label = |decades: f64| format!("{:.1e} N/C", 10f64.powf(decades));
+        let mode = if self.heatmap_range.locked {
+            "locked"
+        } else {
+            "auto"
+        };
+        let labels = [
+            (label(max), rect.top() - font.height()),
+            (label(min), rect.bottom()),
+            (mode.to_string(), rect.bottom() + font.height()),
+        ];
+
+        // Right-aligned with the scale, so they stay inside the play area
+        for (text, y) in labels {
+            let (width, _) = font.size_of(&text).map_err(|e| e.to_string())?;
+            draw_text(
+                canvas,
+                font,
+                &text,
+                rect.right() - width as i32,
+                y,
+                self.theme.text,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Arrows from the center of each charged particle along the net force on it, in screen
+    // coordinates, with the force's magnitude in N. Lengths grow with the log of the magnitude
+    // like the arrow grid's.
+    fn get_force_arrows(&self) -> Vec<(Arrow, f64)> {
+        let forces: Vec<((f64, f64), (f64, f64))> = self
+            .scene
+            .particles
+            .iter()
+            .zip(self.scene.get_net_forces())
+            .filter(|(particle, force)| particle.charge != 0.0 && force.0.hypot(force.1) > 0.0)
+            .map(|(particle, force)| (self.view.to_screen(particle.pos()), force))
+            .collect();
+
+        let (min, max) = forces
+            .iter()
+            .map(|(_, force)| force.0.hypot(force.1).log10())
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+        let range = (max - min).max(f64::EPSILON);
+
+        forces
+            .into_iter()
+            .map(|(pos, force)| {
+                let magnitude = force.0.hypot(force.1);
+                let t = (magnitude.log10() - min) / range;
+                let length =
+                    FORCE_ARROW_MIN_LENGTH + t * (FORCE_ARROW_MAX_LENGTH - FORCE_ARROW_MIN_LENGTH);
+
+                let tip = (
+                    pos.0 + length * force.0 / magnitude,
+                    pos.1 + length * force.1 / magnitude,
+                );
+
+                ((pos, tip), magnitude)
+            })
+            .collect()
+    }
+
+    // Samples `field` at world positions every `spacing` screen pixels and returns the tail and
+    // tip of an arrow centered on each sample point, in screen coordinates. Lengths grow with the
+    // log of the magnitude but stay shorter than the spacing, so neighboring arrows never
+    // overlap.
+    fn get_arrow_grid(
+        &self,
+        spacing: u32,
+        field: impl Fn(f64, f64) -> (f64, f64),
+    ) -> Vec<((f64, f64), (f64, f64))> {
+        let samples: Vec<((f64, f64), (f64, f64))> = (0..)
+            .map(|j| (j * spacing + spacing / 2) as f64)
+            .take_while(|y| *y < self.play_area.1)
+            .flat_map(|y| {
+                (0..)
+                    .map(|i| (i * spacing + spacing / 2) as f64)
+                    .take_while(|x| *x < self.play_area.0)
+                    .map(move |x| (x, y))
+            })
+            .filter(|pos| {
+                let (x, y) = self.view.to_world(*pos);
+
+                self.scene.particles.iter().all(|particle| {
+                    particle.distance_to((x, y)) > self.scene.particle_radius(particle)
+                })
+            })
+            .map(|pos| {
+                let (x, y) = self.view.to_world(pos);
+                (pos, field(x, y))
+            })
+            .filter(|(_, field)| field.0.hypot(field.1) > 0.0)
+            .collect();
+
+        let (min, max) = samples
+            .iter()
+            .map(|(_, field)| field.0.hypot(field.1).log10())
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+        let range = (max - min).max(f64::EPSILON);
+
+        samples
+            .into_iter()
+            .map(|(pos, field)| {
+                let magnitude = field.0.hypot(field.1);
+                let t = (magnitude.log10() - min) / range;
+                let half_length = (0.2 + 0.65 * t) * spacing as f64 / 2.0;
+                let dir = (field.0 / magnitude, field.1 / magnitude);
+
+                (
+                    (pos.0 - half_length * dir.0, pos.1 - half_length * dir.1),
+                    (pos.0 + half_length * dir.0, pos.1 + half_length * dir.1),
+                )
+            })
+            .collect()
+    }
+
+    // Rounds a world position to the nearest grid intersection if snapping is on
+    fn snap(&self, pos: (f64, f64)) -> (f64, f64) {
+        if self.snap_to_grid {
+            (
+                (pos.0 / GRID_PITCH).round() * GRID_PITCH,
+                (pos.1 / GRID_PITCH).round() * GRID_PITCH,
+            )
+        } else {
+            pos
+        }
+    }
+
+    // World position the mirror axes cross at, the middle of the play area snapped to the grid
+    // so mirrored placements stay on it
+    fn get_mirror_center(&self) -> (f64, f64) {
+        self.snap(
+            self.view
+                .to_world((self.play_area.0 / 2.0, self.play_area.1 / 2.0)),
+        )
+    }
+
+    // Particles placing `particle` adds in mirror mode, starting with itself. A placement on an
+    // axis is its own mirror image across it, so it isn't doubled up.
+    fn get_mirrored_placements(&self, particle: ChargedParticle) -> Vec<ChargedParticle> {
+        let mut placements = vec![particle];
+        let Some(axes) = self.mirror else {
+            return placements;
+        };
+
+        let (center_x, center_y) = self.get_mirror_center();
+        let (x, y) = particle.pos();
+        let (mirrored_x, mirrored_y) = (2.0 * center_x - x, 2.0 * center_y - y);
+        let charge = particle.charge;
+        let mirrored_charge = if self.mirror_antisymmetric {
+            -charge
+        } else {
+            charge
+        };
+
+        // Mirroring across both axes flips the charge twice, which leaves it as it was
+        let mut copies = Vec::new();
+        if axes.has_vertical() {
+            copies.push(ChargedParticle::new(mirrored_x, y, mirrored_charge));
+        }
+        if axes.has_horizontal() {
+            copies.push(ChargedParticle::new(x, mirrored_y, mirrored_charge));
+        }
+        if axes == MirrorAxes::Both {
+            copies.push(ChargedParticle::new(mirrored_x, mirrored_y, charge));
+        }
+
+        for copy in copies {
+            if placements.iter().all(|placed| placed.pos() != copy.pos()) {
+                placements.push(copy);
+            }
+        }
+
+        placements
+    }
+
+    // Particles a click with the placement tool at screen position (`x`, `y`) puts down, snapped
+    // to the grid and mirrored
+    fn get_placements(&self, x: i32, y: i32) -> Vec<ChargedParticle> {
+        let (x, y) = self.snap(self.view.to_world((x as f64, y as f64)));
+
+        self.get_mirrored_placements(ChargedParticle::new(x, y, self.current_selected_charge))
+    }
+
+    // Why `placements` can't be put down, if they can't. Each has to be drawn wholly inside the
+    // play area, clear of the toolbar and the status bar, and either land on a particle, which
+    // adds to its charge, or not touch any particle or other placement at all.
+    fn check_placements(&self, placements: &[ChargedParticle]) -> Result<(), String> {
+        let (width, height) = self.play_area;
+
+        for (i, particle) in placements.iter().enumerate() {
+            let (x, y) = self.view.to_screen(particle.pos());
+            let radius = self.get_screen_radius(particle);
+            if !(0.0..width).contains(&x) || !(0.0..height).contains(&y) {
+                return Err("is outside the play area".into());
+            } else if x + radius > width {
+                return Err("would overlap the toolbar".into());
+            } else if y + radius > height {
+                return Err("would overlap the status bar".into());
+            } else if x < radius || y < radius {
+                return Err("would reach past the edge of the window".into());
+            }
+
+            if self.scene.get_merge_target(particle).is_some() {
+                continue;
+            }
+
+            let reach = self.scene.particle_radius(particle);
+            if let Some(other) = self
+                .scene
+                .particles
+                .iter()
+                .chain(&placements[..i])
+                .find(|other| {
+                    other.distance_to(particle.pos()) < reach + self.scene.particle_radius(other)
+                })
+            {
+                return Err(format!(
+                    "would overlap the particle at ({:.0}, {:.0})",
+                    other.x, other.y
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_mouse_down(&mut self, x: i32, y: i32) {
+        let placements = self.get_placements(x, y);
+        if let Err(reason) = self.check_placements(&placements) {
+            self.notify_error(format!("Can't place a particle here, it {reason}"));
+            return;
+        }
+
+        // Mirrored copies are undone along with the particle they mirror
+        self.push_history();
+        for particle in placements {
+            self.place_particle(particle);
+        }
+
+        self.model_changed();
+    }
+
+    // Where the placement preview follows the cursor, which is whenever it's over the play area
+    // with the placement tool and a click there would go to it
+    fn get_placement_cursor(
+        &self,
+        tool: Option<Tool>,
+        probe: Option<(i32, i32)>,
+    ) -> Option<(i32, i32)> {
+        let (x, y) = probe?;
+        let placing = matches!(tool, Some(Tool::Place(_)) | None);
+
+        (placing && !self.pasting && !self.show_help && !self.is_over_legend(x, y))
+            .then_some((x, y))
+    }
+
+    // Outlines what a click at (`x`, `y`) would put down at the size it would be drawn, green if
+    // it would be placed and red if it wouldn't. A charge dropped on a particle is shown as that
+    // particle grown by it.
+    fn draw_placement_preview(
+        &self,
+        canvas: &mut WindowCanvas,
+        x: i32,
+        y: i32,
+    ) -> Result<(), String> {
+        let placements = self.get_placements(x, y);
+        let color = match self.check_placements(&placements) {
+            Ok(()) => PLACEMENT_OK_COLOR,
+            Err(_) => NOTICE_ERROR_COLOR,
+        };
+
+        for particle in placements {
+            let shown = match self.scene.get_merge_target(&particle) {
+                Some(index) => {
+                    let target = self.scene.particles[index];
+                    ChargedParticle {
+                        charge: target.charge + particle.charge,
+                        ..target
+                    }
+                }
+                None => particle,
+            };
+            let Some((screen_x, screen_y)) = to_gfx_point(self.view.to_screen(shown.pos())) else {
+                continue;
+            };
+
+            canvas.aa_circle(
+                screen_x,
+                screen_y,
+                self.get_screen_radius(&shown).round() as i16,
+                color,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn place_particle(&mut self, particle: ChargedParticle) {
+        let (x, y) = particle.pos();
+
+        // Placing on top of a particle adds to its charge instead of stacking a second one,
+        // which would make the field between them blow up. Cancelling out leaves it neutral.
+        if let Some(index) = self.scene.merge_particle(particle) {
+            let merged = &mut self.scene.particles[index];
+            if merged.charge.abs() < CHARGE_SNAP {
+                merged.charge = 0.0;
+            }
+            log!(
+                "Merged charge {} into particle at ({}, {}), its charge is now {}",
+                particle.charge,
+                merged.x,
+                merged.y,
+                merged.charge
+            );
+        } else {
+            self.scene.add_particle(particle);
+            log!(
+                "Added particle with charge {} at ({x}, {y})",
+                particle.charge
+            );
+        }
+    }
+
+    // Flips the sign of the particle under a second left click on it within DOUBLE_CLICK_MS,
+    // returning whether it did. The first click has already gone to the selected tool, so the
+    // particle goes back to its charge from before that first, which undoes the placement tool
+    // merging into it.
+    fn handle_double_click(&mut self, x: i32, y: i32, timestamp: u32) -> bool {
+        let Some(index) = self.get_particle_at(x, y) else {
+            self.last_click = None;
+            return false;
+        };
+
+        match self.last_click.take() {
+            Some((last, time, charge))
+                if last == index && timestamp.wrapping_sub(time) <= DOUBLE_CLICK_MS =>
+            {
+                self.push_history();
+                let particle = &mut self.scene.particles[index];
+                particle.charge = -charge;
+                log!(
+                    "Flipped the particle at ({}, {}) to charge {}",
+                    particle.x,
+                    particle.y,
+                    particle.charge
+                );
+
+                self.model_changed();
+                true
+            }
+            _ => {
+                self.last_click = Some((index, timestamp, self.scene.particles[index].charge));
+                false
+            }
+        }
+    }
+
+    // Index of the particle under the screen position (`x`, `y`), searching from the back so
+    // the topmost of overlapping particles is found first
+    fn get_particle_at(&self, x: i32, y: i32) -> Option<usize> {
+        let (x, y) = self.view.to_world((x as f64, y as f64));
+
+        self.scene.particles.iter().rposition(|particle| {
+            particle.distance_to((x, y)) <= self.scene.particle_radius(particle)
+        })
+    }
+
+    // Particle, otherwise the dipole or otherwise the line charge under the screen position
+    // (`x`, `y`)
+    fn get_hit_at(&self, x: i32, y: i32) -> Option<Hit> {
+        let pos = self.view.to_world((x as f64, y as f64));
+
+        self.get_particle_at(x, y)
+            .map(Hit::Particle)
+            .or_else(|| self.get_dipole_hit_at(pos))
+            .or_else(|| {
+                self.scene
+                    .lines
+                    .iter()
+                    .rposition(|line| line.distance_to(pos) <= LINE_CHARGE_WIDTH)
+                    .map(Hit::Line)
+            })
+            .or_else(|| self.get_comb_hit_at(pos))
+    }
+
+    // An end of the comb at world position `pos`, or the comb itself if it's anywhere else on it
+    fn get_comb_hit_at(&self, pos: (f64, f64)) -> Option<Hit> {
+        let near =
+            |point: (f64, f64)| (point.0 - pos.0).hypot(point.1 - pos.1) <= COMB_HANDLE_RADIUS;
+
+        self.scene
+            .combs
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, comb)| {
+                if near(comb.start) {
+                    Some(Hit::CombEnd(i, false))
+                } else if near(comb.end) {
+                    Some(Hit::CombEnd(i, true))
+                } else {
+                    (comb.distance_to(pos) <= LINE_CHARGE_WIDTH).then_some(Hit::Comb(i))
+                }
+            })
+    }
+
+    // The handle of the dipole at world position `pos`, or the dipole itself if it's on one of
+    // its other charge or the bar between them
+    fn get_dipole_hit_at(&self, pos: (f64, f64)) -> Option<Hit> {
+        self.scene
+            .dipoles
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, dipole)| {
+                let [positive, negative] = dipole.point_charges();
+                let bar = LineCharge::new(positive.pos(), negative.pos(), 0.0);
+
+                if positive.distance_to(pos) <= self.scene.particle_radius(&positive) {
+                    Some(Hit::DipoleHandle(i))
+                } else if negative.distance_to(pos) <= self.scene.particle_radius(&negative)
+                    || bar.distance_to(pos) <= LINE_CHARGE_WIDTH
+                {
+                    Some(Hit::Dipole(i))
+                } else {
+                    None
+                }
+            })
+    }
+
+    // Places a dipole at the screen position (`x`, `y`) made of charges as big as the placement
+    // charge, or of one elementary charge if that's neutral, lying along the x axis
+    fn place_dipole(&mut self, x: i32, y: i32) {
+        let center = self.snap(self.view.to_world((x as f64, y as f64)));
+        let charge = if self.current_selected_charge == 0.0 {
+            CHARGE_STEP
+        } else {
+            self.current_selected_charge.abs()
+        };
+
+        self.push_history();
+        self.scene
+            .dipoles
+            .push(Dipole::new(center, charge, DEFAULT_DIPOLE_SEPARATION, 0.0));
+        log!(
+            "Added dipole of charge {} at {center:?}",
+            format_charge(charge)
+        );
+
+        self.model_changed();
+    }
+
+    fn remove_dipole(&mut self, index: usize) {
+        let dipole = self.scene.dipoles.remove(index);
+        log!("Removed dipole at {:?}", dipole.center);
+
+        self.selected_dipole = match self.selected_dipole {
+            Some(selected) if selected == index => None,
+            Some(selected) if selected > index => Some(selected - 1),
+            selected => selected,
+        };
+    }
+
+    fn handle_erase(&mut self, x: i32, y: i32) {
+        let hit = self.get_hit_at(x, y);
+        if hit.is_some() {
+            self.push_history();
+        }
+
+        match hit {
+            Some(Hit::Particle(index)) => self.remove_particle(index),
+            Some(Hit::Line(index)) => {
+                let line = self.scene.lines.remove(index);
+                log!(
+                    "Removed line charge {} from {:?} to {:?}",
+                    line.charge,
+                    line.start,
+                    line.end
+                );
+            }
+            Some(Hit::Dipole(index) | Hit::DipoleHandle(index)) => self.remove_dipole(index),
+            Some(Hit::Comb(index) | Hit::CombEnd(index, _)) => {
+                let comb = self.scene.combs.remove(index);
+                log!("Removed comb from {:?} to {:?}", comb.start, comb.end);
+            }
+            None => return,
+        }
+
+        self.dragging = None;
+        self.model_changed();
+    }
+
+    // Removes a particle, keeping the selection on the same particle if it wasn't the one removed
+    fn remove_particle(&mut self, index: usize) {
+        let particle = self.scene.remove_particle(index);
+        log!(
+            "Removed particle with charge {} at ({}, {})",
+            particle.charge,
+            particle.x,
+            particle.y
+        );
+
+        self.selected = match self.selected {
+            Some(selected) if selected == index => None,
+            Some(selected) if selected > index => Some(selected - 1),
+            selected => selected,
+        };
+        self.multi_selection.retain(|&selected| selected != index);
+        for selected in self
+            .multi_selection
+            .iter_mut()
+            .filter(|selected| **selected > index)
+        {
+            *selected -= 1;
+        }
+        if let Some(edit) = self.batch_edit.as_mut() {
+            if !edit.after_removal(index) {
+                self.batch_edit = None;
+            }
+        }
+        self.last_click = None;
+
+        self.measure_start = self
+            .measure_start
+            .map(|point| point.after_removal(index, &particle));
+        self.measure_ends = self.measure_ends.map(|(start, end)| {
+            (
+                start.after_removal(index, &particle),
+                end.after_removal(index, &particle),
+            )
+        });
+    }
+
+    fn copy_selection(&mut self) {
+        let Some(particle) = self.selected.map(|index| self.scene.particles[index]) else {
+            return;
+        };
+
+        self.clipboard = vec![ChargedParticle::new(0.0, 0.0, particle.charge)];
+        log!(
+            "Copied particle with charge {}",
+            format_charge(particle.charge)
+        );
+    }
+
+    // Where the clipboard particles would land when pasted at the screen position (`x`, `y`)
+    fn get_paste_positions(&self, x: i32, y: i32) -> Vec<ChargedParticle> {
+        let cursor = self.view.to_world((x as f64, y as f64));
+
+        self.clipboard
+            .iter()
+            .map(|particle| {
+                let (x, y) = self.snap((cursor.0 + particle.x, cursor.1 + particle.y));
+                ChargedParticle::new(x, y, particle.charge)
+            })
+            .collect()
+    }
+
+    // Adds the clipboard particles around the screen position (`x`, `y`), unless some of them
+    // would end up under the toolbar
+    fn paste_at(&mut self, x: i32, y: i32) {
+        let pasted = self.get_paste_positions(x, y);
+
+        if pasted.iter().any(|particle| {
+            let (x, y) = self.view.to_screen(particle.pos());
+            x >= self.play_area.0 || y >= self.play_area.1
+        }) {
+            log!("Can't paste over the toolbar or status bar");
+            return;
+        }
+
+        // Pasting onto a particle merges into it the same way placing one does
+        self.push_history();
+        log!("Pasted {} particles", pasted.len());
+        // Copies get hues of their own
+        for particle in pasted {
+            self.place_particle(ChargedParticle {
+                hue: None,
+                ..particle
+            });
+        }
+        self.pasting = false;
+
+        self.model_changed();
+    }
+
+    // Particles keep their size in world space, so they shrink when zooming out
+    fn get_screen_radius(&self, particle: &ChargedParticle) -> f64 {
+        get_screen_radius(&self.scene, self.view, particle)
+    }
+
+    // Draws outlines of the clipboard particles where they'd be pasted
+    fn draw_paste_ghost(&self, canvas: &mut WindowCanvas, x: i32, y: i32) -> Result<(), String> {
+        for particle in self.get_paste_positions(x, y) {
+            let Some((screen_x, screen_y)) = to_gfx_point(self.view.to_screen(particle.pos()))
+            else {
+                continue;
+            };
+
+            canvas.aa_circle(
+                screen_x,
+                screen_y,
+                self.get_screen_radius(&particle).round() as i16,
+                self.theme
+                    .particle_color(Particle::from_charge(particle.charge)),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Adds `delta` to the charge of a particle, which flips its sign once it crosses zero
+    fn change_particle_charge(&mut self, index: usize, delta: f64) {
+        self.push_history();
+        let charge = &mut self.scene.particles[index].charge;
+        *charge += delta;
+        if charge.abs() < CHARGE_SNAP {
+            *charge = 0.0;
+        }
+        log!("Particle charge: {}", format_charge(*charge));
+
+        self.model_changed();
+    }
+
+    // Picks up what's under the cursor, if anything, for the move tool. Clicking a particle
+    // also selects it, and clicking anything else clears the selection.
+    fn begin_drag(&mut self, x: i32, y: i32) {
+        self.dragging = self.get_hit_at(x, y);
+        if !self.multi_selection.is_empty() {
+            self.multi_selection.clear();
+            self.dirty = true;
+        }
+
+        let selected = match self.dragging {
+            Some(Hit::Particle(index)) => Some(index),
+            _ => None,
+        };
+        let selected_dipole = match self.dragging {
+            Some(Hit::Dipole(index) | Hit::DipoleHandle(index)) => Some(index),
+            _ => None,
+        };
+        if (selected, selected_dipole) != (self.selected, self.selected_dipole) {
+            self.selected = selected;
+            self.selected_dipole = selected_dipole;
+            self.dirty = true;
+        }
+
+        // The whole drag is undone at once
+        if self.dragging.is_some() {
+            self.push_history();
+        }
+
+        self.drag_anchor = self.view.to_world((x as f64, y as f64));
+    }
+
+    // Adds the particle under the cursor to the multi-selection, or takes it out if it's
+    // already in it, for Shift+clicks with the move tool. A particle selected with a plain
+    // click joins it too.
+    fn toggle_multi_selection(&mut self, x: i32, y: i32) {
+        let Some(index) = self.get_particle_at(x, y) else {
+            return;
+        };
+
+        if let Some(selected) = self.selected.take() {
+            if !self.multi_selection.contains(&selected) {
+                self.multi_selection.push(selected);
+            }
+        }
+        self.selected_dipole = None;
+
+        match self
+            .multi_selection
+            .iter()
+            .position(|&selected| selected == index)
+        {
+            Some(position) => {
+                self.multi_selection.remove(position);
+            }
+            None => self.multi_selection.push(index),
+        }
+        log!("Selected {} particles", self.multi_selection.len());
+
+        self.dirty = true;
+    }
+
+    // Moves the dragged particle or comb end to the cursor, or a line charge, dipole or comb
+    // along with it. Particles are stopped in place so dynamics mode doesn't fling them away on
+    // release.
+    fn handle_drag(&mut self, x: i32, y: i32) {
+        let cursor = self.view.to_world((x as f64, y as f64));
+        let (world_x, world_y) = self.snap(cursor);
+
+        match self.dragging {
+            Some(Hit::Particle(index)) => {
+                let Some(particle) = self.scene.particles.get_mut(index) else {
+                    return;
+                };
+                (particle.x, particle.y) = (world_x, world_y);
+
+                if let Some(velocity) = self.scene.velocities.get_mut(index) {
+                    *velocity = (0.0, 0.0);
+                }
+            }
+            Some(Hit::Line(index)) => {
+                let Some(start) = self.scene.lines.get(index).map(|line| line.start) else {
+                    return;
+                };
+
+                // Lines move with the cursor by their first end, which snaps to the grid. The
+                // anchor only follows as far as the line did, so moves smaller than the grid
+                // pitch add up.
+                let target = self.snap((
+                    start.0 + cursor.0 - self.drag_anchor.0,
+                    start.1 + cursor.1 - self.drag_anchor.1,
+                ));
+                let offset = (target.0 - start.0, target.1 - start.1);
+
+                self.scene.lines[index].translate(offset);
+                self.drag_anchor = (self.drag_anchor.0 + offset.0, self.drag_anchor.1 + offset.1);
+            }
+            // Dipoles move by their center the same way lines do by their first end
+            Some(Hit::Dipole(index)) => {
+                let Some(center) = self.scene.dipoles.get(index).map(|dipole| dipole.center) else {
+                    return;
+                };
+
+                let target = self.snap((
+                    center.0 + cursor.0 - self.drag_anchor.0,
+                    center.1 + cursor.1 - self.drag_anchor.1,
+                ));
+                let offset = (target.0 - center.0, target.1 - center.1);
+
+                self.scene.dipoles[index].translate(offset);
+                self.drag_anchor = (self.drag_anchor.0 + offset.0, self.drag_anchor.1 + offset.1);
+            }
+            Some(Hit::DipoleHandle(index)) => {
+                let Some(dipole) = self.scene.dipoles.get_mut(index) else {
+                    return;
+                };
+                if (world_x, world_y) == dipole.center {
+                    return;
+                }
+
+                dipole.set_positive_end((world_x, world_y));
+            }
+            // Combs move by their first end the same way lines do
+            Some(Hit::Comb(index)) => {
+                let Some(start) = self.scene.combs.get(index).map(|comb| comb.start) else {
+                    return;
+                };
+
+                let target = self.snap((
+                    start.0 + cursor.0 - self.drag_anchor.0,
+                    start.1 + cursor.1 - self.drag_anchor.1,
+                ));
+                let offset = (target.0 - start.0, target.1 - start.1);
+
+                self.scene.combs[index].translate(offset);
+                self.drag_anchor = (self.drag_anchor.0 + offset.0, self.drag_anchor.1 + offset.1);
+            }
+            Some(Hit::CombEnd(index, second)) => {
+                let Some(comb) = self.scene.combs.get_mut(index) else {
+                    return;
+                };
+                let (moved, other) = if second {
+                    (&mut comb.end, comb.start)
+                } else {
+                    (&mut comb.start, comb.end)
+                };
+                if (world_x, world_y) == other {
+                    return;
+                }
+
+                *moved = (world_x, world_y);
+            }
+            None => return,
+        }
+
+        self.model_changed();
+    }
+
+    // Sets the first end of a line charge for the line tool
+    fn begin_line(&mut self, x: i32, y: i32) {
+        self.line_start = Some(self.snap(self.view.to_world((x as f64, y as f64))));
+    }
+
+    // Sets the first end of a comb for the comb tool
+    fn begin_comb(&mut self, x: i32, y: i32) {
+        self.comb_start = Some(self.snap(self.view.to_world((x as f64, y as f64))));
+    }
+
+    // Sets the first corner of a Gaussian surface for the Gauss tool
+    fn begin_gauss(&mut self, x: i32, y: i32) {
+        self.gauss_start = Some(self.view.to_world((x as f64, y as f64)));
+    }
+
+    // Starts the drag placing a ground plane for the ground tool
+    fn begin_ground(&mut self, x: i32, y: i32) {
+        self.ground_start = Some(self.snap(self.view.to_world((x as f64, y as f64))));
+    }
+
+    // Puts the ground plane through `start`, horizontal or vertical depending on which way the
+    // drag to `end` mostly went. A drag that goes nowhere removes the plane.
+    fn set_ground_plane(&mut self, start: (f64, f64), end: (f64, f64)) {
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+
+        self.scene.ground_plane = if dx == 0.0 && dy == 0.0 {
+            None
+        } else if dx.abs() >= dy.abs() {
+            Some(GroundPlane::Horizontal(start.1))
+        } else {
+            Some(GroundPlane::Vertical(start.0))
+        };
+        log!("Ground plane: {:?}", self.scene.ground_plane);
+
+        self.model_changed();
+    }
+
+    // Replaces the Gaussian surface with the rectangle spanned by `start` and `end`
+    fn set_gauss_rect(&mut self, start: (f64, f64), end: (f64, f64)) {
+        if start.0 == end.0 || start.1 == end.1 {
+            return;
+        }
+
+        let rect = (
+            (start.0.min(end.0), start.1.min(end.1)),
+            (start.0.max(end.0), start.1.max(end.1)),
+        );
+        self.gauss_rect = Some(rect);
+        log!(
+            "Flux through Gaussian surface: {:.2e} N m\u{b2}/C, \
+             enclosed charge / \u{3b5}\u{2080}: {:.2e} N m\u{b2}/C",
+            self.scene.get_gauss_flux(rect, GAUSS_SAMPLES_PER_SIDE),
+            self.scene.get_enclosed_charge(rect) / VACUUM_PERMITTIVITY
+        );
+
+        self.dirty = true;
+    }
+
+    // Picks either end of a measurement with the measure tool. Starting a new one replaces the
+    // last.
+    fn measure_at(&mut self, x: i32, y: i32) {
+        let point = match self.get_particle_at(x, y) {
+            Some(index) => MeasurePoint::Particle(index),
+            None => MeasurePoint::Fixed(self.view.to_world((x as f64, y as f64))),
+        };
+
+        match self.measure_start.take() {
+            Some(start) => {
+                self.measure_ends = Some((start, point));
+
+                if let Some(measurement) = self.get_measurement() {
+                    log!("{measurement:?}");
+                }
+            }
+            None => {
+                self.measure_start = Some(point);
+                self.measure_ends = None;
+            }
+        }
+
+        self.dirty = true;
+    }
+
+    // Dismisses the measurement, returning whether there was anything to dismiss
+    fn clear_measurement(&mut self) -> bool {
+        self.measure_start.take().is_some() | self.measure_ends.take().is_some()
+    }
+
+    fn get_measure_point_pos(&self, point: MeasurePoint) -> Option<(f64, f64)> {
+        match point {
+            MeasurePoint::Particle(index) => self.scene.particles.get(index).map(|p| p.pos()),
+            MeasurePoint::Fixed(pos) => Some(pos),
+        }
+    }
+
+    // Works out the current readout of the measure tool, following any particles it's on
+    fn get_measurement(&self) -> Option<Measurement> {
+        let (start_point, end_point) = self.measure_ends?;
+        let start = self.get_measure_point_pos(start_point)?;
+        let end = self.get_measure_point_pos(end_point)?;
+        let distance = (end.0 - start.0).hypot(end.1 - start.1);
+
+        let force = match (start_point, end_point) {
+            (MeasurePoint::Particle(a), MeasurePoint::Particle(b)) if a != b && distance > 0.0 => {
+                let r = distance * self.scene.meters_per_pixel;
+
+                Some(
+                    COULUMBS_CONST
+                        * self.scene.particles[a].charge
+                        * self.scene.particles[b].charge
+                        / (r * r),
+                )
+            }
+            _ => None,
+        };
+
+        Some(Measurement {
+            start,
+            end,
+            distance,
+            potential_difference: self.scene.get_potential(start.0, start.1)
+                - self.scene.get_potential(end.0, end.1),
+            force,
+        })
+    }
+
+    // Draws the measuring line dashed, with the readout next to its middle
+    fn draw_measurement<T: SceneTarget>(&self, canvas: &mut Canvas<T>) -> Result<(), String> {
+        let color = self.theme.text;
+
+        if let Some(point) = self.measure_start {
+            if let Some(pos) = self.get_measure_point_pos(point) {
+                if let Some((x, y)) = to_gfx_point(self.view.to_screen(pos)) {
+                    canvas.circle(x, y, TEST_CHARGE_RADIUS, color)?;
+                }
+            }
+        }
+
+        let Some(measurement) = self.get_measurement() else {
+            return Ok(());
+        };
+        let start = self.view.to_screen(measurement.start);
+        let end = self.view.to_screen(measurement.end);
+
+        canvas.set_draw_color(color);
+        draw_dashed_line(canvas, start, end)?;
+
+        let Some(font) = self.font.as_ref() else {
+            return Ok(());
+        };
+
+        let mut readout = format!(
+            "d = {:.1} px ({}), \u{394}V = {:.3e} V",
+            measurement.distance,
+            format_si(measurement.distance * self.scene.meters_per_pixel, "m"),
+            measurement.potential_difference
+        );
+        if let Some(force) = measurement.force {
+            let kind = if force > 0.0 {
+                "repulsive"
+            } else {
+                "attractive"
+            };
+            readout.push_str(&format!(", F = {:.3e} N {kind}", force.abs()));
+        }
+
+        draw_text(
+            canvas,
+            font,
+            &readout,
+            ((start.0 + end.0) / 2.0) as i32 + 4,
+            ((start.1 + end.1) / 2.0) as i32 + 4,
+            color,
+        )
+    }
+
+    fn place_test_charge(&mut self, x: i32, y: i32) {
+        let pos = self.view.to_world((x as f64, y as f64));
+
+        self.test_charges.push(TestCharge::new(pos));
+        self.placing_test_charge = false;
+        log!("Dropped test charge at {pos:?}");
+    }
+
+    fn clear_test_charges(&mut self) {
+        if !self.test_charges.is_empty() {
+            log!("Cleared {} test charges", self.test_charges.len());
+        }
+
+        self.test_charges.clear();
+        self.placing_test_charge = false;
+    }
+
+    // Moves every test charge along, dropping the ones that ran into a charge or left the play
+    // area
+    fn step_test_charges(&mut self, dt: f64) {
+        let ((left, top), (right, bottom)) = self.get_visible_bounds(0.0);
+        let scene = &self.scene;
+
+        self.test_charges.retain_mut(|test| {
+            scene.step_test_charge(test, dt)
+                && (left..=right).contains(&test.pos.0)
+                && (top..=bottom).contains(&test.pos.1)
+        });
+    }
+
+    // Draws the test charges with trails that fade out towards their oldest end
+    fn draw_test_charges(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let color = self.theme.particle_color(Particle::Positive);
+        let size = canvas.output_size()?;
+
+        for test in self.test_charges.iter() {
+            let points: Vec<(f64, f64)> = test
+                .trail
+                .iter()
+                .map(|pos| self.view.to_screen(*pos))
+                .collect();
+            // Trails that haven't reached their full length yet start out partly faded
+            let faded = TEST_CHARGE_TRAIL_LENGTH - points.len();
+
+            for (i, pair) in points.windows(2).enumerate() {
+                let alpha = 255 * (faded + i + 1) / TEST_CHARGE_TRAIL_LENGTH;
+                let Some((start, end)) = clip_segment(pair[0], pair[1], size) else {
+                    continue;
+                };
+
+                canvas.aa_line(
+                    start.0,
+                    start.1,
+                    end.0,
+                    end.1,
+                    Color::RGBA(color.r, color.g, color.b, alpha as u8),
+                )?;
+            }
+
+            if let Some((x, y)) = to_gfx_point(self.view.to_screen(test.pos)) {
+                canvas.filled_circle(x, y, TEST_CHARGE_RADIUS, color)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Ends a drag, places the line charge started by `begin_line` or sets the Gaussian surface
+    // started by `begin_gauss`
+    fn handle_mouse_up(&mut self, x: i32, y: i32) {
+        self.dragging = None;
+
+        if let Some((grab, _, moved)) = self.legend_drag.take() {
+            if grab == LegendGrab::Middle && !moved {
+                self.toggle_heatmap_lock();
+            }
+            return;
+        }
+
+        if let Some(start) = self.gauss_start.take() {
+            self.set_gauss_rect(start, self.view.to_world((x as f64, y as f64)));
+            return;
+        }
+
+        if let Some(start) = self.ground_start.take() {
+            self.set_ground_plane(start, self.snap(self.view.to_world((x as f64, y as f64))));
+            return;
+        }
+
+        if let Some(start) = self.comb_start.take() {
+            let end = self.snap(self.view.to_world((x as f64, y as f64)));
+            if start != end {
+                self.push_history();
+                let lines = self.comb_lines.clamp(1, MAX_COMB_LINES);
+                self.scene.combs.push(Comb::new(start, end, lines));
+                log!("Added comb of {lines} lines from {start:?} to {end:?}");
+
+                self.model_changed();
+            }
+            return;
+        }
+
+        let Some(start) = self.line_start.take() else {
+            return;
+        };
+        let end = self.snap(self.view.to_world((x as f64, y as f64)));
+
+        if start == end {
+            return;
+        }
+
+        self.push_history();
+        self.scene
+            .lines
+            .push(LineCharge::new(start, end, self.current_selected_charge));
+        log!(
+            "Added line charge {} from {start:?} to {end:?}",
+            self.current_selected_charge
+        );
+
+        self.model_changed();
+    }
+
+    // The play area is the canvas minus the toolbar strip on the right
+    fn update_play_area(&mut self, canvas: &WindowCanvas) -> Result<(), String> {
+        let output_size = canvas.output_size()?;
+        self.play_area = (
+            (output_size.0 * 9 / 10) as f64,
+            get_toolbar_bottom(output_size) as f64,
+        );
+
+        Ok(())
+    }
+
+    // World space rectangle shown in the play area, grown by `margin` screen pixels on every
+    // side
+    fn get_visible_bounds(&self, margin: f64) -> Bounds {
+        (
+            self.view.to_world((-margin, -margin)),
+            self.view
+                .to_world((self.play_area.0 + margin, self.play_area.1 + margin)),
+        )
+    }
+
+    fn get_visible_equipotentials(&self, cell_size: f64) -> Vec<ContourSegment> {
+        get_visible_equipotentials(&self.scene, self.view, self.play_area, cell_size)
+    }
+
+    fn save_scene(&self) -> Result<(), Box<dyn Error>> {
+        fs::write(&self.scene_path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    fn load_scene(&mut self) -> Result<(), Box<dyn Error>> {
+        let loaded: Game = serde_json::from_str(&fs::read_to_string(&self.scene_path)?)?;
+
+        self.push_history();
+        self.scene.particles = loaded.scene.particles;
+        self.scene.lines = loaded.scene.lines;
+        self.scene.dipoles = loaded.scene.dipoles;
+        self.scene.combs = loaded.scene.combs;
+        self.scene.period = loaded.scene.period;
+        self.scene.velocities.clear();
+        self.restore_scale();
+        self.selected = None;
+        self.selected_dipole = None;
+        self.multi_selection.clear();
+        self.clear_measurement();
+        self.current_selected_charge = loaded.current_selected_charge;
+        self.heatmap_range = loaded.heatmap_range;
+        self.dirty = true;
+        self.model_changed();
+        self.mark_saved();
+
+        Ok(())
+    }
+
+    // The scene file matches the model as it is now
+    fn mark_saved(&mut self) {
+        self.saved_generation = self.generation;
+        self.consumers.title = None;
+    }
+
+    // Queues the field over the visible play area to be written to a timestamped CSV file
+    fn export_field_csv(&mut self) {
+        let path = PathBuf::from(
+            chrono::Local::now()
+                .format("field-%Y-%m-%dT%H-%M-%S.csv")
+                .to_string(),
+        );
+
+        self.queue_export(ExportJob::Csv {
+            path,
+            scene: self.scene.clone(),
+            bounds: self.get_visible_bounds(0.0),
+        });
+    }
+
+    // Queues the scene to be written to a timestamped SVG file
+    fn export_svg(&mut self) {
+        let path = PathBuf::from(
+            chrono::Local::now()
+                .format("scene-%Y-%m-%dT%H-%M-%S.svg")
+                .to_string(),
+        );
+
+        self.queue_export(ExportJob::Svg {
+            path,
+            snapshot: self.get_svg_snapshot(),
+        });
+    }
+
+    fn get_svg_snapshot(&self) -> SvgSnapshot {
+        SvgSnapshot {
+            scene: self.scene.clone(),
+            view: self.view,
+            theme: self.theme,
+            play_area: self.play_area,
+            field_lines: self.field_lines.clone(),
+            color_lines_by_source: self.color_lines_by_source,
+            show_equipotentials: self.show_equipotentials,
+        }
+    }
+
+    fn queue_export(&mut self, job: ExportJob) {
+        if let Err(e) = self.exports.queue(job) {
+            self.notify_error(e);
+        }
+    }
+
+    // Shows how the exports that finished since the last frame went
+    fn report_exports(&mut self) {
+        for result in self.exports.finished() {
+            match result {
+                Ok(text) => self.notify(text),
+                Err(text) => self.notify_error(text),
+            }
+        }
+    }
+
+    // Replaces the scene with `preset` laid out over the visible play area
+    fn load_preset(&mut self, preset: Preset) {
+        self.push_history();
+        self.scene.particles = preset
+            .particles(self.get_visible_bounds(0.0))
+            .into_iter()
+            .map(|particle| {
+                let (x, y) = self.snap(particle.pos());
+                ChargedParticle { x, y, ..particle }
+            })
+            .collect();
+        self.scene.lines.clear();
+        self.scene.dipoles.clear();
+        self.scene.combs.clear();
+        self.scene.velocities.clear();
+        self.restore_scale();
+        if let Preset::Hydrogen = preset {
+            self.replaced_scale = Some((self.scene.meters_per_pixel, self.simulating));
+            let period = self.scene.start_bohr_orbit();
+            self.scene.time_scale = period / HYDROGEN_ORBIT_SECONDS;
+            self.simulating = true;
+            self.notify(CLASSICAL_NOTE.into());
+        }
+        self.selected = None;
+        self.selected_dipole = None;
+        self.multi_selection.clear();
+        self.clear_measurement();
+        log!("Loaded {preset:?} preset");
+
+        self.model_changed();
+    }
+
+    // Goes back to the configured scale after the hydrogen preset, and stops the dynamics it
+    // started if they weren't running before
+    fn restore_scale(&mut self) {
+        if let Some((meters_per_pixel, simulating)) = self.replaced_scale.take() {
+            let defaults = Scene::default();
+            self.scene.meters_per_pixel = meters_per_pixel;
+            self.scene.particle_mass = defaults.particle_mass;
+            self.scene.time_scale = defaults.time_scale;
+            self.scene.max_timestep = defaults.max_timestep;
+            self.simulating = simulating;
+            if self
+                .notice
+                .as_ref()
+                .is_some_and(|notice| notice.text == CLASSICAL_NOTE)
+            {
+                self.notice = None;
+            }
+        }
+    }
+
+    // Adds one step of uniform field pointing along `direction` to the external field
+    fn step_external_field(&mut self, direction: (f64, f64)) {
+        let distance = EXTERNAL_FIELD_STEP_DISTANCE * self.scene.meters_per_pixel;
+        let step = COULUMBS_CONST * CHARGE_STEP / (distance * distance);
+
+        let field = &mut self.scene.external_field;
+        field.0 += direction.0 * step;
+        field.1 += direction.1 * step;
+        log!("External field: ({:.2e}, {:.2e}) N/C", field.0, field.1);
+
+        self.model_changed();
+    }
+
+    // Tells everything derived from the model that it changed. Every change to the charges,
+    // the external field, the ground plane or the boundaries goes through here. The field tree
+    // is dropped right away, so field queries made before the next refresh sum the charges
+    // directly instead of seeing the old ones.
+    fn model_changed(&mut self) {
+        self.generation += 1;
+        self.scene.field_tree = None;
+    }
+
+    // Recomputes whatever's derived from the model and behind it, returning the names of those
+    // that were. The scene texture is redrawn separately by draw_frame.
+    fn refresh_model_consumers(&mut self) -> Vec<&'static str> {
+        let mut refreshed = Vec::new();
+
+        if take_stale(&mut self.consumers.field_tree, self.generation) {
+            self.scene.rebuild_field_tree();
+            refreshed.push("field tree");
+        }
+        if take_stale(&mut self.consumers.moments, self.generation) {
+            self.moments = self.scene.get_multipole_moments();
+            refreshed.push("moments");
+        }
+        if take_stale(&mut self.consumers.title, self.generation) {
+            let unsaved = if self.generation == self.saved_generation {
+                ""
+            } else {
+                "*"
+            };
+            self.title = format!(
+                "Electric Field Visualizer - {}{unsaved}",
+                self.scene_path.display()
+            );
+            refreshed.push("title");
+        }
+
+        refreshed
+    }
+
+    // Keeps the charges as they are before an edit, so Ctrl+Z can put them back
+    fn push_history(&mut self) {
+        if self.history.len() == MAX_UNDO_STEPS {
+            self.history.remove(0);
+        }
+
+        self.history.push(Snapshot {
+            particles: self.scene.particles.clone(),
+            lines: self.scene.lines.clone(),
+            dipoles: self.scene.dipoles.clone(),
+            combs: self.scene.combs.clone(),
+            atomic_scale: self.replaced_scale.is_some(),
+        });
+    }
+
+    // Puts the charges back the way they were before the last edit, returning whether there
+    // was one. Velocities are dropped, the same as loading a scene.
+    fn undo(&mut self) -> bool {
+        let Some(snapshot) = self.history.pop() else {
+            return false;
+        };
+
+        self.scene.particles = snapshot.particles;
+        self.scene.lines = snapshot.lines;
+        self.scene.dipoles = snapshot.dipoles;
+        self.scene.combs = snapshot.combs;
+        self.scene.velocities.clear();
+        if !snapshot.atomic_scale {
+            self.restore_scale();
+        }
+        self.selected = None;
+        self.selected_dipole = None;
+        self.multi_selection.clear();
+        self.dragging = None;
+        self.last_click = None;
+        self.clear_measurement();
+
+        self.model_changed();
+        true
+    }
+
+    // Adds the charges in `imported` to the scene, moved by `offset`, as one undoable edit. An
+    // imported particle that lands on one already there merges into it like placing it would,
+    // and the number of particles and line charges added is returned.
+    fn merge_charges(&mut self, imported: Snapshot, offset: (f64, f64)) -> usize {
+        self.push_history();
+
+        let count = imported.particles.len() + imported.lines.len() + imported.dipoles.len();
+        // They stay pinned, but get hues of their own
+        for particle in imported.particles {
+            self.place_particle(ChargedParticle {
+                x: particle.x + offset.0,
+                y: particle.y + offset.1,
+                hue: None,
+                ..particle
+            });
+        }
+        for mut line in imported.lines {
+            line.translate(offset);
+            self.scene.lines.push(line);
+        }
+        for mut dipole in imported.dipoles {
+            dipole.translate(offset);
+            self.scene.dipoles.push(dipole);
+        }
+        // Combs come along, but aren't charges
+        for mut comb in imported.combs {
+            comb.translate(offset);
+            self.scene.combs.push(comb);
+        }
+        log!("Merged in {count} charges offset by {offset:?}");
+
+        self.model_changed();
+        count
+    }
+
+    // Merges the scene at `merge_path` with the middle of its charges at the screen position
+    // (`x`, `y`). The offset is snapped, so charges on the grid stay on it.
+    fn merge_scene_at(&mut self, x: i32, y: i32) -> Result<usize, Box<dyn Error>> {
+        let imported = Snapshot::read(&self.merge_path)?;
+        let Some(center) = imported.get_center() else {
+            return Ok(0);
+        };
+
+        let cursor = self.view.to_world((x as f64, y as f64));
+        let offset = self.snap((cursor.0 - center.0, cursor.1 - center.1));
+
+        Ok(self.merge_charges(imported, offset))
+    }
+
+    // Removes every charge and resets the placement charge, for starting over
+    fn clear_scene(&mut self) {
+        self.push_history();
+        self.scene.particles.clear();
+        self.scene.lines.clear();
+        self.scene.dipoles.clear();
+        self.scene.combs.clear();
+        self.scene.velocities.clear();
+        self.restore_scale();
+        self.selected = None;
+        self.selected_dipole = None;
+        self.multi_selection.clear();
+        self.gauss_rect = None;
+        self.clear_measurement();
+        self.current_selected_charge = 0.0;
+        log!("Cleared the scene");
+
+        self.model_changed();
+    }
+
+    // Moves the view by a mouse drag of (`x_rel`, `y_rel`) screen pixels
+    fn handle_pan(&mut self, x_rel: i32, y_rel: i32) {
+        self.view.offset.0 -= x_rel as f64 / self.view.scale;
+        self.view.offset.1 -= y_rel as f64 / self.view.scale;
+
+        self.dirty = true;
+    }
+
+    fn handle_zoom(&mut self, x: i32, y: i32, scroll: i32) {
+        self.view
+            .zoom_at((x as f64, y as f64), ZOOM_STEP.powi(scroll));
+
+        self.dirty = true;
+    }
+
+    // Scrolling over a particle steps its charge, Shift steps faster. Anywhere else in the play
+    // area it zooms.
+    fn handle_scroll(&mut self, x: i32, y: i32, scroll: i32, keymod: Mod) {
+        let Some(index) = self.get_particle_at(x, y) else {
+            match self.get_comb_hit_at(self.view.to_world((x as f64, y as f64))) {
+                Some(Hit::Comb(index) | Hit::CombEnd(index, _)) => {
+                    self.change_comb_lines(index, scroll)
+                }
+                _ => self.handle_zoom(x, y, scroll),
+            }
+            return;
+        };
+
+        let step = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+            FAST_CHARGE_STEPS * CHARGE_STEP
+        } else {
+            CHARGE_STEP
+        };
+
+        self.change_particle_charge(index, scroll as f64 * step);
+    }
+
+    // Seeds `delta` more lines along a comb, keeping at least one
+    fn change_comb_lines(&mut self, index: usize, delta: i32) {
+        self.push_history();
+        let comb = &mut self.scene.combs[index];
+        comb.lines = (comb.lines as i64 + delta as i64).clamp(1, MAX_COMB_LINES as i64) as usize;
+        log!("Comb lines: {}", comb.lines);
+
+        self.model_changed();
+    }
+
+    // Builds up the typed charge from digits, minus signs, periods and Backspace. Enter sets it
+    // on the selected particle, or the placement charge without one, and Escape cancels.
+    fn handle_charge_entry_keydown(&mut self, keycode: Keycode) {
+        let Some(entry) = self.charge_entry.as_mut() else {
+            return;
+        };
+
+        match keycode {
+            Keycode::Backspace => {
+                entry.pop();
+                log!("Charge entry: {entry}");
+            }
+            Keycode::Return | Keycode::KpEnter => {
+                let entry = self.charge_entry.take().unwrap_or_default();
+                self.commit_charge_entry(&entry);
+            }
+            Keycode::Escape => {
+                self.charge_entry = None;
+                log!("Cancelled charge entry");
+            }
+            _ => {
+                if let Some(typed) = charge_entry_char(keycode) {
+                    entry.push(typed);
+                    log!("Charge entry: {entry}");
+                }
+            }
+        }
+    }
+
+    fn commit_charge_entry(&mut self, entry: &str) {
+        if let Some(index) = self.selected_dipole {
+            self.commit_dipole_entry(index, entry);
+            return;
+        }
+
+        let charge = match entry.parse::<f64>() {
+            Ok(elementary) if elementary.is_finite() => elementary * CHARGE_STEP,
+            _ => {
+                self.notify_error(format!(
+                    "Invalid charge `{entry}`, expected a number of e like 7 or -0.5"
+                ));
+                return;
+            }
+        };
+
+        match self.selected {
+            Some(index) => {
+                let delta = charge - self.scene.particles[index].charge;
+                self.change_particle_charge(index, delta);
+            }
+            None => {
+                self.current_selected_charge = if charge.abs() < CHARGE_SNAP {
+                    0.0
+                } else {
+                    charge
+                };
+                log!(
+                    "Placement charge: {}",
+                    format_charge(self.current_selected_charge)
+                );
+            }
+        }
+    }
+
+    // Sets the charge in e, separation in m and angle in degrees of a dipole from the
+    // comma-separated numbers in `entry`. Numbers that are left out or empty keep their value,
+    // so "2" only sets the charge and ",,90" only the angle.
+    fn commit_dipole_entry(&mut self, index: usize, entry: &str) {
+        let dipole = self.scene.dipoles[index];
+        let meters_per_pixel = self.scene.meters_per_pixel;
+        let current = [
+            dipole.charge / CHARGE_STEP,
+            dipole.separation * meters_per_pixel,
+            dipole.angle.to_degrees(),
+        ];
+
+        let fields: Vec<&str> = entry.split(',').map(str::trim).collect();
+        let mut values = current;
+        let mut valid = fields.len() <= values.len();
+        for (field, value) in fields.iter().zip(values.iter_mut()) {
+            match field.parse::<f64>() {
+                _ if field.is_empty() => {}
+                Ok(parsed) if parsed.is_finite() => *value = parsed,
+                _ => valid = false,
+            }
+        }
+
+        let [charge, separation, angle] = values;
+        if !valid || charge <= 0.0 || separation <= 0.0 {
+            self.notify_error(format!(
+                "Invalid dipole `{entry}`, expected a positive charge in e, separation in m and \
+                 angle in degrees like 2,0.4,90"
+            ));
+            return;
+        }
+
+        self.push_history();
+        self.scene.dipoles[index] = Dipole::new(
+            dipole.center,
+            charge * CHARGE_STEP,
+            separation / meters_per_pixel,
+            angle.to_radians(),
+        );
+        log!("Dipole: {charge} e, {separation} m, {angle} degrees");
+
+        self.model_changed();
+    }
+
+    // Opens the batch edit table on the multi-selection, in the order it was picked
+    fn open_batch_edit(&mut self) {
+        self.batch_edit = Some(BatchEdit::new(
+            &self.scene.particles,
+            self.multi_selection.clone(),
+        ));
+        log!("Batch editing {} particles", self.multi_selection.len());
+    }
+
+    // Tab and Shift+Tab or the arrow keys move between cells, and digits, minus signs, periods
+    // and + edit them. Enter goes down a row, or fills the column from a header cell.
+    // Ctrl+Enter commits the table and Escape cancels it.
+    fn handle_batch_edit_keydown(&mut self, keycode: Keycode, keymod: Mod) {
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+        let Some(edit) = self.batch_edit.as_mut() else {
+            return;
+        };
+        let columns = BATCH_EDIT_COLUMNS.len() as isize;
+
+        match keycode {
+            Keycode::Escape => {
+                self.batch_edit = None;
+                log!("Cancelled batch edit");
+            }
+            Keycode::Return | Keycode::KpEnter if ctrl => self.commit_batch_edit(),
+            Keycode::Return | Keycode::KpEnter if edit.cursor.0 == 0 => {
+                let column = edit.cursor.1;
+                if !edit.apply_header(column) {
+                    self.notify_error(format!(
+                        "Invalid column edit for {}, expected a number to set or + and a number \
+                         to add",
+                        BATCH_EDIT_COLUMNS[column]
+                    ));
+                }
+            }
+            Keycode::Return | Keycode::KpEnter | Keycode::Down => edit.move_by(columns),
+            Keycode::Up => edit.move_by(-columns),
+            Keycode::Tab if shift => edit.move_by(-1),
+            Keycode::Tab | Keycode::Right => edit.move_by(1),
+            Keycode::Left => edit.move_by(-1),
+            Keycode::Backspace => edit.backspace(),
+            Keycode::Equals if shift => edit.type_char('+'),
+            Keycode::KpPlus => edit.type_char('+'),
+            _ => {
+                if let Some(typed) = charge_entry_char(keycode).filter(|typed| *typed != ',') {
+                    edit.type_char(typed);
+                }
+            }
+        }
+    }
+
+    // Writes the table to the particles as one undo step. Nothing is written while any cell
+    // is invalid, those are highlighted so they can be fixed.
+    fn commit_batch_edit(&mut self) {
+        let Some(edit) = self.batch_edit.as_ref() else {
+            return;
+        };
+        let Some(changes) = edit.changes(&self.scene.particles) else {
+            let invalid = edit.invalid_cells();
+            self.notify_error(format!(
+                "{invalid} highlighted cell{} must be fixed before committing",
+                if invalid == 1 { "" } else { "s" }
+            ));
+            return;
+        };
+        self.batch_edit = None;
+
+        if changes.is_empty() {
+            log!("Batch edit changed nothing");
+            return;
+        }
+
+        self.push_history();
+        for &(index, particle) in changes.iter() {
+            // Moved particles are stopped, the same as dragging them
+            if particle.pos() != self.scene.particles[index].pos() {
+                if let Some(velocity) = self.scene.velocities.get_mut(index) {
+                    *velocity = (0.0, 0.0);
+                }
+            }
+            self.scene.particles[index] = particle;
+        }
+        log!("Batch edited {} particles", changes.len());
+
+        self.model_changed();
+    }
+
+    // The charge keys and Delete act on the selected particle while there is one
+    fn handle_selection_keydown(&mut self, index: usize, keycode: Keycode, keymod: Mod) {
+        let step = charge_key_step(keymod);
+
+        match keycode {
+            Keycode::Equals => self.change_particle_charge(index, step),
+            Keycode::Minus => self.change_particle_charge(index, -step),
+            Keycode::N => {
+                let charge = self.scene.particles[index].charge;
+                self.change_particle_charge(index, -charge);
+            }
+            Keycode::Delete => {
+                self.push_history();
+                self.remove_particle(index);
+                self.model_changed();
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_keydown(&mut self, keycode: Keycode, keymod: Mod) {
+        let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+
+        if let Some(index) = self.selected {
+            if matches!(
+                keycode,
+                Keycode::Equals | Keycode::Minus | Keycode::N | Keycode::Delete
+            ) {
+                self.handle_selection_keydown(index, keycode, keymod);
+                return;
+            }
+        }
+
+        match keycode {
+            Keycode::S if ctrl && shift => self.export_svg(),
+            Keycode::S if ctrl => match self.save_scene() {
+                Ok(()) => {
+                    self.mark_saved();
+                    self.notify(format!("Saved scene to {}", self.scene_path.display()));
+                }
+                Err(e) => self.notify_error(format!(
+                    "Failed to save scene to {}: {e}",
+                    self.scene_path.display()
+                )),
+            },
+            Keycode::O if ctrl => match self.load_scene() {
+                Ok(()) => self.notify(format!("Loaded scene from {}", self.scene_path.display())),
+                Err(e) => self.notify_error(format!(
+                    "Failed to load scene from {}: {e}",
+                    self.scene_path.display()
+                )),
+            },
+            // Needs both modifiers, it's too easy to wipe the scene by accident otherwise
+            Keycode::C if ctrl && shift => self.clear_scene(),
+            Keycode::Z if ctrl => {
+                if self.undo() {
+                    log!("Undid the last change, {} more left", self.history.len());
+                } else {
+                    self.notify("Nothing to undo".into());
+                }
+            }
+            Keycode::C if ctrl => self.copy_selection(),
+            Keycode::C => {
+                self.charge_entry = Some(String::new());
+                log!("Type a charge in e and press Enter, or Escape to cancel");
+            }
+            Keycode::Delete => {
+                if let Some(index) = self.selected_dipole {
+                    self.push_history();
+                    self.remove_dipole(index);
+                    self.model_changed();
+                }
+            }
+            // The clipboard is kept after pasting, so it can be pasted again
+            Keycode::V if ctrl => {
+                self.pasting = !self.clipboard.is_empty();
+
+                if self.pasting {
+                    log!("Click to paste {} particles", self.clipboard.len());
+                }
+            }
+            Keycode::F1 => self.load_preset(Preset::Dipole),
+            Keycode::F2 => self.load_preset(Preset::LinearQuadrupole),
+            Keycode::F3 => self.load_preset(Preset::ParallelPlates),
+            Keycode::F4 => self.load_preset(Preset::Ring),
+            Keycode::F5 => self.load_preset(Preset::Hydrogen),
+            Keycode::E if ctrl => self.export_field_csv(),
+            Keycode::L => {
+                self.color_lines_by_source = !self.color_lines_by_source;
+                log!("Coloring lines by source: {}", self.color_lines_by_source);
+
+                self.dirty = true;
+            }
+            Keycode::V => {
+                self.show_arrow_grid = !self.show_arrow_grid;
+
+                self.dirty = true;
+            }
+            Keycode::F => {
+                self.show_forces = !self.show_forces;
+
+                self.dirty = true;
+            }
+            // Redrawing re-traces the lines, which starts the animation over
+            Keycode::A => {
+                self.animate_lines = !self.animate_lines;
+                log!("Animated field lines: {}", self.animate_lines);
+
+                self.dirty = true;
+            }
+            // Pausing keeps the velocities, so resuming carries on exactly where it stopped
+            Keycode::Space => {
+                self.simulating = !self.simulating;
+                log!("Dynamics: {}", self.simulating);
+            }
+            Keycode::M => {
+                self.show_heatmap = !self.show_heatmap;
+
+                self.dirty = true;
+            }
+            Keycode::E if !self.multi_selection.is_empty() => self.open_batch_edit(),
+            Keycode::E => {
+                self.show_equipotentials = !self.show_equipotentials;
+
+                self.dirty = true;
+            }
+            // Shift+S leaves the toolbar out of the screenshot. It's taken once the next frame
+            // has been drawn.
+            Keycode::S => {
+                self.pending_screenshot = Some(shift);
+            }
+            // The keys change the magnitude of the selected sign, or pick a sign if the charge
+            // is neutral. Stepping the magnitude down stops at zero rather than flipping the sign
+            Keycode::Equals => {
+                let step = charge_key_step(keymod);
+
+                if self.current_selected_charge == 0.0 {
+                    self.current_selected_charge = step;
+                } else {
+                    self.current_selected_charge += self.current_selected_charge.signum() * step;
+                }
+            }
+            Keycode::Minus => {
+                let step = charge_key_step(keymod);
+
+                if self.current_selected_charge == 0.0 {
+                    self.current_selected_charge = -step;
+                } else if self.current_selected_charge.abs() <= step + CHARGE_SNAP {
+                    self.current_selected_charge = 0.0;
+                } else {
+                    self.current_selected_charge -= self.current_selected_charge.signum() * step;
+                }
+            }
+            Keycode::N => {
+                self.current_selected_charge = 0.0;
+            }
+            Keycode::Left => self.step_external_field((-1.0, 0.0)),
+            Keycode::Right => self.step_external_field((1.0, 0.0)),
+            Keycode::Up => self.step_external_field((0.0, -1.0)),
+            Keycode::Down => self.step_external_field((0.0, 1.0)),
+            Keycode::Num0 | Keycode::Kp0 => {
+                self.scene.external_field = (0.0, 0.0);
+                log!("External field off");
+
+                self.model_changed();
+            }
+            Keycode::G => {
+                self.snap_to_grid = !self.snap_to_grid;
+                log!("Snap to grid: {}", self.snap_to_grid);
+
+                self.dirty = true;
+            }
+            Keycode::Escape => {
+                if self.gauss_rect.take().is_some()
+                    | self.selected.take().is_some()
+                    | self.selected_dipole.take().is_some()
+                    | self.clear_measurement()
+                {
+                    self.dirty = true;
+                }
+
+                self.pasting = false;
+
+                self.clear_test_charges();
+            }
+            // Pressing P again before clicking clears the test charges instead
+            Keycode::P => {
+                if self.placing_test_charge {
+                    self.clear_test_charges();
+                } else {
+                    self.placing_test_charge = true;
+                    log!("Click to drop a test charge");
+                }
+            }
+            // Without a font the bindings can't be drawn, so they're printed instead
+            Keycode::H => {
+                self.show_help = !self.show_help;
+
+                if self.show_help && self.font.is_none() {
+                    for (key, description) in KEYBINDINGS {
+                        say!("{key:>16}  {description}");
+                    }
+                }
+            }
+            Keycode::K => {
+                self.theme = self.color_overrides.apply(self.theme.colorblind_toggled());
+                log!("Colorblind palette: {}", self.theme.colorblind);
+
+                self.dirty = true;
+            }
+            Keycode::T => {
+                self.theme = self.color_overrides.apply(self.theme.toggled());
+                self.dirty = true;
+            }
+            Keycode::B => {
+                self.scene.periodic = !self.scene.periodic;
+                log!("Periodic boundaries: {}", self.scene.periodic);
+
+                self.model_changed();
+            }
+            Keycode::X if shift => {
+                self.mirror_antisymmetric = !self.mirror_antisymmetric;
+                log!("Anti-symmetric mirroring: {}", self.mirror_antisymmetric);
+            }
+            Keycode::X => {
+                self.mirror = match self.mirror {
+                    None => Some(MirrorAxes::Vertical),
+                    Some(MirrorAxes::Vertical) => Some(MirrorAxes::Horizontal),
+                    Some(MirrorAxes::Horizontal) => Some(MirrorAxes::Both),
+                    Some(MirrorAxes::Both) => None,
+                };
+                log!("Mirror axes: {:?}", self.mirror);
+
+                self.dirty = true;
+            }
+            Keycode::O if shift => {
+                self.show_multipole_error = !self.show_multipole_error;
+                log!("Multipole error heatmap: {}", self.show_multipole_error);
+
+                self.dirty = true;
+            }
+            Keycode::O => {
+                self.multipole_order = match self.multipole_order {
+                    None => Some(0),
+                    Some(order) if order < 2 => Some(order + 1),
+                    Some(_) => None,
+                };
+                log!("Multipole comparison order: {:?}", self.multipole_order);
+
+                self.dirty = true;
+            }
+            // Without a font the moments can't be drawn either, so they're printed instead
+            Keycode::I => {
+                self.show_moments = !self.show_moments;
+                if self.show_moments && self.font.is_none() {
+                    self.refresh_model_consumers();
+                    say!("{}", self.get_moment_lines().join(", "));
+                }
+
+                self.dirty = true;
+            }
+            Keycode::LAlt | Keycode::RAlt => self.predicting = true,
+            _ => {}
+        }
+
+        if self.current_selected_charge.abs() < CHARGE_SNAP {
+            self.current_selected_charge = 0.0;
+        }
+
+        log!("Current charge: {}", self.current_selected_charge);
+    }
+
+    // Makes the placement charge match the sign picked in the toolbar, keeping its magnitude
+    fn set_selected_particle(&mut self, particle: Particle) {
+        let magnitude = if self.current_selected_charge == 0.0 {
+            CHARGE_STEP
+        } else {
+            self.current_selected_charge.abs()
+        };
+
+        self.current_selected_charge = match particle {
+            Particle::Positive => magnitude,
+            Particle::Negative => -magnitude,
+            Particle::Neutral => 0.0,
+        };
+
+        log!("Current charge: {}", self.current_selected_charge);
+    }
+
+    fn handle_keyup(&mut self, keycode: Keycode) {
+        if matches!(keycode, Keycode::LAlt | Keycode::RAlt) {
+            self.predicting = false;
+        }
+    }
+
+    // Integrates the trajectory preview again if the charges changed or another particle was
+    // selected since the last one, or drops it when there's nothing to preview
+    fn refresh_prediction(&mut self) {
+        let Some(index) = self.selected.filter(|_| self.predicting) else {
+            self.prediction = None;
+            return;
+        };
+
+        if let Some(prediction) = &self.prediction {
+            if (prediction.generation, prediction.index) == (self.generation, index) {
+                return;
+            }
+        }
+
+        let steps =
+            ((self.prediction_horizon / PREDICTION_STEP).ceil() as usize).min(MAX_PREDICTION_STEPS);
+        let dt = PREDICTION_STEP * self.scene.time_scale;
+        let path = self.scene.predict_trajectory(index, dt, steps);
+        self.prediction = Some(Prediction {
+            generation: self.generation,
+            index,
+            path,
+        });
+    }
+
+    // Draws the trajectory preview as a dot every step, with a tick across it every
+    // `prediction_tick` seconds. The dots are evenly spaced in time, so they bunch up where the
+    // particle is slow.
+    fn draw_prediction(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let Some(prediction) = &self.prediction else {
+            return Ok(());
+        };
+        let size = canvas.output_size()?;
+        let tick_steps = ((self.prediction_tick / PREDICTION_STEP).round() as usize).max(1);
+        let points: Vec<(f64, f64)> = prediction
+            .path
+            .iter()
+            .map(|pos| self.view.to_screen(*pos))
+            .collect();
+
+        for (i, point) in points.iter().enumerate().skip(1) {
+            if let Some((x, y)) = to_gfx_point(*point) {
+                canvas.pixel(x, y, PREDICTION_COLOR)?;
+            }
+
+            if !i.is_multiple_of(tick_steps) {
+                continue;
+            }
+
+            // Across the direction of motion, taken from the neighbouring samples
+            let next = points.get(i + 1).unwrap_or(point);
+            let previous = points[i - 1];
+            let (dx, dy) = (next.0 - previous.0, next.1 - previous.1);
+            let length = dx.hypot(dy);
+            if length == 0.0 {
+                continue;
+            }
+            let normal = (
+                -dy / length * PREDICTION_TICK_SIZE,
+                dx / length * PREDICTION_TICK_SIZE,
+            );
+            let start = (point.0 - normal.0, point.1 - normal.1);
+            let end = (point.0 + normal.0, point.1 + normal.1);
+
+            if let Some((start, end)) = clip_segment(start, end, size) {
+                canvas.aa_line(start.0, start.1, end.0, end.1, PREDICTION_COLOR)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Draws a faint dot at every grid intersection in the play area, unless they'd be so
+    // close together they blur into a solid color
+    fn draw_grid<T: SceneTarget>(&self, canvas: &mut Canvas<T>) -> Result<(), String> {
+        let pitch = GRID_PITCH * self.view.scale;
+        if pitch < MIN_GRID_DOT_SPACING {
+            return Ok(());
+        }
+
+        let ((left, top), (right, bottom)) = self.get_visible_bounds(0.0);
+        let points: Vec<Point> = ((top / GRID_PITCH).ceil() as i64..=(bottom / GRID_PITCH) as i64)
+            .flat_map(|j| {
+                ((left / GRID_PITCH).ceil() as i64..=(right / GRID_PITCH) as i64)
+                    .map(move |i| (i as f64 * GRID_PITCH, j as f64 * GRID_PITCH))
+            })
+            .map(|pos| self.view.to_screen_point(pos))
+            .filter(|point| (point.x as f64) < self.play_area.0)
+            .collect();
+
+        canvas.set_draw_color(Color::RGB(70, 70, 70));
+        canvas.draw_points(&points[..])?;
+
+        Ok(())
+    }
+
+    // Draws an arrow along the field at the cursor. Its magnitude and the potential there are
+    // read out in the status bar.
+    fn draw_probe(&self, canvas: &mut WindowCanvas, x: i32, y: i32) -> Result<(), String> {
+        let (world_x, world_y) = self.view.to_world((x as f64, y as f64));
+        let (x_comp, y_comp) = self.scene.get_field_strength(world_x, world_y);
+        let magnitude = x_comp.hypot(y_comp);
+
+        canvas.set_draw_color(Color::CYAN);
+
+        if magnitude > 0.0 && magnitude.is_finite() {
+            let angle = y_comp.atan2(x_comp);
+            let tip = Point::new(
+                x + (PROBE_ARROW_LENGTH * angle.cos()) as i32,
+                y + (PROBE_ARROW_LENGTH * angle.sin()) as i32,
+            );
+
+            canvas.draw_line(Point::new(x, y), tip)?;
+
+            for side in [-1.0, 1.0] {
+                let head_angle = angle + side * 5.0 * std::f64::consts::PI / 6.0;
+
+                canvas.draw_line(
+                    tip,
+                    Point::new(
+                        tip.x + (0.3 * PROBE_ARROW_LENGTH * head_angle.cos()) as i32,
+                        tip.y + (0.3 * PROBE_ARROW_LENGTH * head_angle.sin()) as i32,
+                    ),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Draws the selected tool, the placement charge and, with the cursor over the play area,
+    // its position and the field and potential there along the bottom of the window
+    fn draw_status_bar(
+        &self,
+        canvas: &mut WindowCanvas,
+        tool: Option<Tool>,
+        probe: Option<(i32, i32)>,
+    ) -> Result<(), String> {
+        let output_size = canvas.output_size()?;
+        let top = get_toolbar_bottom(output_size) as i32;
+
+        canvas.set_draw_color(self.theme.toolbar);
+        canvas.fill_rect(Rect::new(0, top, output_size.0, STATUS_BAR_HEIGHT))?;
+
+        let Some(font) = self.font.as_ref() else {
+            return Ok(());
+        };
+
+        if let Some(notice) = self
+            .notice
+            .as_ref()
+            .filter(|notice| notice.posted.elapsed() < NOTICE_DURATION)
+        {
+            let color = if notice.error {
+                NOTICE_ERROR_COLOR
+            } else {
+                self.theme.text
+            };
+
+            return draw_text(
+                canvas,
+                font,
+                &notice.text,
+                STATUS_BAR_PADDING,
+                top + (STATUS_BAR_HEIGHT as i32 - font.height()) / 2,
+                color,
+            );
+        }
+
+        let mut status = match self.charge_entry.as_ref() {
+            _ if self.batch_edit.is_some() => format!(
+                "{} | Batch edit: Tab for the next cell, Enter for the next row or to fill a \
+                 column from the top row, Ctrl+Enter to commit, Escape to cancel",
+                tool.map_or("No tool", Tool::name)
+            ),
+            Some(entry) if self.selected_dipole.is_some() => format!(
+                "{} | Dipole: {entry}_ (charge in e, separation in m, angle in degrees, Enter to \
+                 set)",
+                tool.map_or("No tool", Tool::name)
+            ),
+            Some(entry) => format!(
+                "{} | Charge: {entry}_ e (Enter to set, Escape to cancel)",
+                tool.map_or("No tool", Tool::name)
+            ),
+            None => format!(
+                "{} | Charge {}",
+                tool.map_or("No tool", Tool::name),
+                format_charge(self.current_selected_charge)
+            ),
+        };
+
+        if let Some((x, y)) = self.get_placement_cursor(tool, probe) {
+            if let Err(reason) = self.check_placements(&self.get_placements(x, y)) {
+                status += &format!(" | Can't place here, it {reason}");
+            }
+        }
+
+        if let Some(axes) = self.mirror {
+            let kind = if self.mirror_antisymmetric {
+                " anti-symmetric"
+            } else {
+                ""
+            };
+            status += &format!(" | Mirror {axes:?}{kind}");
+        }
+
+        if self.scene.external_field != (0.0, 0.0) {
+            let (x_comp, y_comp) = self.scene.external_field;
+            status += &format!(" | E0 = ({x_comp:.2e}, {y_comp:.2e}) N/C");
+        }
+
+        if self.scene.time_scale != 1.0 {
+            status += &format!(" | 1 s = {}", format_si(self.scene.time_scale, "s"));
+        }
+
+        if self.exports.pending > 0 {
+            status += &format!(" | Writing {} export(s)", self.exports.pending);
+        }
+
+        // Only the external field is uniform, so that's the one the torque and energy are for
+        if let Some(dipole) = self.selected_dipole.map(|index| self.scene.dipoles[index]) {
+            let meters_per_pixel = self.scene.meters_per_pixel;
+            let (p_x, p_y) = dipole.moment(meters_per_pixel);
+            status += &format!(" | p = ({p_x:.2e}, {p_y:.2e}) C m");
+
+            let field = self.scene.external_field;
+            if field != (0.0, 0.0) {
+                status += &format!(
+                    " | \u{3c4} = {:.2e} N m | U = {:.2e} J",
+                    dipole.torque(field, meters_per_pixel),
+                    dipole.energy(field, meters_per_pixel)
+                );
+            }
+        }
+
+        if let Some((x, y)) = probe {
+            let (world_x, world_y) = self.view.to_world((x as f64, y as f64));
+            let (x_comp, y_comp) = self.scene.get_field_strength(world_x, world_y);
+            let meters_per_pixel = self.scene.meters_per_pixel;
+
+            status += &format!(
+                " | ({}, {}) | |E| = {:.2e} N/C | V = {:.2e} V",
+                format_si(world_x * meters_per_pixel, "m"),
+                format_si(world_y * meters_per_pixel, "m"),
+                x_comp.hypot(y_comp),
+                self.scene.get_potential(world_x, world_y)
+            );
+
+            if let Some(order) = self.multipole_order {
+                let (approx_x, approx_y) = self.scene.get_multipole_field(world_x, world_y, order);
+                status += &format!(
+                    " | Order {order} error = {:.2e} N/C",
+                    (approx_x - x_comp).hypot(approx_y - y_comp)
+                );
+            }
+        }
+
+        draw_text(
+            canvas,
+            font,
+            &status,
+            STATUS_BAR_PADDING,
+            top + (STATUS_BAR_HEIGHT as i32 - font.height()) / 2,
+            self.theme.text,
+        )
+    }
+
+    // Draws the keybindings on a translucent panel covering the play area
+    fn draw_help(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let Some(font) = self.font.as_ref() else {
+            return Ok(());
+        };
+
+        let background = self.theme.background;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(
+            background.r,
+            background.g,
+            background.b,
+            HELP_ALPHA,
+        ));
+        canvas.fill_rect(Rect::new(
+            0,
+            0,
+            self.play_area.0 as u32,
+            self.play_area.1 as u32,
+        ))?;
+        canvas.set_blend_mode(BlendMode::None);
+
+        let line_height = font.recommended_line_spacing();
+        let top = (self.play_area.1 as i32 - line_height * KEYBINDINGS.len() as i32).max(0) / 2;
+
+        for (i, (key, description)) in KEYBINDINGS.iter().enumerate() {
+            let y = top + i as i32 * line_height;
+
+            draw_text(canvas, font, key, 20, y, self.theme.text)?;
+            draw_text(
+                canvas,
+                font,
+                description,
+                20 + HELP_KEY_COLUMN_WIDTH,
+                y,
+                self.theme.text,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Keeps the note that the atomic scale's orbit is classical along the bottom of the play area
+    fn draw_scale_note(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let Some(font) = self.font.as_ref().filter(|_| self.replaced_scale.is_some()) else {
+            return Ok(());
+        };
+        let y = self.play_area.1 as i32 - font.recommended_line_spacing() - BATCH_EDIT_MARGIN;
+
+        draw_text(canvas, font, CLASSICAL_NOTE, 20, y, self.theme.text)
+    }
+
+    // Lists the problems found in the config file on a panel over the top of the play area
+    fn draw_config_problems(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let Some(font) = self.font.as_ref() else {
+            return Ok(());
+        };
+        let line_height = font.recommended_line_spacing();
+        let lines = std::iter::once("Problems in the config file:")
+            .chain(self.config_problems.iter().map(String::as_str))
+            .chain(std::iter::once("Click or press any key to dismiss"));
+        let count = self.config_problems.len() as i32 + 2;
+
+        let background = self.theme.background;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(
+            background.r,
+            background.g,
+            background.b,
+            HELP_ALPHA,
+        ));
+        canvas.fill_rect(Rect::new(
+            0,
+            0,
+            self.play_area.0 as u32,
+            (count * line_height + 2 * BATCH_EDIT_MARGIN) as u32,
+        ))?;
+        canvas.set_blend_mode(BlendMode::None);
+
+        for (i, line) in lines.enumerate() {
+            let color = if i == 0 || i as i32 == count - 1 {
+                self.theme.text
+            } else {
+                NOTICE_ERROR_COLOR
+            };
+            let y = BATCH_EDIT_MARGIN + i as i32 * line_height;
+            draw_text(canvas, font, line, 20, y, color)?;
+        }
+
+        Ok(())
+    }
+
+    // Draws the batch edit table over the top left of the play area. The cell being edited is
+    // outlined, and cells that don't hold a valid value are drawn in the error color.
+    fn draw_batch_edit(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        let Some(edit) = self.batch_edit.as_ref() else {
+            return Ok(());
+        };
+        let row_height = self.font.as_ref().map_or(BATCH_EDIT_ROW_HEIGHT, |font| {
+            font.recommended_line_spacing()
+        });
+        let columns = BATCH_EDIT_COLUMNS.len() as i32;
+        let rows = edit.rows.len() as i32 + 2;
+
+        let background = self.theme.background;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(
+            background.r,
+            background.g,
+            background.b,
+            HELP_ALPHA,
+        ));
+        canvas.fill_rect(Rect::new(
+            BATCH_EDIT_MARGIN,
+            BATCH_EDIT_MARGIN,
+            (columns * BATCH_EDIT_COLUMN_WIDTH) as u32,
+            (rows * row_height) as u32,
+        ))?;
+        canvas.set_blend_mode(BlendMode::None);
+
+        // The headings, then the header cells and one row per particle
+        let texts = std::iter::once(BATCH_EDIT_COLUMNS.map(String::from))
+            .chain(std::iter::once(edit.header.clone()))
+            .chain(edit.cells.iter().cloned());
+
+        for (i, row) in texts.enumerate() {
+            let y = BATCH_EDIT_MARGIN + i as i32 * row_height;
+
+            for (column, text) in row.iter().enumerate() {
+                let x = BATCH_EDIT_MARGIN + column as i32 * BATCH_EDIT_COLUMN_WIDTH;
+                // Row 0 of the table is its header, the first drawn after the headings
+                let cell = (i > 0).then(|| (i - 1, column));
+                let invalid = match cell {
+                    Some((row, column)) => row > 0 && !edit.is_valid(row - 1, column),
+                    None => false,
+                };
+                let color = if invalid {
+                    NOTICE_ERROR_COLOR
+                } else {
+                    self.theme.text
+                };
+
+                if cell == Some(edit.cursor) || invalid {
+                    canvas.set_draw_color(color);
+                    canvas.draw_rect(Rect::new(
+                        x,
+                        y,
+                        BATCH_EDIT_COLUMN_WIDTH as u32,
+                        row_height as u32,
+                    ))?;
+                }
+
+                if let Some(font) = self.font.as_ref() {
+                    draw_text(canvas, font, text, x + BATCH_EDIT_PADDING, y, color)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Traces the field lines over the visible area, kept until the next redraw
+    fn trace_visible_lines(&mut self) {
+        if self.color_lines_by_source {
+            self.assign_hues();
+        }
+
+        // Lines wrap around instead of leaving the tile in periodic mode
+        let line_bounds = if self.scene.periodic {
+            None
+        } else {
+            Some(self.get_visible_bounds(FIELD_LINE_MARGIN))
+        };
+
+        let trace_start = Instant::now();
+        self.field_lines = self
+            .scene
+            .trace_field_lines(self.scene.max_line_iters, line_bounds);
+        self.line_progress = 0;
+
+        if self.print_timing {
+            say!(
+                "Traced {} field lines in {:?}",
+                self.field_lines.len(),
+                trace_start.elapsed()
+            );
+        }
+    }
+
+    // Draws the edges of the periodic tiles dashed, every `period` from the world origin. Nothing
+    // is drawn once they're too close together to tell apart.
+    fn draw_period_seams<T: SceneTarget>(&self, canvas: &mut Canvas<T>) -> Result<(), String> {
+        let (width, height) = self.scene.period;
+        if width.min(height) * self.view.scale < MIN_GRID_DOT_SPACING * 4.0 {
+            return Ok(());
+        }
+
+        let ((left, top), (right, bottom)) = self.get_visible_bounds(0.0);
+        canvas.set_draw_color(self.theme.neutral);
+
+        for i in (left / width).ceil() as i64..=(right / width).floor() as i64 {
+            let x = self.view.to_screen((i as f64 * width, 0.0)).0;
+            draw_dashed_line(canvas, (x, 0.0), (x, self.play_area.1))?;
+        }
+        for j in (top / height).ceil() as i64..=(bottom / height).floor() as i64 {
+            let y = self.view.to_screen((0.0, j as f64 * height)).1;
+            draw_dashed_line(canvas, (0.0, y), (self.play_area.0, y))?;
+        }
+
+        Ok(())
+    }
+
+    // Draws each dipole as its two charges joined by a bar, labelled with its dipole moment.
+    // The selected one gets a ring around its handle, the positive charge.
+    fn draw_dipoles<T: SceneTarget>(&self, canvas: &mut Canvas<T>) -> Result<(), String> {
+        let size = canvas.output_size()?;
+
+        for (i, dipole) in self.scene.dipoles.iter().enumerate() {
+            let charges = dipole.point_charges();
+            let ends = charges.map(|charge| self.view.to_screen(charge.pos()));
+
+            if let Some((start, end)) = clip_segment(ends[0], ends[1], size) {
+                canvas.thick_line(start.0, start.1, end.0, end.1, 2, self.theme.neutral)?;
+            }
+
+            for (charge, end) in charges.iter().zip(ends) {
+                let Some((x, y)) = to_gfx_point(end) else {
+                    continue;
+                };
+                let radius = self.get_screen_radius(charge).round() as i32;
+
+                draw_particle(
+                    canvas,
+                    &self.theme,
+                    Particle::from_charge(charge.charge),
+                    x,
+                    y,
+                    radius,
+                )?;
+
+                if Some(i) == self.selected_dipole && charge.charge > 0.0 {
+                    canvas.aa_circle(
+                        x,
+                        y,
+                        (radius + SELECTION_RING_GAP) as i16,
+                        self.theme.text,
+                    )?;
+                }
+            }
+
+            if let (Some(font), Some((x, y))) = (
+                self.font.as_ref(),
+                to_gfx_point(self.view.to_screen(dipole.center)),
+            ) {
+                let (p_x, p_y) = dipole.moment(self.scene.meters_per_pixel);
+
+                draw_text(
+                    canvas,
+                    font,
+                    &format!("p = {:.2e} C m", p_x.hypot(p_y)),
+                    x as i32 + SELECTION_RING_GAP,
+                    y as i32 + SELECTION_RING_GAP,
+                    self.theme.text,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // The multipole moments about the centroid in SI units, one per line. Moments are computed
+    // from pixel positions, so they're converted to meters here.
+    fn get_moment_lines(&self) -> Vec<String> {
+        let moments = &self.moments;
+        let scale = self.scene.meters_per_pixel;
+
+        vec![
+            format!(
+                "Moments about ({:.3} m, {:.3} m)",
+                moments.centroid.0 * scale,
+                moments.centroid.1 * scale
+            ),
+            format!("Q = {:.2e} C", moments.total_charge),
+            format!(
+                "p = ({:.2e}, {:.2e}) C m",
+                moments.dipole.0 * scale,
+                moments.dipole.1 * scale
+            ),
+            format!(
+                "Qxx = {:.2e}, Qxy = {:.2e}, Qyy = {:.2e} C m\u{b2}",
+                moments.quadrupole[0][0] * scale * scale,
+                moments.quadrupole[0][1] * scale * scale,
+                moments.quadrupole[1][1] * scale * scale,
+            ),
+        ]
+    }
+
+    // Lists the multipole moments in the top left corner, along with the order of the expansion
+    // that's drawn if there is one
+    fn draw_moments<T: SceneTarget>(&self, canvas: &mut Canvas<T>) -> Result<(), String> {
+        let Some(font) = self.font.as_ref() else {
+            return Ok(());
+        };
+
+        let mut lines = self.get_moment_lines();
+        if let Some(order) = self.multipole_order {
+            lines.push(format!("Expansion to order {order} drawn in magenta"));
+        }
+
+        let (x, y) = MOMENTS_PANEL_POS;
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(
+                canvas,
+                font,
+                line,
+                x,
+                y + i as i32 * font.recommended_line_spacing(),
+                MULTIPOLE_ARROW_COLOR,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Draws the first `max_points` points of every traced field line, with arrowheads along the
+    // part that's drawn
+    fn draw_field_lines<T: SceneTarget>(
+        &self,
+        canvas: &mut Canvas<T>,
+        max_points: usize,
+    ) -> Result<(), String> {
+        let size = canvas.output_size()?;
+
+        for line in self.field_lines.iter() {
+            let color = get_line_color(&self.scene, &self.theme, self.color_lines_by_source, line);
+            let points: Vec<(f64, f64)> = line
+                .points
+                .iter()
+                .take(max_points)
+                .map(|pos| self.view.to_screen(*pos))
+                .collect();
+
+            for pair in points.windows(2) {
+                if let Some((start, end)) = clip_segment(pair[0], pair[1], size) {
+                    canvas.aa_line(start.0, start.1, end.0, end.1, color)?;
+                }
+            }
+
+            // Lines from negative charges are traced against the field, so arrowheads are
+            // flipped to match the field where they sit
+            for (pos, tangent) in get_arrowheads(&points, FIELD_ARROW_SPACING) {
+                let world_pos = self.view.to_world(pos);
+
+                if self.scene.particles.iter().any(|particle| {
+                    particle.distance_to(world_pos) <= self.scene.particle_radius(particle)
+                }) {
+                    continue;
+                }
+
+                let field = self.scene.get_field_strength(world_pos.0, world_pos.1);
+                let sign = if tangent.0 * field.0 + tangent.1 * field.1 < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                let (dx, dy) = (
+                    sign * FIELD_ARROW_SIZE * tangent.0,
+                    sign * FIELD_ARROW_SIZE * tangent.1,
+                );
+
+                let corners = (
+                    to_gfx_point((pos.0 + dx, pos.1 + dy)),
+                    to_gfx_point((pos.0 - dx - 0.6 * dy, pos.1 - dy + 0.6 * dx)),
+                    to_gfx_point((pos.0 - dx + 0.6 * dy, pos.1 - dy - 0.6 * dx)),
+                );
+                let (Some(tip), Some(left), Some(right)) = corners else {
+                    continue;
+                };
+
+                canvas.filled_trigon(tip.0, tip.1, left.0, left.1, right.0, right.1, color)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Gives every positive particle without a hue the one fewest others have, the first of
+    // those in the palette, so the first ten all differ. Particles keep their hues as others come
+    // and go.
+    fn assign_hues(&mut self) {
+        let mut counts = [0; LINE_PALETTE.len()];
+        for hue in self
+            .scene
+            .particles
+            .iter()
+            .filter_map(|particle| particle.hue)
+        {
+            counts[hue % LINE_PALETTE.len()] += 1;
+        }
+
+        for particle in self.scene.particles.iter_mut() {
+            if particle.charge <= 0.0 || particle.hue.is_some() {
+                continue;
+            }
+
+            let hue = (0..LINE_PALETTE.len())
+                .min_by_key(|&hue| counts[hue])
+                .unwrap_or_default();
+            counts[hue] += 1;
+            particle.hue = Some(hue);
+        }
+    }
+
+    // Draws the lines left out of the cached scene while animating
+    fn draw_growing_lines(&self, canvas: &mut WindowCanvas) -> Result<(), String> {
+        if !self.animate_lines {
+            return Ok(());
+        }
+
+        self.draw_field_lines(canvas, self.line_progress)
+    }
+
+    // Grows the animated lines by another frame's worth of points
+    fn grow_lines(&mut self) {
+        if self.animate_lines {
+            let longest = self
+                .field_lines
+                .iter()
+                .map(|line| line.points.len())
+                .max()
+                .unwrap_or(0);
+            self.line_progress = (self.line_progress + self.line_growth).min(longest);
+        }
+    }
+
+    fn on_update<T: SceneTarget>(&self, canvas: &mut Canvas<T>) -> Result<(), String> {
+        canvas.set_draw_color(self.theme.background);
+        canvas.clear();
+
+        // The heatmap is sampled at a coarse resolution into a texture, then scaled up over the
+        // play area with a single copy
+        if let Some(samples) = self.heatmap_samples.as_ref() {
+            let (columns, rows) = (samples.columns, samples.rows);
+            let pixels = self.get_heatmap_pixels(samples);
+
+            let texture_creator = T::texture_creator(canvas);
+            let mut texture = texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, columns, rows)
+                .map_err(|e| e.to_string())?;
+            texture
+                .update(None, &pixels, columns as usize * 3)
+                .map_err(|e| e.to_string())?;
+
+            canvas.copy(
+                &texture,
+                None,
+                Rect::new(
+                    0,
+                    0,
+                    columns * HEATMAP_BLOCK_SIZE,
+                    rows * HEATMAP_BLOCK_SIZE,
+                ),
+            )?;
+        }
+
+        if self.snap_to_grid {
+            self.draw_grid(canvas)?;
+        }
+
+        if self.scene.periodic {
+            self.draw_period_seams(canvas)?;
+        }
+
+        if let Some(axes) = self.mirror {
+            let (center_x, center_y) = self.view.to_screen(self.get_mirror_center());
+            let color = self.theme.text;
+            canvas.set_blend_mode(BlendMode::Blend);
+            canvas.set_draw_color(Color::RGBA(color.r, color.g, color.b, MIRROR_AXIS_ALPHA));
+
+            if axes.has_vertical() {
+                draw_dashed_line(canvas, (center_x, 0.0), (center_x, self.play_area.1))?;
+            }
+            if axes.has_horizontal() {
+                draw_dashed_line(canvas, (0.0, center_y), (self.play_area.0, center_y))?;
+            }
+
+            canvas.set_blend_mode(BlendMode::None);
+        }
+
+        if self.show_equipotentials {
+            canvas.set_draw_color(EQUIPOTENTIAL_COLOR);
+
+            for (_, start, end) in self.get_visible_equipotentials(EQUIPOTENTIAL_CELL_SIZE) {
+                canvas.draw_line(
+                    self.view.to_screen_point(start),
+                    self.view.to_screen_point(end),
+                )?;
+            }
+        }
+
+        let line_width = ((LINE_CHARGE_WIDTH * self.view.scale).round() as u8).max(1);
+        let size = canvas.output_size()?;
+
+        for line in self.scene.lines.iter() {
+            let Some((start, end)) = clip_segment(
+                self.view.to_screen(line.start),
+                self.view.to_screen(line.end),
+                size,
+            ) else {
+                continue;
+            };
+
+            canvas.thick_line(
+                start.0,
+                start.1,
+                end.0,
+                end.1,
+                line_width,
+                self.theme
+                    .particle_color(Particle::from_charge(line.charge)),
+            )?;
+        }
+
+        // Combs are dashed with a dot on each seed, so they don't pass for line charges, and
+        // have a ring on each end to drag it by
+        canvas.set_draw_color(COMB_COLOR);
+        for comb in self.scene.combs.iter() {
+            let ends = [comb.start, comb.end].map(|end| self.view.to_screen(end));
+            draw_dashed_line(canvas, ends[0], ends[1])?;
+
+            for seed in comb.seeds() {
+                if let Some((x, y)) = to_gfx_point(self.view.to_screen(seed)) {
+                    canvas.filled_circle(x, y, COMB_SEED_RADIUS, COMB_COLOR)?;
+                }
+            }
+            let handle_radius = (COMB_HANDLE_RADIUS * self.view.scale).round() as i16;
+            for end in ends {
+                if let Some((x, y)) = to_gfx_point(end) {
+                    canvas.aa_circle(x, y, handle_radius.max(1), COMB_COLOR)?;
+                }
+            }
+        }
+
+        // Off-screen particles are skipped, their positions could be past what gfx's i16
+        // coordinates can hold
+        let (width, height) = canvas.output_size()?;
+
+        for (i, particle) in self.scene.particles.iter().enumerate() {
+            let (screen_x, screen_y) = self.view.to_screen(particle.pos());
+            let radius = self.get_screen_radius(particle).round() as i32;
+            let margin = (radius + SELECTION_RING_GAP) as f64;
+
+            if !(-margin..width as f64 + margin).contains(&screen_x)
+                || !(-margin..height as f64 + margin).contains(&screen_y)
+            {
+                continue;
+            }
+
+            draw_particle(
+                canvas,
+                &self.theme,
+                Particle::from_charge(particle.charge),
+                screen_x as i16,
+                screen_y as i16,
+                radius,
+            )?;
+
+            if Some(i) == self.selected || self.multi_selection.contains(&i) {
+                canvas.aa_circle(
+                    screen_x as i16,
+                    screen_y as i16,
+                    (radius + SELECTION_RING_GAP) as i16,
+                    self.theme.text,
+                )?;
+            }
+
+            // Pinned particles are boxed in
+            if particle.pinned {
+                let half = (radius + SELECTION_RING_GAP) as i16;
+                canvas.rectangle(
+                    screen_x as i16 - half,
+                    screen_y as i16 - half,
+                    screen_x as i16 + half,
+                    screen_y as i16 + half,
+                    self.theme.text,
+                )?;
+            }
+
+            if let Some(font) = self.font.as_ref() {
+                draw_text(
+                    canvas,
+                    font,
+                    &format_charge(particle.charge),
+                    screen_x as i32 + radius,
+                    screen_y as i32 - 2 * radius,
+                    self.theme.text,
+                )?;
+            }
+        }
+
+        self.draw_dipoles(canvas)?;
+
+        if !self.animate_lines {
+            self.draw_field_lines(canvas, usize::MAX)?;
+        }
+
+        // The plane is drawn across the whole play area on top of the lines ending on it
+        if let Some(plane) = self.scene.ground_plane {
+            canvas.set_draw_color(self.theme.neutral);
+            let half_width = GROUND_PLANE_WIDTH as i32 / 2;
+
+            let rect = match plane {
+                GroundPlane::Horizontal(y) => Rect::new(
+                    0,
+                    self.view.to_screen_point((0.0, y)).y - half_width,
+                    self.play_area.0 as u32,
+                    GROUND_PLANE_WIDTH,
+                ),
+                GroundPlane::Vertical(x) => Rect::new(
+                    self.view.to_screen_point((x, 0.0)).x - half_width,
+                    0,
+                    GROUND_PLANE_WIDTH,
+                    self.play_area.1 as u32,
+                ),
+            };
+            canvas.fill_rect(rect)?;
+        }
+
+        // The flux is recomputed on every redraw, so it follows the enclosed charges as they're
+        // edited
+        if let Some(rect) = self.gauss_rect {
+            let (top_left, bottom_right) = (
+                self.view.to_screen_point(rect.0),
+                self.view.to_screen_point(rect.1),
+            );
+
+            canvas.set_draw_color(Color::MAGENTA);
+            canvas.draw_rect(Rect::new(
+                top_left.x,
+                top_left.y,
+                (bottom_right.x - top_left.x).max(1) as u32,
+                (bottom_right.y - top_left.y).max(1) as u32,
+            ))?;
+
+            if let Some(font) = self.font.as_ref() {
+                let flux = self.scene.get_gauss_flux(rect, GAUSS_SAMPLES_PER_SIDE);
+                let enclosed = self.scene.get_enclosed_charge(rect) / VACUUM_PERMITTIVITY;
+
+                draw_text(
+                    canvas,
+                    font,
+                    &format!(
+                        "\u{3a6} = {flux:.2e} N m\u{b2}/C, \
+                         Q/\u{3b5}\u{2080} = {enclosed:.2e} N m\u{b2}/C"
+                    ),
+                    top_left.x + 4,
+                    top_left.y + 4,
+                    Color::MAGENTA,
+                )?;
+            }
+        }
+
+        self.draw_measurement(canvas)?;
+
+        if self.show_arrow_grid {
+            canvas.set_draw_color(Color::YELLOW);
+
+            for (tail, tip) in self.get_arrow_grid(ARROW_GRID_SPACING, |x, y| {
+                self.scene.get_field_strength(x, y)
+            }) {
+                draw_arrow(canvas, tail, tip)?;
+            }
+        }
+
+        if let Some(order) = self.multipole_order {
+            canvas.set_draw_color(MULTIPOLE_ARROW_COLOR);
+
+            for (tail, tip) in self.get_arrow_grid(ARROW_GRID_SPACING, |x, y| {
+                self.scene.get_expansion_field(&self.moments, x, y, order)
+            }) {
+                draw_arrow(canvas, tail, tip)?;
+            }
+        }
+
+        if self.show_moments || self.multipole_order.is_some() {
+            self.draw_moments(canvas)?;
+        }
+
+        if self.show_forces {
+            canvas.set_draw_color(FORCE_ARROW_COLOR);
+
+            for ((tail, tip), magnitude) in self.get_force_arrows() {
+                draw_arrow(canvas, tail, tip)?;
+
+                if let Some(font) = self.font.as_ref() {
+                    draw_text(
+                        canvas,
+                        font,
+                        &format!("{magnitude:.2e} N"),
+                        tip.0 as i32 + 4,
+                        tip.1 as i32 + 4,
+                        FORCE_ARROW_COLOR,
+                    )?;
+                }
+            }
+        }
+
+        if let Some(samples) = self.heatmap_samples.as_ref() {
+            self.draw_heatmap_legend(canvas, samples)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Draws a frame without presenting it. The cached scene is redrawn first if it changed, then
+// copied to the screen with the toolbar and overlays on top.
+fn draw_frame(
+    canvas: &mut WindowCanvas,
+    scene_texture: &mut Texture,
+    game: &mut Game,
+    toolbar: &Toolbar,
+    probe: Option<(i32, i32)>,
+) -> Result<(), String> {
+    game.refresh_model_consumers();
+
+    // The scene stays stale if it fails to draw, so it's retried on the next frame
+    if game.dirty || game.consumers.scene != Some(game.generation) {
+        game.trace_visible_lines();
+        game.sample_heatmap();
+
+        let mut drawn = Ok(());
+        canvas
+            .with_texture_canvas(scene_texture, |canvas| drawn = game.on_update(canvas))
+            .map_err(|e| e.to_string())?;
+        drawn?;
+        game.dirty = false;
+        game.consumers.scene = Some(game.generation);
+    }
+
+    canvas.copy(scene_texture, None, None)?;
+    game.draw_growing_lines(canvas)?;
+    game.draw_test_charges(canvas)?;
+    game.refresh_prediction();
+    game.draw_prediction(canvas)?;
+    toolbar.on_update(canvas, &game.theme)?;
+    game.report_exports();
+    game.draw_status_bar(canvas, toolbar.get_selected_option(), probe)?;
+    game.draw_scale_note(canvas)?;
+
+    if let Some(crop_toolbar) = game.pending_screenshot.take() {
+        match take_screenshot(canvas, crop_toolbar) {
+            Ok(job) => game.queue_export(job),
+            Err(e) => game.notify_error(format!("Failed to save screenshot: {e}")),
+        }
+    }
+
+    if !game.config_problems.is_empty() {
+        game.draw_config_problems(canvas)?;
+    } else if game.show_help {
+        game.draw_help(canvas)?;
+    } else if game.batch_edit.is_some() {
+        game.draw_batch_edit(canvas)?;
+    } else if let Some((x, y)) = probe {
+        if game.pasting {
+            game.draw_paste_ghost(canvas, x, y)?;
+        }
+        if let Some((x, y)) = game.get_placement_cursor(toolbar.get_selected_option(), probe) {
+            game.draw_placement_preview(canvas, x, y)?;
+        }
+
+        game.draw_probe(canvas, x, y)?;
+    }
+
+    Ok(())
+}
+
+// Largest window side accepted in the config or on the command line, well past any real display
+pub const MAX_WINDOW_SIZE: u32 = 16384;
+
+// Colors replacing the theme's, given as RGB in the config file. They apply to every theme.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorOverrides {
+    pub background: Option<[u8; 3]>,
+    pub line: Option<[u8; 3]>,
+    pub positive: Option<[u8; 3]>,
+    pub negative: Option<[u8; 3]>,
+    pub neutral: Option<[u8; 3]>,
+    pub toolbar: Option<[u8; 3]>,
+    pub glyph: Option<[u8; 3]>,
+    pub text: Option<[u8; 3]>,
+}
+
+impl ColorOverrides {
+    fn apply(&self, theme: Theme) -> Theme {
+        let pick = |color: Option<[u8; 3]>, fallback: Color| {
+            color.map_or(fallback, |[r, g, b]| Color::RGB(r, g, b))
+        };
+
+        Theme {
+            background: pick(self.background, theme.background),
+            line: pick(self.line, theme.line),
+            positive: pick(self.positive, theme.positive),
+            negative: pick(self.negative, theme.negative),
+            neutral: pick(self.neutral, theme.neutral),
+            toolbar: pick(self.toolbar, theme.toolbar),
+            glyph: pick(self.glyph, theme.glyph),
+            text: pick(self.text, theme.text),
+            ..theme
+        }
+    }
+}
+
+// Start-up settings, from the config file with the command line on top. Every key of the file
+// is optional.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    // Scene loaded at startup and used for saving and loading, scene.json if not given. Only
+    // set from the command line.
+    #[serde(skip)]
+    pub scene_path: Option<PathBuf>,
+    // Scene whose charges are added to the loaded one at startup, moved by `merge_offset` world
+    // pixels, and that Ctrl+Shift+O merges in at the cursor. Only set from the command line.
+    #[serde(skip)]
+    pub merge_path: Option<PathBuf>,
+    #[serde(skip)]
+    pub merge_offset: (f64, f64),
+    // What was wrong with the config file, each key it's about left at its default. Shown once
+    // at startup.
+    #[serde(skip)]
+    pub problems: Vec<String>,
+    pub meters_per_pixel: f64,
+    // Frame rate used when vsync isn't available
+    pub target_fps: u32,
+    // Prints feedback on every action to the terminal
+    pub verbose: bool,
+    // Points added to each field line per frame when they're animated
+    pub line_growth: u32,
+    // Seconds ahead the Alt trajectory preview looks, and between the ticks along it
+    pub prediction_horizon: f64,
+    pub prediction_tick: f64,
+    // Starts with the colorblind-friendly palette
+    pub colorblind: bool,
+    // Radius of a particle of one elementary charge, in pixels
+    pub particle_radius: f64,
+    // Field lines seeded around a particle per elementary charge
+    pub lines_per_charge: f64,
+    // Most steps a field line is traced for
+    pub max_line_iters: usize,
+    // Field lines seeded along each new comb
+    pub comb_lines: usize,
+    // One in every this many frames is recorded to GIFs
+    pub record_stride: u32,
+    // Stops dynamics, test charges and line animations while the window is minimized or
+    // unfocused, instead of letting them run on
+    pub pause_in_background: bool,
+    // Tables have to come after every plain key in TOML, so this stays last
+    pub colors: ColorOverrides,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            fullscreen: false,
+            scene_path: None,
+            merge_path: None,
+            merge_offset: (0.0, 0.0),
+            problems: Vec::new(),
+            meters_per_pixel: DEFAULT_METERS_PER_PIXEL,
+            target_fps: DEFAULT_TARGET_FPS,
+            verbose: false,
+            line_growth: DEFAULT_LINE_GROWTH,
+            prediction_horizon: DEFAULT_PREDICTION_HORIZON,
+            prediction_tick: DEFAULT_PREDICTION_TICK,
+            colorblind: false,
+            particle_radius: PARTICLE_RADIUS,
+            lines_per_charge: DEFAULT_LINES_PER_CHARGE,
+            max_line_iters: MAX_LINE_ITERS,
+            comb_lines: DEFAULT_COMB_LINES,
+            record_stride: DEFAULT_RECORD_STRIDE,
+            pause_in_background: false,
+            colors: ColorOverrides::default(),
+        }
+    }
+}
+
+impl Config {
+    // Where the config file is read from unless another one is given, following the XDG base
+    // directory spec
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+        Some(config_home.join("efield").join("config.toml"))
+    }
+
+    // Only a file that can't be read is an error, the problems in one that can are printed and
+    // kept in `problems`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Couldn't read config file {}: {e}", path.display()))?;
+        let config = Self::from_toml(&text);
+
+        for problem in &config.problems {
+            eprintln!("{}: {problem}", path.display());
+        }
+
+        Ok(config)
+    }
+
+    // Every key that has the wrong type or an out of range value is left at its default and
+    // noted in `problems`, along with keys that aren't settings. The rest still apply. A file
+    // that isn't valid TOML at all gives the defaults, with the line of the syntax error.
+    pub fn from_toml(text: &str) -> Self {
+        let table = match text.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => {
+                return Self {
+                    problems: vec![format!("Not valid TOML, using the defaults: {e}")],
+                    ..Self::default()
+                }
+            }
+        };
+
+        let mut problems = Vec::new();
+        let check = |table: toml::Table| {
+            toml::Value::Table(table)
+                .try_into::<Config>()
+                .map(drop)
+                .map_err(|e| e.message().to_string())
+        };
+        let table = keep_valid_keys(table, &check, "", &mut problems);
+
+        let mut config: Config = serde_ignored::deserialize(toml::Value::Table(table), |key| {
+            problems.push(format!("Unknown key `{key}`, ignored"))
+        })
+        .unwrap_or_default();
+
+        problems.extend(config.validate());
+        config.problems = problems;
+
+        config
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|e| e.to_string())
+    }
+
+    // Puts every out of range value back to its default, saying which
+    fn validate(&mut self) -> Vec<String> {
+        let defaults = Config::default();
+        let mut problems = Vec::new();
+
+        for (name, size, default) in [
+            ("width", &mut self.width, defaults.width),
+            ("height", &mut self.height, defaults.height),
+        ] {
+            if *size == 0 || *size > MAX_WINDOW_SIZE {
+                problems.push(format!(
+                    "`{name}` must be between 1 and {MAX_WINDOW_SIZE} pixels, got {size}, \
+                     using {default}"
+                ));
+                *size = default;
+            }
+        }
+
+        for (name, value, default) in [
+            (
+                "meters_per_pixel",
+                &mut self.meters_per_pixel,
+                defaults.meters_per_pixel,
+            ),
+            (
+                "particle_radius",
+                &mut self.particle_radius,
+                defaults.particle_radius,
+            ),
+            (
+                "lines_per_charge",
+                &mut self.lines_per_charge,
+                defaults.lines_per_charge,
+            ),
+            (
+                "prediction_horizon",
+                &mut self.prediction_horizon,
+                defaults.prediction_horizon,
+            ),
+            (
+                "prediction_tick",
+                &mut self.prediction_tick,
+                defaults.prediction_tick,
+            ),
+        ] {
+            if !(*value > 0.0 && value.is_finite()) {
+                problems.push(format!(
+                    "`{name}` must be a positive number, got {value}, using {default}"
+                ));
+                *value = default;
+            }
+        }
+
+        for (name, value, default) in [
+            ("target_fps", &mut self.target_fps, defaults.target_fps),
+            ("line_growth", &mut self.line_growth, defaults.line_growth),
+            (
+                "record_stride",
+                &mut self.record_stride,
+                defaults.record_stride,
+            ),
+        ] {
+            if *value == 0 {
+                problems.push(format!(
+                    "`{name}` must be a positive whole number, using {default}"
+                ));
+                *value = default;
+            }
+        }
+
+        if self.max_line_iters == 0 {
+            problems.push(format!(
+                "`max_line_iters` must be a positive whole number, using {}",
+                defaults.max_line_iters
+            ));
+            self.max_line_iters = defaults.max_line_iters;
+        }
+
+        if !(1..=MAX_COMB_LINES).contains(&self.comb_lines) {
+            problems.push(format!(
+                "`comb_lines` must be between 1 and {MAX_COMB_LINES}, got {}, using {}",
+                self.comb_lines, defaults.comb_lines
+            ));
+            self.comb_lines = defaults.comb_lines;
+        }
+
+        problems
+    }
+}
+
+// Leaves out the keys of `table` that `check` rejects on their own, noting why in `problems`.
+// Tables are checked key by key in turn, so one bad color doesn't lose the others.
+fn keep_valid_keys(
+    table: toml::Table,
+    check: &dyn Fn(toml::Table) -> Result<(), String>,
+    prefix: &str,
+    problems: &mut Vec<String>,
+) -> toml::Table {
+    let mut kept = toml::Table::new();
+
+    for (key, value) in table {
+        let name = format!("{prefix}{key}");
+        let single = toml::Table::from_iter([(key.clone(), value.clone())]);
+
+        match (check(single), value) {
+            (Ok(()), value) => {
+                kept.insert(key, value);
+            }
+            (Err(_), toml::Value::Table(inner)) => {
+                let check_inner = |inner| {
+                    check(toml::Table::from_iter([(
+                        key.clone(),
+                        toml::Value::Table(inner),
+                    )]))
+                };
+                let inner = keep_valid_keys(inner, &check_inner, &format!("{name}."), problems);
+                kept.insert(key, toml::Value::Table(inner));
+            }
+            (Err(e), _) => problems.push(format!("`{name}`: {e}, using the default")),
+        }
+    }
+
+    kept
+}
+
+// Takes the RGBA pixels of a frame, its width and its height
+pub type FrameCallback = Box<dyn FnMut(&[u8], u32, u32)>;
+
+// Lets a program embedding the visualizer watch what it draws. Set the fields on
+// `Hooks::default()` and pass it to run_with_hooks or render_frames.
+pub struct Hooks {
+    // Called with the RGBA pixels of every `frame_stride`th frame, counting from the first, and
+    // their width and height. It runs on the main thread after the frame is drawn and before
+    // it's presented, so it should be done within a couple of milliseconds to keep up with
+    // 60 fps. Anything slower should copy the pixels and hand them to another thread, the
+    // slice is reused for the next frame.
+    pub on_frame: Option<FrameCallback>,
+    pub frame_stride: u32,
+    // Set when on_frame writes the frames to stdout, which moves all other output to stderr
+    pub frames_on_stdout: bool,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self {
+            on_frame: None,
+            frame_stride: 1,
+            frames_on_stdout: false,
+        }
+    }
+}
+
+impl Hooks {
+    // Hands frame number `frame` to on_frame if it's due one. The pixels are read into `buffer`,
+    // which keeps its allocation from one frame to the next.
+    fn frame_drawn<T: SceneTarget>(
+        &mut self,
+        canvas: &Canvas<T>,
+        frame: u64,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), String> {
+        let Some(on_frame) = self.on_frame.as_mut() else {
+            return Ok(());
+        };
+        if !frame.is_multiple_of(self.frame_stride.max(1) as u64) {
+            return Ok(());
+        }
+
+        let (width, height) = canvas.output_size()?;
+        buffer.resize(width as usize * height as usize * 4, 0);
+
+        // Canvas::read_pixels allocates a new Vec every call, so this reads straight into the
+        // buffer instead
+        // SAFETY: the buffer holds `height` rows of `width * 4` bytes, exactly what SDL writes
+        // for the whole target in a 32 bit format with that pitch
+        let result = unsafe {
+            sdl2::sys::SDL_RenderReadPixels(
+                canvas.raw(),
+                std::ptr::null(),
+                PixelFormatEnum::RGBA32 as u32,
+                buffer.as_mut_ptr().cast(),
+                (width * 4) as i32,
+            )
+        };
+        if result != 0 {
+            return Err(sdl2::get_error());
+        }
+
+        on_frame(buffer, width, height);
+
+        Ok(())
+    }
+}
+
+// Draws the scene at `config.scene_path` onto an off-screen surface the size of the window and
+// writes it to a PNG at `out`, without opening a window. The toolbar and status bar are left
+// out, so the whole image is play area.
+pub fn render(config: Config, out: &Path) -> Result<(), Box<dyn Error>> {
+    VERBOSE.store(config.verbose, Ordering::Relaxed);
+
+    let ttf_context = init_ttf();
+    let mut game = Game::new(&config, ttf_context.as_ref());
+    load_headless_scene(&mut game, &config)?;
+
+    let (width, height) = (config.width, config.height);
+    let mut canvas = Surface::new(width, height, PixelFormatEnum::RGB888)?.into_canvas()?;
+    game.play_area = (width as f64, height as f64);
+
+    game.refresh_model_consumers();
+    game.trace_visible_lines();
+    game.sample_heatmap();
+    game.on_update(&mut canvas)?;
+
+    let pixels = canvas.read_pixels(None, PixelFormatEnum::RGB24)?;
+    write_png(out, width, height, &pixels)?;
+    log!(
+        "Rendered {} to {}",
+        game.scene_path.display(),
+        out.display()
+    );
+
+    Ok(())
+}
+
+// Draws `frames` frames of the scene at `config.scene_path` off-screen and hands them to
+// `hooks` the same way run_with_hooks does, without opening a window. The charges move under
+// dynamics at `config.target_fps` from one frame to the next, since a still scene would just
+// give the same frame every time.
+pub fn render_frames(config: Config, frames: u64, mut hooks: Hooks) -> Result<(), Box<dyn Error>> {
+    VERBOSE.store(config.verbose, Ordering::Relaxed);
+    PIPING.store(hooks.frames_on_stdout, Ordering::Relaxed);
+
+    let ttf_context = init_ttf();
+    let mut game = Game::new(&config, ttf_context.as_ref());
+    load_headless_scene(&mut game, &config)?;
+
+    let (width, height) = (config.width, config.height);
+    let mut canvas = Surface::new(width, height, PixelFormatEnum::RGB888)?.into_canvas()?;
+    game.play_area = (width as f64, height as f64);
+
+    let dt = 1.0 / config.target_fps as f64;
+    let mut buffer = Vec::new();
+    for frame in 0..frames {
+        if frame > 0 {
+            game.scene.step_dynamics(dt * game.scene.time_scale);
+            game.model_changed();
+        }
+
+        game.refresh_model_consumers();
+        game.trace_visible_lines();
+        game.sample_heatmap();
+        game.on_update(&mut canvas)?;
+        hooks.frame_drawn(&canvas, frame, &mut buffer)?;
+    }
+
+    Ok(())
+}
+
+// Loads the scene and merges in the --merge one for drawing it without a window, where a
+// missing scene is an error rather than something to start from
+fn load_headless_scene(game: &mut Game, config: &Config) -> Result<(), Box<dyn Error>> {
+    game.load_scene().map_err(|e| {
+        format!(
+            "Failed to load scene from {}: {e}",
+            game.scene_path.display()
+        )
+    })?;
+    if let Some(path) = &config.merge_path {
+        let imported = Snapshot::read(path)
+            .map_err(|e| format!("Failed to merge scene from {}: {e}", path.display()))?;
+        game.merge_charges(imported, config.merge_offset);
+    }
+
+    Ok(())
+}
+
+pub fn run(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    run_with_hooks(config, Hooks::default())
+}
+
+// Same as run, with `hooks` told about each frame drawn
+pub fn run_with_hooks(config: Config, mut hooks: Hooks) -> Result<(), Box<dyn std::error::Error>> {
+    VERBOSE.store(config.verbose, Ordering::Relaxed);
+    PIPING.store(hooks.frames_on_stdout, Ordering::Relaxed);
+
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+
+    let mut window_builder =
+        video_subsystem.window("Electric Field Visualizer", config.width, config.height);
+    window_builder.position_centered().resizable();
+    if config.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build()?;
+
+    let mut event_pump = sdl_context.event_pump()?;
+    let keyboard = sdl_context.keyboard();
+    let ttf_context = init_ttf();
+
+    let mut game = Game::new(&config, ttf_context.as_ref());
+    let mut toolbar = Toolbar::default();
+    let mut canvas = window.into_canvas().present_vsync().build()?;
+    // Some drivers ignore the request, in which case frames are paced by sleeping instead
+    let vsync = canvas.info().flags & SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0;
+
+    // The scene is drawn into this texture whenever it changes. Every frame it's copied to the
+    // screen with the toolbar and overlays like the cursor probe drawn on top.
+    let texture_creator = canvas.texture_creator();
+    let (width, height) = canvas.output_size()?;
+    let mut scene_texture = texture_creator.create_texture_target(None, width, height)?;
+
+    game.update_play_area(&canvas)?;
+    game.dirty = true;
+
+    // A scene file that doesn't exist yet is fine, it's created on the first save
+    if config.scene_path.is_some() && game.scene_path.exists() {
+        match game.load_scene() {
+            Ok(()) => game.notify(format!("Loaded scene from {}", game.scene_path.display())),
+            Err(e) => game.notify_error(format!(
+                "Failed to load scene from {}: {e}",
+                game.scene_path.display()
+            )),
+        }
+    }
+    if let Some(path) = &config.merge_path {
+        match Snapshot::read(path) {
+            Ok(imported) => {
+                let count = game.merge_charges(imported, config.merge_offset);
+                game.notify(format!("Merged {count} charges from {}", path.display()));
+            }
+            Err(e) => game.notify_error(format!(
+                "Failed to merge scene from {}: {e}",
+                path.display()
+            )),
+        }
+    }
+
+    let mut last_frame = Instant::now();
+    let mut recorder: Option<Recorder> = None;
+    let mut minimized = false;
+    let mut focused = true;
+    // Where the window was before going fullscreen, if it was windowed
+    let mut windowed: Option<Rect> = None;
+    // Cursor position if it's over the play area
+    let mut probe: Option<(i32, i32)> = None;
+    // Frames drawn so far and the pixels of the last one handed to the hooks
+    let mut frame: u64 = 0;
+    let mut frame_buffer = Vec::new();
+    // Whether the last pass blocked waiting for events
+    let mut idle = false;
+
+    'main_loop: loop {
+        let frame_start = Instant::now();
+        // A pass that blocked on events is meant to take longer than MAX_FRAME_DT, so that
+        // time isn't cut short but simulated in steps no longer than MAX_FRAME_DT instead
+        let max_elapsed = if idle {
+            MINIMIZED_WAIT_MS as f64 / 1000.0 + MAX_FRAME_DT
+        } else {
+            MAX_FRAME_DT
+        };
+        let elapsed = frame_start
+            .duration_since(last_frame)
+            .as_secs_f64()
+            .min(max_elapsed);
+        let dt = elapsed.min(MAX_FRAME_DT);
+        last_frame = frame_start;
+
+        // Nothing is visible while minimized, so block on events instead of spinning. A
+        // recording keeps capturing at the full rate whatever happens to the window.
+        idle = minimized && recorder.is_none();
+        let events: Vec<Event> = if idle {
+            event_pump
+                .wait_event_timeout(MINIMIZED_WAIT_MS)
+                .into_iter()
+                .chain(event_pump.poll_iter())
+                .collect()
+        } else {
+            event_pump.poll_iter().collect()
+        };
+
+        for event in events {
+            let in_playfield = |x: i32, y: i32| {
+                canvas.output_size().is_ok_and(|output_size| {
+                    (x as u32) < output_size.0 * 9 / 10
+                        && (y as u32) < get_toolbar_bottom(output_size)
+                })
+            };
+
+            match event {
+                Event::Quit { .. } => {
+                    break 'main_loop;
+                }
+                Event::Window { win_event, .. } => match win_event {
+                    WindowEvent::Minimized | WindowEvent::Hidden => {
+                        minimized = true;
+                    }
+                    // Every frame copies the cached scene to the screen, so coming back only
+                    // takes the next frame and doesn't re-trace anything
+                    WindowEvent::Restored | WindowEvent::Shown | WindowEvent::Exposed => {
+                        minimized = false;
+                    }
+                    // The cached scene has to match the new window size, and lines are
+                    // re-traced since the visible area changed. Particles left outside the new
+                    // bounds are kept
+                    WindowEvent::SizeChanged(..) => {
+                        let (width, height) = canvas.output_size()?;
+                        scene_texture =
+                            texture_creator.create_texture_target(None, width, height)?;
+
+                        if let Err(e) = game.update_play_area(&canvas) {
+                            eprintln!("Failed to read the new window size: {e}");
+                        }
+                        game.dirty = true;
+                    }
+                    // Some window managers only send this when a minimized window is brought back
+                    WindowEvent::FocusGained => {
+                        focused = true;
+                        minimized = false;
+                    }
+                    WindowEvent::FocusLost => {
+                        focused = false;
+                    }
+                    WindowEvent::Leave => {
+                        probe = None;
+                    }
+                    _ => {}
+                },
+                // Some renderers lose the contents of target textures, e.g. when the display
+                // mode changes
+                Event::RenderTargetsReset { .. } | Event::RenderDeviceReset { .. } => {
+                    game.dirty = true;
+                }
+                // The config problems are dismissed by whatever comes first
+                Event::MouseButtonDown { .. } | Event::KeyDown { .. }
+                    if !game.config_problems.is_empty() =>
+                {
+                    game.config_problems.clear();
+                }
+                // Nothing underneath the help overlay reacts to the mouse while it's open
+                Event::MouseButtonDown { .. }
+                | Event::MouseButtonUp { .. }
+                | Event::MouseWheel { .. }
+                    if game.show_help => {}
+                Event::MouseMotion {
+                    xrel,
+                    yrel,
+                    mousestate,
+                    ..
+                } if mousestate.middle() => {
+                    game.handle_pan(xrel, yrel);
+                }
+                Event::MouseMotion { y, mousestate, .. }
+                    if mousestate.left() && game.legend_drag.is_some() =>
+                {
+                    game.handle_legend_drag(y);
+                }
+                Event::MouseMotion {
+                    x, y, mousestate, ..
+                } if mousestate.left() && game.dragging.is_some() => {
+                    game.handle_drag(x, y);
+                }
+                Event::MouseMotion { x, y, .. } => {
+                    probe = Some((x, y)).filter(|_| in_playfield(x, y));
+                }
+                Event::MouseButtonDown {
+                    mouse_btn,
+                    x,
+                    y,
+                    timestamp,
+                    ..
+                } => match mouse_btn {
+                    // Right clicks always erase, whatever tool is selected
+                    MouseButton::Right if in_playfield(x, y) => {
+                        game.handle_erase(x, y);
+                    }
+                    // The middle button pans the view, see the motion handling above
+                    MouseButton::Right | MouseButton::Middle => {}
+                    MouseButton::Left if in_playfield(x, y) && game.begin_legend_drag(x, y) => {}
+                    MouseButton::Left if in_playfield(x, y) && game.pasting => {
+                        game.paste_at(x, y);
+                    }
+                    MouseButton::Left if in_playfield(x, y) && game.placing_test_charge => {
+                        game.place_test_charge(x, y);
+                    }
+                    MouseButton::Left
+                        if in_playfield(x, y)
+                            && toolbar
+                                .get_selected_option()
+                                .is_none_or(Tool::flips_on_double_click)
+                            && game.handle_double_click(x, y, timestamp) => {}
+                    _ if in_playfield(x, y) => match toolbar.get_selected_option() {
+                        Some(Tool::Erase) => game.handle_erase(x, y),
+                        Some(Tool::Move)
+                            if keyboard
+                                .mod_state()
+                                .intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) =>
+                        {
+                            game.toggle_multi_selection(x, y)
+                        }
+                        Some(Tool::Move) => game.begin_drag(x, y),
+                        Some(Tool::Line) => game.begin_line(x, y),
+                        Some(Tool::Gauss) => game.begin_gauss(x, y),
+                        Some(Tool::Ground) => game.begin_ground(x, y),
+                        Some(Tool::Measure) => game.measure_at(x, y),
+                        Some(Tool::Dipole) => game.place_dipole(x, y),
+                        Some(Tool::Comb) => game.begin_comb(x, y),
+                        Some(Tool::Place(_)) | None => game.handle_mouse_down(x, y),
+                    },
+                    _ => {
+                        if let Err(e) = toolbar.handle_mouse_down(&canvas, x, y) {
+                            eprintln!("Failed to handle toolbar click: {e}");
+                        }
+
+                        if let Some(Tool::Place(particle)) = toolbar.get_selected_option() {
+                            game.set_selected_particle(particle);
+                            toolbar.set_charge(game.current_selected_charge);
+                        }
+                    }
+                },
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    game.handle_mouse_up(x, y);
+                }
+                Event::MouseWheel { y: scroll, .. } => {
+                    let mouse_state = event_pump.mouse_state();
+
+                    if in_playfield(mouse_state.x(), mouse_state.y()) {
+                        game.handle_scroll(
+                            mouse_state.x(),
+                            mouse_state.y(),
+                            scroll,
+                            keyboard.mod_state(),
+                        );
+                    }
+                }
+                // The batch edit table takes every key the same way
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } if game.batch_edit.is_some() => {
+                    game.handle_batch_edit_keydown(keycode, keymod);
+                }
+                // Typing a charge takes every key, so its digits don't pick tools and so on
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if game.charge_entry.is_some() => {
+                    game.handle_charge_entry_keydown(keycode);
+                    toolbar
+                        .set_selected_particle(Particle::from_charge(game.current_selected_charge));
+                    toolbar.set_charge(game.current_selected_charge);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Q),
+                    ..
+                } => {
+                    break 'main_loop;
+                }
+                // Merging needs the cursor, which only the event loop knows about
+                Event::KeyDown {
+                    keycode: Some(Keycode::O),
+                    keymod,
+                    ..
+                } if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+                    && keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) =>
+                {
+                    let mouse_state = event_pump.mouse_state();
+                    let path = game.merge_path.clone();
+
+                    match game.merge_scene_at(mouse_state.x(), mouse_state.y()) {
+                        Ok(count) => {
+                            game.notify(format!("Merged {count} charges from {}", path.display()))
+                        }
+                        Err(e) => game.notify_error(format!(
+                            "Failed to merge scene from {}: {e}",
+                            path.display()
+                        )),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    ..
+                } => {
+                    if let Err(e) = toggle_fullscreen(canvas.window_mut(), &mut windowed) {
+                        eprintln!("Failed to toggle fullscreen: {e}");
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    ..
+                } => {
+                    recorder = match recorder.take() {
+                        Some(active) => {
+                            game.queue_export(ExportJob::Gif(active));
+                            None
+                        }
+                        None => Recorder::start(&canvas, config.record_stride)
+                            .map_err(|e| {
+                                game.notify_error(format!("Failed to start recording: {e}"))
+                            })
+                            .ok(),
+                    };
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } => {
+                    // Number keys pick tools the same way clicking them in the toolbar does
+                    if let Some(index) = tool_key_index(keycode) {
+                        if let Some(Tool::Place(particle)) = toolbar.select_index(index) {
+                            game.set_selected_particle(particle);
+                        }
+                    } else {
+                        game.handle_keydown(keycode, keymod);
+                        toolbar.set_selected_particle(Particle::from_charge(
+                            game.current_selected_charge,
+                        ));
+                    }
+                    toolbar.set_charge(game.current_selected_charge);
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => {
+                    game.handle_keyup(keycode);
+                }
+                _ => {}
+            }
+        }
+
+        // Dynamics and animations carry on in the background unless the config pauses them
+        let background = (minimized || !focused) && recorder.is_none();
+        if !(background && config.pause_in_background) {
+            // Dynamics run on the scene's clock, but line animations on the screen's
+            let steps = (elapsed / MAX_FRAME_DT).ceil().max(1.0);
+            let scene_dt = elapsed / steps * game.scene.time_scale;
+            for _ in 0..steps as u32 {
+                if game.simulating {
+                    game.scene.step_dynamics(scene_dt);
+                    game.model_changed();
+                }
+
+                game.step_test_charges(scene_dt);
+            }
+            game.grow_lines();
+        }
+
+        if minimized && recorder.is_none() {
+            continue;
+        }
+
+        // A frame that fails to draw, e.g. halfway through a resize, is skipped instead of
+        // ending the program
+        match draw_frame(&mut canvas, &mut scene_texture, &mut game, &toolbar, probe) {
+            Ok(()) => {
+                if let Some(active) = recorder.as_mut() {
+                    if let Err(e) = active.capture(&canvas, dt) {
+                        eprintln!("Failed to record frame, stopping the recording: {e}");
+                        recorder = None;
+                    }
+                }
+
+                if let Err(e) = hooks.frame_drawn(&canvas, frame, &mut frame_buffer) {
+                    eprintln!("Failed to read the frame for the frame hook: {e}");
+                }
+                frame += 1;
+
+                // The recording indicator is drawn after capturing so it doesn't show up in the
+                // GIF
+                if recorder.is_some() {
+                    if let Err(e) = canvas.filled_circle(12, 12, 6, Color::RED) {
+                        eprintln!("Failed to draw the recording indicator: {e}");
+                    }
+                }
+
+                canvas.present();
+            }
+            Err(e) => eprintln!("Skipped a frame that failed to draw: {e}"),
+        }
+
+        // Drawing the frame brought the title up to date with the model
+        if canvas.window().title() != game.title {
+            if let Err(e) = canvas.window_mut().set_title(&game.title) {
+                eprintln!("Failed to set the window title: {e}");
+            }
+        }
+
+        // With vsync presenting already waited for the display, otherwise only the rest of the
+        // frame that wasn't spent handling events and drawing is slept
+        let throttled = !focused && recorder.is_none();
+        if !vsync || throttled {
+            let fps = if throttled {
+                UNFOCUSED_FPS
+            } else {
+                config.target_fps
+            };
+            let frame_time = Duration::from_secs_f64(1.0 / fps as f64);
+            thread::sleep(frame_time.saturating_sub(frame_start.elapsed()));
+        }
+    }
+
+    // Quitting mid-recording still leaves a playable GIF, and exports still being written are
+    // waited for. The window is gone by now, so how they went is printed rather than shown.
+    if let Some(active) = recorder {
+        game.queue_export(ExportJob::Gif(active));
+    }
+    for result in game.exports.join() {
+        match result {
+            Ok(text) => say!("{text}"),
+            Err(text) => eprintln!("{text}"),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_are_written_without_holding_up_the_frame() {
+        let csv_path = std::env::temp_dir().join("efield-async-export-test.csv");
+        let svg_path = std::env::temp_dir().join("efield-async-export-test.svg");
+        let mut game = Game {
+            play_area: (800.0, 600.0),
+            ..Default::default()
+        };
+        game.scene.particles = (0..3000)
+            .map(|i| {
+                ChargedParticle::new((i % 60) as f64 * 13.0, (i / 60) as f64 * 11.0, CHARGE_STEP)
+            })
+            .collect();
+        game.field_lines = (0..300)
+            .map(|i| FieldLine {
+                points: (0..2000).map(|j| (i as f64, j as f64 * 0.3)).collect(),
+                ..Default::default()
+            })
+            .collect();
+
+        let queueing = Instant::now();
+        game.queue_export(ExportJob::Csv {
+            path: csv_path.clone(),
+            scene: game.scene.clone(),
+            bounds: game.get_visible_bounds(0.0),
+        });
+        game.queue_export(ExportJob::Svg {
+            path: svg_path.clone(),
+            snapshot: game.get_svg_snapshot(),
+        });
+        assert!(queueing.elapsed() < Duration::from_millis(200));
+        assert_eq!(game.exports.pending, 2);
+
+        // Each tick only picks up what's finished
+        let started = Instant::now();
+        while game.exports.pending > 0 {
+            let tick = Instant::now();
+            game.report_exports();
+            assert!(tick.elapsed() < Duration::from_millis(20));
+            assert!(started.elapsed() < Duration::from_secs(120));
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let notice = game.notice.as_ref().unwrap();
+        assert!(!notice.error && notice.text.starts_with("Exported SVG"));
+        assert!(fs::read_to_string(&csv_path).unwrap().lines().count() > CSV_ROWS);
+        assert!(fs::read_to_string(&svg_path).unwrap().ends_with("</svg>\n"));
+        fs::remove_file(csv_path).unwrap();
+        fs::remove_file(svg_path).unwrap();
+        assert!(game.exports.join().is_empty());
+    }
+
+    #[test]
+    fn source_hues_stay_with_their_charges() {
+        let mut game = Game {
+            play_area: (800.0, 600.0),
+            color_lines_by_source: true,
+            ..Default::default()
+        };
+        game.scene.particles = vec![
+            ChargedParticle::new(100.0, 100.0, CHARGE_STEP),
+            ChargedParticle::new(300.0, 100.0, CHARGE_STEP),
+            ChargedParticle::new(500.0, 100.0, -CHARGE_STEP),
+            ChargedParticle::new(700.0, 100.0, CHARGE_STEP),
+        ];
+        game.trace_visible_lines();
+        let hues = |game: &Game| -> Vec<Option<usize>> {
+            game.scene.particles.iter().map(|p| p.hue).collect()
+        };
+        assert_eq!(hues(&game), [Some(0), Some(1), None, Some(2)]);
+
+        // Removing a charge frees its hue for the next one without repainting the rest
+        game.remove_particle(0);
+        game.place_particle(ChargedParticle::new(400.0, 500.0, CHARGE_STEP));
+        game.trace_visible_lines();
+        assert_eq!(hues(&game), [Some(1), None, Some(2), Some(0)]);
+
+        let line = game.field_lines.iter().find(|line| line.seed == Some(0));
+        let color = get_line_color(&game.scene, &game.theme, true, line.unwrap());
+        assert_eq!(color, LINE_PALETTE[1]);
+        let negative = FieldLine {
+            seed: Some(1),
+            ..Default::default()
+        };
+        let color = get_line_color(&game.scene, &game.theme, true, &negative);
+        assert_eq!(color, game.theme.line);
+
+        let mut svg = Vec::new();
+        game.get_svg_snapshot().write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains(&svg_color(LINE_PALETTE[1])));
+        assert!(svg.contains("data-particle=\"0\""));
+
+        let saved: Game = serde_json::from_str(&serde_json::to_string(&game).unwrap()).unwrap();
+        assert_eq!(hues(&saved), hues(&game));
+    }
+
+    #[test]
+    fn scene_round_trip_preserves_charges_exactly() {
+        let path = std::env::temp_dir().join("efield-round-trip-test.json");
+        let game = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(12.5, 300.0, CHARGE_STEP / 3.0),
+                    ChargedParticle::new(0.1, 0.2, -7.0 * CHARGE_STEP),
+                    ChargedParticle::new(640.0, 1.0 / 3.0, 0.0),
+                ],
+                lines: vec![LineCharge::new(
+                    (10.0, 20.0),
+                    (300.5, 400.0 / 7.0),
+                    -CHARGE_STEP / 7.0,
+                )],
+                period: (360.0, 1000.0 / 3.0),
+                ..Default::default()
+            },
+            current_selected_charge: std::f64::consts::PI * CHARGE_STEP,
+            scene_path: path.clone(),
+            ..Default::default()
+        };
+        game.save_scene().unwrap();
+
+        let mut loaded = Game {
+            scene: Scene {
+                particles: vec![ChargedParticle::new(1.0, 1.0, CHARGE_STEP)],
+                ..Default::default()
+            },
+            scene_path: path.clone(),
+            ..Default::default()
+        };
+        loaded.load_scene().unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.scene.particles, game.scene.particles);
+        assert_eq!(loaded.scene.lines, game.scene.lines);
+        assert_eq!(loaded.scene.period, game.scene.period);
+        assert_eq!(loaded.current_selected_charge, game.current_selected_charge);
+    }
+
+    #[test]
+    fn loading_missing_or_malformed_scene_is_an_error() {
+        let path = std::env::temp_dir().join("efield-malformed-test.json");
+        fs::write(&path, "{ \"particles\": [1, 2").unwrap();
+
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![ChargedParticle::new(1.0, 1.0, CHARGE_STEP)],
+                ..Default::default()
+            },
+            scene_path: path.clone(),
+            ..Default::default()
+        };
+        assert!(game.load_scene().is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(game.load_scene().is_err());
+
+        assert_eq!(
+            game.scene.particles,
+            vec![ChargedParticle::new(1.0, 1.0, CHARGE_STEP)]
+        );
+    }
+
+    #[test]
+    fn headless_render_writes_a_png_of_the_window_size() {
+        let scene_path = std::env::temp_dir().join("efield-render-test.json");
+        let out = std::env::temp_dir().join("efield-render-test.png");
+        let game = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(100.0, 120.0, CHARGE_STEP),
+                    ChargedParticle::new(220.0, 120.0, -CHARGE_STEP),
+                ],
+                ..Default::default()
+            },
+            scene_path: scene_path.clone(),
+            ..Default::default()
+        };
+        game.save_scene().unwrap();
+
+        let config = Config {
+            width: 320,
+            height: 240,
+            scene_path: Some(scene_path.clone()),
+            ..Default::default()
+        };
+        render(config, &out).unwrap();
+        fs::remove_file(scene_path).unwrap();
+
+        assert!(fs::metadata(&out).unwrap().len() > 0);
+        let info = png::Decoder::new(io::BufReader::new(File::open(&out).unwrap()))
+            .read_info()
+            .unwrap()
+            .info()
+            .clone();
+        fs::remove_file(out).unwrap();
+
+        assert_eq!((info.width, info.height), (320, 240));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+    }
+
+    #[test]
+    fn frame_hook_sees_every_nth_headless_frame() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let scene_path = std::env::temp_dir().join("efield-frame-hook-test.json");
+        let game = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(100.0, 120.0, CHARGE_STEP),
+                    ChargedParticle::new(220.0, 120.0, -CHARGE_STEP),
+                ],
+                ..Default::default()
+            },
+            scene_path: scene_path.clone(),
+            ..Default::default()
+        };
+        game.save_scene().unwrap();
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let hooks = Hooks {
+            on_frame: Some(Box::new({
+                let seen = seen.clone();
+                move |pixels: &[u8], width, height| {
+                    let lit = pixels
+                        .chunks(4)
+                        .filter(|pixel| pixel[..3] != [0; 3])
+                        .count();
+                    seen.borrow_mut().push((pixels.len(), width, height, lit));
+                }
+            })),
+            frame_stride: 2,
+            ..Default::default()
+        };
+        let config = Config {
+            width: 320,
+            height: 240,
+            scene_path: Some(scene_path.clone()),
+            ..Default::default()
+        };
+        render_frames(config, 5, hooks).unwrap();
+        fs::remove_file(scene_path).unwrap();
+
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 3);
+        for &(len, width, height, lit) in seen.iter() {
+            assert_eq!((len, width, height), (320 * 240 * 4, 320, 240));
+            assert!(lit > 0);
+        }
+    }
+
+    #[test]
+    fn piped_frames_are_whole() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let scene_path = std::env::temp_dir().join("efield-piped-frames-test.json");
+        let game = Game {
+            scene: Scene {
+                particles: vec![ChargedParticle::new(60.0, 40.0, CHARGE_STEP)],
+                ..Default::default()
+            },
+            scene_path: scene_path.clone(),
+            ..Default::default()
+        };
+        game.save_scene().unwrap();
+
+        let chunks = Rc::new(RefCell::new(Vec::new()));
+        let hooks = Hooks {
+            on_frame: Some(Box::new({
+                let chunks = chunks.clone();
+                move |pixels: &[u8], _, _| chunks.borrow_mut().push(pixels.len())
+            })),
+            frames_on_stdout: true,
+            ..Default::default()
+        };
+        let config = Config {
+            width: 160,
+            height: 120,
+            scene_path: Some(scene_path.clone()),
+            ..Default::default()
+        };
+        render_frames(config, 4, hooks).unwrap();
+        fs::remove_file(scene_path).unwrap();
+
+        assert_eq!(*chunks.borrow(), vec![160 * 120 * 4; 4]);
+    }
+
+    #[test]
+    fn placement_preview_agrees_with_placing() {
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![ChargedParticle::new(200.0, 150.0, CHARGE_STEP)],
+                ..Default::default()
+            },
+            current_selected_charge: 6.0 * CHARGE_STEP,
+            play_area: (400.0, 300.0),
+            ..Default::default()
+        };
+        game.handle_zoom(200, 150, 1);
+        let verdict = |game: &Game, x, y| game.check_placements(&game.get_placements(x, y));
+
+        // The preview is as big as the particle will be drawn, zoom and charge included
+        let placement = game.get_placements(10, 10)[0];
+        let radius = game.get_screen_radius(&placement);
+        assert!(radius > game.scene.particle_radius(&placement));
+
+        let (center_x, center_y) = game.view.to_screen((200.0, 150.0));
+        let (center_x, center_y) = (center_x as i32, center_y as i32);
+        let near_miss = center_x + radius as i32;
+        assert!(verdict(&game, center_x, center_y).is_ok());
+        let overlap = verdict(&game, near_miss, center_y).unwrap_err();
+        assert!(
+            overlap.contains("overlap the particle at (200, 150)"),
+            "{overlap}"
+        );
+        let toolbar = verdict(&game, 399, 150).unwrap_err();
+        assert!(toolbar.contains("toolbar"), "{toolbar}");
+        let status_bar = verdict(&game, 100, 299).unwrap_err();
+        assert!(status_bar.contains("status bar"), "{status_bar}");
+        let edge = verdict(&game, 1, 150).unwrap_err();
+        assert!(edge.contains("edge"), "{edge}");
+
+        // Every click is placed exactly when the preview says it would be, snapped and mirrored
+        // or not
+        for (snap_to_grid, mirror) in [(false, None), (true, None), (true, Some(MirrorAxes::Both))]
+        {
+            game.snap_to_grid = snap_to_grid;
+            game.mirror = mirror;
+            let mut outcomes = [0, 0];
+
+            for x in (0..400).step_by(9) {
+                for y in (0..300).step_by(9) {
+                    let accepted = verdict(&game, x, y).is_ok();
+                    let before = game.scene.particles.clone();
+                    game.handle_mouse_down(x, y);
+
+                    assert_eq!(game.scene.particles != before, accepted, "({x}, {y})");
+                    if accepted {
+                        game.undo();
+                    }
+                    assert_eq!(game.scene.particles, before);
+                    outcomes[accepted as usize] += 1;
+                }
+            }
+
+            assert!(outcomes.iter().all(|&count| count > 0), "{outcomes:?}");
+        }
+    }
+
+    #[test]
+    fn hydrogen_preset_switches_to_atomic_units_and_back() {
+        let mut game = Game {
+            play_area: (800.0, 600.0),
+            ..Default::default()
+        };
+        game.handle_keydown(Keycode::F5, Mod::NOMOD);
+        assert!(game.simulating);
+
+        let [nucleus, electron] = [game.scene.particles[0], game.scene.particles[1]];
+        assert!(nucleus.pinned);
+        let radius = electron.distance_to(nucleus.pos()) * game.scene.meters_per_pixel;
+        assert_eq!(format_si(radius, "m"), "52.9 pm");
+        // A third of the 152 as orbit goes by each second
+        assert_eq!(format_si(game.scene.time_scale, "s"), "50.7 as");
+
+        // So a second of frames takes the electron a third of the way round
+        for _ in 0..60 {
+            game.scene.step_dynamics(game.scene.time_scale / 60.0);
+        }
+        let (x, y) = game.scene.particles[1].pos();
+        let angle = (y - nucleus.y).atan2(x - nucleus.x).to_degrees();
+        assert!((angle - 120.0).abs() < 1.0, "{angle}");
+
+        game.handle_keydown(Keycode::F1, Mod::NOMOD);
+        let defaults = Scene::default();
+        assert_eq!(game.scene.meters_per_pixel, defaults.meters_per_pixel);
+        assert_eq!(game.scene.particle_mass, defaults.particle_mass);
+        assert_eq!(game.scene.time_scale, 1.0);
+        assert_eq!(game.scene.max_timestep, None);
+        assert!(!game.simulating);
+
+        // Undoing an edit to the preset keeps its scale, but undoing the preset itself or
+        // clearing it puts the configured one back
+        let assert_configured = |game: &Game| {
+            assert_eq!(game.scene.meters_per_pixel, defaults.meters_per_pixel);
+            assert_eq!(game.scene.particle_mass, defaults.particle_mass);
+            assert_eq!(game.scene.time_scale, 1.0);
+            assert_eq!(game.scene.max_timestep, None);
+            assert!(!game.simulating);
+            assert!(game.replaced_scale.is_none());
+            assert!(game
+                .notice
+                .as_ref()
+                .is_none_or(|notice| notice.text != CLASSICAL_NOTE));
+        };
+        let dipole = game.scene.particles.clone();
+        game.handle_keydown(Keycode::F5, Mod::NOMOD);
+        game.change_particle_charge(1, -CHARGE_STEP);
+        game.handle_keydown(Keycode::Z, Mod::LCTRLMOD);
+        assert_eq!(format_si(game.scene.time_scale, "s"), "50.7 as");
+        assert!(game.simulating);
+        game.handle_keydown(Keycode::Z, Mod::LCTRLMOD);
+        assert_eq!(game.scene.particles, dipole);
+        assert_configured(&game);
+
+        game.handle_keydown(Keycode::F5, Mod::NOMOD);
+        game.handle_keydown(Keycode::C, Mod::LCTRLMOD | Mod::LSHIFTMOD);
+        assert!(game.scene.particles.is_empty());
+        assert_configured(&game);
+
+        assert_eq!(format_si(0.5, "m"), "500 mm");
+        assert_eq!(format_si(4.0, "m"), "4.00 m");
+        assert_eq!(format_si(-2.5e-11, "m"), "-25.0 pm");
+        assert_eq!(format_si(0.0, "m"), "0 m");
+    }
+
+    #[test]
+    fn combs_are_placed_edited_and_saved() {
+        let mut game = Game {
+            play_area: (800.0, 600.0),
+            comb_lines: 4,
+            ..Default::default()
+        };
+        game.scene.particles = vec![ChargedParticle::new(100.0, 300.0, CHARGE_STEP)];
+
+        game.begin_comb(400, 200);
+        game.handle_mouse_up(400, 400);
+        assert_eq!(
+            game.scene.combs,
+            [Comb::new((400.0, 200.0), (400.0, 400.0), 4)]
+        );
+        game.trace_visible_lines();
+        let comb_lines: Vec<&FieldLine> = game
+            .field_lines
+            .iter()
+            .filter(|line| line.comb == Some(0))
+            .collect();
+        assert_eq!(comb_lines.len(), 8);
+        let color = get_line_color(&game.scene, &game.theme, false, comb_lines[0]);
+        assert_eq!(color, COMB_COLOR);
+
+        // Scrolling over it seeds more lines, and the move tool stretches it by an end or moves
+        // all of it
+        game.handle_scroll(400, 300, 2, Mod::NOMOD);
+        assert_eq!(game.scene.combs[0].lines, 6);
+        game.begin_drag(400, 400);
+        assert!(game.dragging == Some(Hit::CombEnd(0, true)));
+        game.handle_drag(500, 450);
+        game.handle_mouse_up(500, 450);
+        assert_eq!(game.scene.combs[0].end, (500.0, 450.0));
+        game.begin_drag(450, 325);
+        assert!(game.dragging == Some(Hit::Comb(0)));
+        game.handle_drag(460, 335);
+        game.handle_mouse_up(460, 335);
+        let comb = game.scene.combs[0].clone();
+        assert_eq!((comb.start, comb.end), ((410.0, 210.0), (510.0, 460.0)));
+
+        let saved: Game = serde_json::from_str(&serde_json::to_string(&game).unwrap()).unwrap();
+        assert_eq!(saved.scene.combs, game.scene.combs);
+
+        game.handle_erase(410, 210);
+        assert!(game.scene.combs.is_empty());
+        game.undo();
+        assert_eq!(game.scene.combs, [comb]);
+    }
+
+    #[test]
+    fn double_clicks_flip_particles_without_merging() {
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(100.0, 100.0, 2.0 * CHARGE_STEP),
+                    ChargedParticle::new(300.0, 100.0, 0.0),
+                ],
+                ..Default::default()
+            },
+            current_selected_charge: CHARGE_STEP,
+            play_area: (400.0, 300.0),
+            ..Default::default()
+        };
+
+        // Both clicks go through the placement tool the way the run loop sends them
+        let click = |game: &mut Game, x, y, timestamp| {
+            if !game.handle_double_click(x, y, timestamp) {
+                game.handle_mouse_down(x, y);
+            }
+        };
+
+        click(&mut game, 100, 100, 1000);
+        assert_eq!(game.scene.particles[0].charge, 3.0 * CHARGE_STEP);
+        click(&mut game, 100, 100, 1000 + DOUBLE_CLICK_MS);
+        assert_eq!(game.scene.particles[0].charge, -2.0 * CHARGE_STEP);
+
+        // Too slow to count, so it's two placements
+        click(&mut game, 100, 100, 5000);
+        click(&mut game, 100, 100, 5001 + DOUBLE_CLICK_MS);
+        assert_eq!(game.scene.particles[0].charge, 0.0);
+
+        click(&mut game, 300, 100, 9000);
+        click(&mut game, 300, 100, 9100);
+        assert_eq!(game.scene.particles[1].charge, 0.0);
+        assert_eq!(game.scene.particles.len(), 2);
+
+        assert!(Tool::Move.flips_on_double_click());
+        assert!(!Tool::Measure.flips_on_double_click());
+        assert!(!Tool::Erase.flips_on_double_click());
+    }
+
+    #[test]
+    fn typed_charges_set_the_placement_or_selected_charge() {
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![ChargedParticle::new(0.0, 0.0, CHARGE_STEP)],
+                ..Default::default()
+            },
+            current_selected_charge: CHARGE_STEP,
+            ..Default::default()
+        };
+        let type_keys = |game: &mut Game, keys: &[Keycode]| {
+            game.handle_keydown(Keycode::C, Mod::NOMOD);
+            for &keycode in keys {
+                game.handle_charge_entry_keydown(keycode);
+            }
+        };
+
+        type_keys(
+            &mut game,
+            &[Keycode::Minus, Keycode::Num7, Keycode::Period, Keycode::Kp5],
+        );
+        assert!(game.charge_entry.is_some());
+        game.handle_charge_entry_keydown(Keycode::Return);
+        assert_eq!(game.charge_entry, None);
+        assert_eq!(game.current_selected_charge, -7.5 * CHARGE_STEP);
+
+        // Keys that aren't part of a number are swallowed rather than toggling anything
+        type_keys(
+            &mut game,
+            &[Keycode::Num1, Keycode::N, Keycode::Period, Keycode::Num2],
+        );
+        assert_eq!(game.charge_entry.as_deref(), Some("1.2"));
+        for keycode in [Keycode::Period, Keycode::Num3, Keycode::Return] {
+            game.handle_charge_entry_keydown(keycode);
+        }
+        assert_eq!(game.current_selected_charge, -7.5 * CHARGE_STEP);
+
+        assert!(game.notice.as_ref().is_some_and(|notice| notice.error));
+
+        type_keys(&mut game, &[Keycode::Num4, Keycode::Escape]);
+        assert_eq!(game.charge_entry, None);
+        assert_eq!(game.current_selected_charge, -7.5 * CHARGE_STEP);
+
+        game.selected = Some(0);
+        type_keys(&mut game, &[Keycode::Num3, Keycode::KpEnter]);
+        assert_eq!(game.scene.particles[0].charge, 3.0 * CHARGE_STEP);
+        assert_eq!(game.current_selected_charge, -7.5 * CHARGE_STEP);
+    }
+
+    #[test]
+    fn holding_alt_previews_the_selected_particle() {
+        let mut game = Game {
+            prediction_horizon: 0.5,
+            prediction_tick: DEFAULT_PREDICTION_TICK,
+            selected: Some(0),
+            ..Default::default()
+        };
+        game.scene.meters_per_pixel = DEFAULT_METERS_PER_PIXEL;
+        game.scene.particles = vec![
+            ChargedParticle::new(100.0, 100.0, -CHARGE_STEP),
+            ChargedParticle::new(200.0, 100.0, CHARGE_STEP),
+        ];
+
+        game.refresh_prediction();
+        assert!(game.prediction.is_none());
+
+        game.handle_keydown(Keycode::LAlt, Mod::LALTMOD);
+        game.refresh_prediction();
+        let Prediction {
+            index, path: first, ..
+        } = game.prediction.clone().unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(first.len(), 31);
+        assert!(first[30].0 > 100.0);
+
+        // Nudging the particle integrates the preview again from where it is now
+        game.handle_keydown(Keycode::Equals, Mod::NOMOD);
+        game.refresh_prediction();
+        assert_ne!(game.prediction.as_ref().unwrap().path, first);
+
+        game.handle_keyup(Keycode::LAlt);
+        game.refresh_prediction();
+        assert!(game.prediction.is_none());
+    }
+
+    #[test]
+    fn mirror_mode_places_symmetric_copies() {
+        let mut game = Game {
+            play_area: (400.0, 300.0),
+            current_selected_charge: CHARGE_STEP,
+            mirror: Some(MirrorAxes::Both),
+            mirror_antisymmetric: true,
+            ..Default::default()
+        };
+
+        game.handle_mouse_down(150, 100);
+        assert_eq!(
+            game.scene.particles,
+            vec![
+                ChargedParticle::new(150.0, 100.0, CHARGE_STEP),
+                ChargedParticle::new(250.0, 100.0, -CHARGE_STEP),
+                ChargedParticle::new(150.0, 200.0, -CHARGE_STEP),
+                ChargedParticle::new(250.0, 200.0, CHARGE_STEP),
+            ]
+        );
+
+        // On the vertical axis the only copy is the one across the horizontal axis
+        game.mirror = Some(MirrorAxes::Vertical);
+        game.mirror_antisymmetric = false;
+        game.handle_mouse_down(200, 50);
+        game.mirror = Some(MirrorAxes::Horizontal);
+        game.handle_mouse_down(200, 20);
+        assert_eq!(
+            game.scene.particles[4..],
+            [
+                ChargedParticle::new(200.0, 50.0, CHARGE_STEP),
+                ChargedParticle::new(200.0, 20.0, CHARGE_STEP),
+                ChargedParticle::new(200.0, 280.0, CHARGE_STEP),
+            ]
+        );
+
+        // The copies aren't linked, removing one leaves the rest
+        game.remove_particle(1);
+        assert_eq!(game.scene.particles.len(), 6);
+        assert_eq!(game.scene.particles[1].pos(), (150.0, 200.0));
+    }
+
+    #[test]
+    fn selection_follows_its_particle_and_crosses_zero() {
+        let mut game = Game {
+            scene: Scene {
+                particles: (0..3)
+                    .map(|i| ChargedParticle::new(100.0 * i as f64, 0.0, CHARGE_STEP))
+                    .collect(),
+                ..Default::default()
+            },
+            selected: Some(2),
+            ..Default::default()
+        };
+
+        game.remove_particle(0);
+        assert_eq!(game.selected, Some(1));
+
+        game.handle_keydown(Keycode::Minus, Mod::NOMOD);
+        game.handle_keydown(Keycode::Minus, Mod::NOMOD);
+        assert_eq!(game.scene.particles[1].charge, -CHARGE_STEP);
+        assert_eq!(game.current_selected_charge, 0.0);
+
+        game.handle_keydown(Keycode::Delete, Mod::NOMOD);
+        assert_eq!(game.selected, None);
+        assert_eq!(
+            game.scene.particles,
+            vec![ChargedParticle::new(100.0, 0.0, CHARGE_STEP)]
+        );
+    }
+
+    #[test]
+    fn modifiers_change_the_charge_step() {
+        let mut game = Game {
+            current_selected_charge: 0.0,
+            ..Default::default()
+        };
+
+        game.handle_keydown(Keycode::Equals, Mod::LCTRLMOD);
+        assert!((game.current_selected_charge - 0.1 * CHARGE_STEP).abs() < CHARGE_SNAP);
+
+        game.handle_keydown(Keycode::Minus, Mod::RCTRLMOD);
+        assert_eq!(game.current_selected_charge, 0.0);
+
+        game.handle_keydown(Keycode::Equals, Mod::LSHIFTMOD);
+        assert_eq!(game.current_selected_charge, 10.0 * CHARGE_STEP);
+
+        // Stepping a selected particle below zero in fine steps lands exactly on zero on the way
+        game.scene
+            .add_particle(ChargedParticle::new(0.0, 0.0, 0.3 * CHARGE_STEP));
+        game.selected = Some(0);
+        for _ in 0..3 {
+            game.handle_keydown(Keycode::Minus, Mod::LCTRLMOD);
+        }
+        assert_eq!(game.scene.particles[0].charge, 0.0);
+        assert_eq!(game.current_selected_charge, 10.0 * CHARGE_STEP);
+    }
+
+    #[test]
+    fn number_keys_select_tools() {
+        let mut toolbar = Toolbar::default();
+
+        assert_eq!(
+            toolbar.select_index(tool_key_index(Keycode::Num2).unwrap()),
+            Some(Tool::Place(Particle::Negative))
+        );
+        assert_eq!(
+            toolbar.select_index(tool_key_index(Keycode::Kp5).unwrap()),
+            Some(Tool::Erase)
+        );
+
+        // Keys past the last tool leave the selection alone
+        assert_eq!(toolbar.select_index(toolbar.choices.len()), None);
+        assert_eq!(toolbar.get_selected_option(), Some(Tool::Erase));
+        assert_eq!(tool_key_index(Keycode::Num0), None);
+    }
+
+    #[test]
+    fn measurements_follow_particles() {
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(0.0, 0.0, 2.0 * CHARGE_STEP),
+                    ChargedParticle::new(100.0, 0.0, -CHARGE_STEP),
+                ],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        game.measure_at(0, 0);
+        game.measure_at(100, 0);
+        let measurement = game.get_measurement().unwrap();
+        let r = 100.0 * game.scene.meters_per_pixel;
+        assert_eq!(measurement.distance, 100.0);
+        assert_eq!(
+            measurement.force,
+            Some(-2.0 * COULUMBS_CONST * CHARGE_STEP * CHARGE_STEP / (r * r))
+        );
+
+        // Dragging an end moves the measurement along with it
+        game.scene.particles[1].x = 50.0;
+        assert_eq!(game.get_measurement().unwrap().distance, 50.0);
+
+        // Removing an end leaves it where the particle was, and there's no force without it
+        game.remove_particle(0);
+        let measurement = game.get_measurement().unwrap();
+        assert_eq!(
+            (measurement.start, measurement.end),
+            ((0.0, 0.0), (50.0, 0.0))
+        );
+        assert_eq!(measurement.force, None);
+
+        // Both ends on the same particle measure nothing rather than an infinite force
+        game.measure_at(50, 0);
+        game.measure_at(50, 0);
+        let measurement = game.get_measurement().unwrap();
+        assert_eq!(measurement.distance, 0.0);
+        assert_eq!(measurement.potential_difference, 0.0);
+        assert_eq!(measurement.force, None);
+    }
+
+    #[test]
+    fn config_files_fill_in_defaults() {
+        let config = Config::from_toml(
+            "width = 1024\nunknown = true\n\n[colors]\npositive = [230, 159, 0]\n",
+        );
+
+        assert_eq!(config.width, 1024);
+        assert_eq!(config.height, Config::default().height);
+        assert_eq!(config.colors.positive, Some([230, 159, 0]));
+        assert_eq!(
+            config.colors.apply(Theme::LIGHT).positive,
+            Color::RGB(230, 159, 0)
+        );
+        assert_eq!(
+            config.colors.apply(Theme::LIGHT).negative,
+            Theme::LIGHT.negative
+        );
+
+        assert_eq!(config.problems, ["Unknown key `unknown`, ignored"]);
+
+        // What's dumped reads back the same
+        let dumped = Config::from_toml(&config.to_toml().unwrap());
+        assert!(dumped.problems.is_empty(), "{:?}", dumped.problems);
+        assert_eq!(
+            dumped,
+            Config {
+                problems: Vec::new(),
+                ..config
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_config_keys_fall_back_one_by_one() {
+        let config = Config::from_toml(
+            "width = 1024\n\
+             height = \"tall\"\n\
+             particle_radius = -1.0\n\
+             max_line_iters = 0\n\
+             record_stride = 3\n\
+             colorblind = true\n\
+             \n\
+             [colors]\n\
+             positive = [230, 159, 0]\n\
+             negative = \"blue\"\n\
+             line = [1, 2, 300]\n",
+        );
+
+        // Everything that was fine is kept
+        assert_eq!(config.width, 1024);
+        assert_eq!(config.record_stride, 3);
+        assert!(config.colorblind);
+        assert_eq!(config.colors.positive, Some([230, 159, 0]));
+
+        let defaults = Config::default();
+        assert_eq!(config.height, defaults.height);
+        assert_eq!(config.particle_radius, defaults.particle_radius);
+        assert_eq!(config.max_line_iters, defaults.max_line_iters);
+        assert_eq!(config.colors.negative, None);
+        assert_eq!(config.colors.line, None);
+
+        // And each of the rest is reported once
+        assert_eq!(config.problems.len(), 5, "{:?}", config.problems);
+        for key in [
+            "`height`",
+            "`particle_radius`",
+            "`max_line_iters`",
+            "`colors.negative`",
+            "`colors.line`",
+        ] {
+            assert_eq!(
+                config.problems.iter().filter(|p| p.contains(key)).count(),
+                1,
+                "{key} in {:?}",
+                config.problems
+            );
+        }
+
+        // A file that isn't TOML can't be salvaged, but still starts with the defaults
+        let config = Config::from_toml("width = 1024\nheight = [\n");
+        assert_eq!(config.width, defaults.width);
+        assert_eq!(config.problems.len(), 1);
+        assert!(
+            config.problems[0].contains("line 2"),
+            "{:?}",
+            config.problems
+        );
+    }
+
+    #[test]
+    fn theme_toggles_are_independent() {
+        let theme = Theme::default().colorblind_toggled();
+        assert_eq!(theme, Theme::DARK_COLORBLIND);
+        assert_eq!(theme.toggled(), Theme::LIGHT_COLORBLIND);
+        assert_eq!(theme.toggled().colorblind_toggled(), Theme::LIGHT);
+        assert_eq!(theme.toggled().toggled(), theme);
+    }
+
+    #[test]
+    fn svg_export_is_well_formed() {
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(150.0, 150.0, CHARGE_STEP),
+                    ChargedParticle::new(250.0, 150.0, -CHARGE_STEP),
+                ],
+                ..Default::default()
+            },
+            play_area: (400.0, 300.0),
+            show_equipotentials: true,
+            ..Default::default()
+        };
+        game.trace_visible_lines();
+
+        let mut svg = Vec::new();
+        game.get_svg_snapshot().write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+
+        assert!(svg.starts_with("<svg ") && svg.contains("viewBox=\"0 0 400 300\""));
+        assert_eq!(svg.matches("<polyline ").count(), game.field_lines.len());
+        assert_eq!(svg.matches("<circle ").count(), 2);
+
+        // Every element is self-closing apart from the root, so the tags balance if each one is
+        // closed and the root is closed last
+        let tags: Vec<&str> = svg
+            .split('<')
+            .skip(1)
+            .map(|tag| tag.split('>').next().unwrap())
+            .collect();
+        assert_eq!(tags.last(), Some(&"/svg"));
+        assert!(tags[1..tags.len() - 1].iter().all(|tag| tag.ends_with('/')));
+        assert!(!svg.contains("NaN") && !svg.contains("inf"));
+    }
+
+    #[test]
+    fn paste_repeats_merges_and_stays_off_the_toolbar() {
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![ChargedParticle::new(50.0, 50.0, -3.0 * CHARGE_STEP)],
+                ..Default::default()
+            },
+            play_area: (720.0, 600.0),
+            selected: Some(0),
+            ..Default::default()
+        };
+
+        game.handle_keydown(Keycode::C, Mod::LCTRLMOD);
+        for x in [100, 200] {
+            game.handle_keydown(Keycode::V, Mod::LCTRLMOD);
+            assert!(game.pasting);
+            game.paste_at(x, 300);
+            assert!(!game.pasting);
+        }
+
+        game.handle_keydown(Keycode::V, Mod::LCTRLMOD);
+        game.paste_at(750, 300);
+        assert!(game.pasting);
+
+        game.paste_at(200, 300);
+
+        assert_eq!(
+            game.scene.particles,
+            vec![
+                ChargedParticle::new(50.0, 50.0, -3.0 * CHARGE_STEP),
+                ChargedParticle::new(100.0, 300.0, -3.0 * CHARGE_STEP),
+                ChargedParticle::new(200.0, 300.0, -6.0 * CHARGE_STEP),
+            ]
+        );
+    }
+
+    #[test]
+    fn far_away_segments_are_clipped_to_gfx_coordinates() {
+        let size = (800, 600);
+
+        // A zoomed in line charge reaching far past both sides keeps its direction
+        let (start, end) = clip_segment((-1e6, 300.0), (1e6, 310.0), size).unwrap();
+        assert_eq!((start.0, end.0), (-16, 816));
+        assert!((start.1 - 305).abs() <= 1 && (end.1 - 305).abs() <= 1);
+
+        assert_eq!(clip_segment((100.0, 1e9), (200.0, 2e9), size), None);
+        assert_eq!(
+            clip_segment((10.0, 20.0), (30.0, 40.0), size),
+            Some(((10, 20), (30, 40)))
+        );
+        assert_eq!(to_gfx_point((40000.0, 0.0)), None);
+    }
+
+    #[test]
+    fn charge_labels_use_units_of_e() {
+        assert_eq!(format_charge(3.0 * CHARGE_STEP), "+3e");
+        assert_eq!(format_charge(-CHARGE_STEP), "\u{2212}1e");
+        assert_eq!(format_charge(0.5 * CHARGE_STEP), "+0.5e");
+        assert_eq!(format_charge(-2.25 * CHARGE_STEP), "\u{2212}2.3e");
+        assert_eq!(format_charge(0.0), "0");
+    }
+
+    #[test]
+    fn arrowheads_are_spaced_along_arc_length() {
+        let bent = [(0.0, 0.0), (100.0, 0.0), (100.0, 150.0)];
+        let arrowheads = get_arrowheads(&bent, 80.0);
+
+        assert_eq!(
+            arrowheads,
+            vec![
+                ((80.0, 0.0), (1.0, 0.0)),
+                ((100.0, 60.0), (0.0, 1.0)),
+                ((100.0, 140.0), (0.0, 1.0)),
+            ]
+        );
+
+        // Short lines get one arrowhead in the middle, and points get none
+        assert_eq!(
+            get_arrowheads(&[(10.0, 10.0), (10.0, 0.0)], 80.0),
+            vec![((10.0, 5.0), (0.0, -1.0))]
+        );
+        assert!(get_arrowheads(&[(10.0, 10.0), (10.0, 10.0)], 80.0).is_empty());
+    }
+
+    #[test]
+    fn zoom_keeps_cursor_world_position_fixed() {
+        let mut view = View {
+            offset: (-35.0, 12.5),
+            ..Default::default()
+        };
+
+        let cursor = (310.0, 220.0);
+        let before = view.to_world(cursor);
+        view.zoom_at(cursor, ZOOM_STEP.powi(30));
+        let after = view.to_world(cursor);
+
+        assert_eq!(view.scale, MAX_ZOOM);
+        assert!((before.0 - after.0).abs() < 1e-9 && (before.1 - after.1).abs() < 1e-9);
+
+        let screen = view.to_screen((1.0, 2.0));
+        let world = view.to_world(screen);
+        assert!((world.0 - 1.0).abs() < 1e-9 && (world.1 - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn heatmap_colors_clamp_at_the_ends_of_the_range() {
+        let range = (2.0, 4.0);
+
+        assert_eq!(heatmap_color(Some(2.0), range), [0, 0, 255]);
+        assert_eq!(heatmap_color(Some(4.0), range), [255, 0, 0]);
+        assert_eq!(heatmap_color(Some(3.0), range), [127, 0, 127]);
+        assert_eq!(heatmap_color(Some(-10.0), range), [0, 0, 255]);
+        assert_eq!(heatmap_color(Some(10.0), range), [255, 0, 0]);
+        assert_eq!(heatmap_color(None, range), [255, 0, 0]);
+        // A range that's closed up doesn't divide by zero
+        assert_eq!(heatmap_color(Some(2.0), (2.0, 2.0)), [0, 0, 255]);
+    }
+
+    #[test]
+    fn locked_heatmap_range_recolors_without_resampling() {
+        let mut game = Game {
+            play_area: (200.0, 300.0),
+            show_heatmap: true,
+            ..Default::default()
+        };
+        game.scene.particles = vec![ChargedParticle::new(50.0, 150.0, CHARGE_STEP)];
+        game.sample_heatmap();
+        let sampled = game.heatmap_samples.as_ref().unwrap().range;
+        let auto_pixels = game.get_heatmap_pixels(game.heatmap_samples.as_ref().unwrap());
+
+        // Clicking the middle of the legend locks the range shown
+        let rect = game.get_legend_rect();
+        let middle = (rect.center().x(), rect.center().y());
+        assert!(game.begin_legend_drag(middle.0, middle.1));
+        game.handle_mouse_up(middle.0, middle.1);
+        assert_eq!(
+            game.heatmap_range,
+            HeatmapRange {
+                locked: true,
+                min: sampled.0,
+                max: sampled.1,
+            }
+        );
+        let samples = game.heatmap_samples.as_ref().unwrap();
+        assert_eq!(game.get_heatmap_pixels(samples), auto_pixels);
+
+        // Dragging the top down a quarter of the way takes a quarter off the top of the range
+        assert!(game.begin_legend_drag(middle.0, rect.top()));
+        game.handle_legend_drag(rect.top() + rect.height() as i32 / 4);
+        game.handle_mouse_up(middle.0, rect.top());
+        let expected_max = sampled.1 - (sampled.1 - sampled.0) / 4.0;
+        assert!((game.heatmap_range.max - expected_max).abs() < 1e-9);
+        assert!(game.heatmap_range.locked);
+
+        // The same samples are colored against the new range, hotter than before
+        game.sample_heatmap();
+        let samples = game.heatmap_samples.as_ref().unwrap();
+        assert_eq!(samples.range, sampled);
+        let pixels = game.get_heatmap_pixels(samples);
+        assert!(pixels
+            .chunks(3)
+            .zip(auto_pixels.chunks(3))
+            .all(|(locked, auto)| locked[0] >= auto[0]));
+        assert_ne!(pixels, auto_pixels);
+
+        // The bottom can't be dragged past the top
+        assert!(game.begin_legend_drag(middle.0, rect.bottom()));
+        game.handle_legend_drag(-1000);
+        assert!((game.heatmap_range.max - game.heatmap_range.min - MIN_HEATMAP_RANGE).abs() < 1e-9);
+
+        // The locked range is saved with the scene
+        let saved: Game = serde_json::from_str(&serde_json::to_string(&game).unwrap()).unwrap();
+        assert_eq!(saved.heatmap_range, game.heatmap_range);
+
+        // Clicking again lets it follow the samples
+        game.handle_mouse_up(middle.0, -1000);
+        assert!(game.begin_legend_drag(middle.0, middle.1));
+        game.handle_mouse_up(middle.0, middle.1);
+        assert!(!game.heatmap_range.locked);
+        let samples = game.heatmap_samples.as_ref().unwrap();
+        assert_eq!(game.get_heatmap_pixels(samples), auto_pixels);
+    }
+
+    #[test]
+    fn multipole_error_heatmap_fades_far_from_the_charges() {
+        let mut game = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(380.0, 300.0, CHARGE_STEP),
+                    ChargedParticle::new(420.0, 300.0, -CHARGE_STEP),
+                ],
+                ..Default::default()
+            },
+            multipole_order: Some(1),
+            ..Default::default()
+        };
+        let moments = game.scene.get_multipole_moments();
+        let near = (400.0, 340.0);
+        let far = (400.0, 1300.0);
+
+        let exact_near = game.get_heatmap_magnitude(near, &moments);
+        game.show_multipole_error = true;
+        let error_near = game.get_heatmap_magnitude(near, &moments);
+        let error_far = game.get_heatmap_magnitude(far, &moments);
+
+        assert!(error_near < exact_near);
+        assert!(error_far < error_near * 1e-3);
+
+        game.multipole_order = None;
+        assert_eq!(game.get_heatmap_magnitude(near, &moments), exact_near);
+        assert_eq!(game.get_moment_lines().len(), 4);
+    }
+
+    #[test]
+    fn merged_scenes_land_offset_and_undo_in_one_step() {
+        let plates_path = std::env::temp_dir().join("efield-merge-plates-test.json");
+        let pair_path = std::env::temp_dir().join("efield-merge-pair-test.json");
+        let plates = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(100.0, 100.0, CHARGE_STEP),
+                    ChargedParticle::new(300.0, 100.0, CHARGE_STEP),
+                ],
+                lines: vec![LineCharge::new(
+                    (100.0, 200.0),
+                    (300.0, 200.0),
+                    -2.0 * CHARGE_STEP,
+                )],
+                ..Default::default()
+            },
+            scene_path: plates_path.clone(),
+            ..Default::default()
+        };
+        plates.save_scene().unwrap();
+        let pair = Game {
+            scene: Scene {
+                particles: vec![
+                    ChargedParticle::new(0.0, 0.0, -CHARGE_STEP),
+                    ChargedParticle::new(200.0, 0.0, CHARGE_STEP),
+                ],
+                ..Default::default()
+            },
+            scene_path: pair_path.clone(),
+            ..Default::default()
+        };
+        pair.save_scene().unwrap();
+
+        let mut game = Game {
+            scene_path: plates_path.clone(),
+            merge_path: pair_path.clone(),
+            ..Default::default()
+        };
+        game.load_scene().unwrap();
+        let original = (game.scene.particles.clone(), game.scene.lines.clone());
+
+        let imported = Snapshot::read(&pair_path).unwrap();
+        assert_eq!(imported.get_center(), Some((100.0, 0.0)));
+        assert_eq!(game.merge_charges(imported, (50.0, 400.0)), 2);
+        assert_eq!(
+            game.scene.particles[2..],
+            [
+                ChargedParticle::new(50.0, 400.0, -CHARGE_STEP),
+                ChargedParticle::new(250.0, 400.0, CHARGE_STEP),
+            ]
+        );
+        let after_first = game.scene.particles.clone();
+
+        // Centered on the cursor, both land on the plate charges and merge into them
+        assert_eq!(game.merge_scene_at(200, 100).unwrap(), 2);
+        fs::remove_file(plates_path).unwrap();
+        fs::remove_file(pair_path).unwrap();
+        assert_eq!(game.scene.particles.len(), 4);
+        assert_eq!(game.scene.particles[0].charge, 0.0);
+        assert_eq!(game.scene.particles[1].charge, 2.0 * CHARGE_STEP);
+        assert_eq!(game.scene.lines, original.1);
+
+        assert!(game.undo());
+        assert_eq!(game.scene.particles, after_first);
+        assert!(game.undo());
+        assert_eq!(
+            (game.scene.particles.clone(), game.scene.lines.clone()),
+            original
+        );
+    }
+
+    #[test]
+    fn batch_edit_table_commits_as_one_undo_step() {
+        let mut game = Game::default();
+        game.scene.particles = vec![
+            ChargedParticle::new(100.0, 100.0, CHARGE_STEP),
+            ChargedParticle::new(200.0, 100.0, CHARGE_STEP),
+            ChargedParticle::new(300.0, 100.0, CHARGE_STEP),
+        ];
+        let original = game.scene.particles.clone();
+        let press = |game: &mut Game, keys: &[(Keycode, Mod)]| {
+            for &(keycode, keymod) in keys {
+                game.handle_batch_edit_keydown(keycode, keymod);
+            }
+        };
+        let plain = |keycode| (keycode, Mod::NOMOD);
+
+        game.toggle_multi_selection(100, 100);
+        game.toggle_multi_selection(300, 100);
+        game.handle_keydown(Keycode::E, Mod::NOMOD);
+        assert!(!game.show_equipotentials);
+        assert_eq!(game.batch_edit.as_ref().unwrap().rows, vec![0, 2]);
+
+        // Typing replaces the cell the cursor lands on
+        press(
+            &mut game,
+            &[
+                plain(Keycode::Num5),
+                plain(Keycode::Num0),
+                plain(Keycode::Tab),
+                plain(Keycode::Tab),
+                plain(Keycode::Minus),
+                plain(Keycode::Num2),
+                plain(Keycode::Tab),
+                plain(Keycode::Num1),
+            ],
+        );
+
+        // Then +10 in the header of x moves both
+        press(
+            &mut game,
+            &[
+                plain(Keycode::Up),
+                (Keycode::Tab, Mod::LSHIFTMOD),
+                (Keycode::Tab, Mod::LSHIFTMOD),
+                (Keycode::Tab, Mod::LSHIFTMOD),
+                (Keycode::Equals, Mod::LSHIFTMOD),
+                plain(Keycode::Num1),
+                plain(Keycode::Num0),
+                plain(Keycode::Return),
+            ],
+        );
+
+        // An invalid cell holds up the commit without losing the rest
+        press(
+            &mut game,
+            &[
+                plain(Keycode::Down),
+                plain(Keycode::Down),
+                plain(Keycode::Tab),
+                plain(Keycode::Minus),
+                (Keycode::Return, Mod::LCTRLMOD),
+            ],
+        );
+        let edit = game.batch_edit.as_ref().unwrap();
+        assert_eq!(edit.invalid_cells(), 1);
+        assert!(!edit.is_valid(1, 1));
+        assert!(game.notice.as_ref().is_some_and(|notice| notice.error));
+        assert_eq!(game.scene.particles, original);
+
+        press(
+            &mut game,
+            &[
+                plain(Keycode::Backspace),
+                plain(Keycode::Num7),
+                (Keycode::Return, Mod::LCTRLMOD),
+            ],
+        );
+        assert!(game.batch_edit.is_none());
+        assert_eq!(
+            game.scene.particles,
+            vec![
+                ChargedParticle {
+                    pinned: true,
+                    ..ChargedParticle::new(60.0, 100.0, -2.0 * CHARGE_STEP)
+                },
+                original[1],
+                ChargedParticle::new(310.0, 7.0, CHARGE_STEP),
+            ]
+        );
+
+        assert_eq!(game.history.len(), 1);
+        assert!(game.undo());
+        assert_eq!(game.scene.particles, original);
+
+        // Escape leaves everything as it was
+        game.toggle_multi_selection(200, 100);
+        game.open_batch_edit();
+        press(&mut game, &[plain(Keycode::Num9), plain(Keycode::Escape)]);
+        assert!(game.batch_edit.is_none());
+        assert_eq!(game.scene.particles, original);
+        assert!(game.history.is_empty());
+    }
+
+    #[test]
+    fn dipoles_edit_and_drag_as_one_object() {
+        let mut game = Game {
+            current_selected_charge: -3.0 * CHARGE_STEP,
+            ..Default::default()
+        };
+        game.place_dipole(200, 100);
+        assert_eq!(
+            game.scene.dipoles,
+            vec![Dipole::new(
+                (200.0, 100.0),
+                3.0 * CHARGE_STEP,
+                DEFAULT_DIPOLE_SEPARATION,
+                0.0
+            )]
+        );
+
+        // Grabbing the negative end selects and moves the whole dipole
+        game.begin_drag(180, 100);
+        assert_eq!(game.selected_dipole, Some(0));
+        game.handle_drag(230, 150);
+        game.handle_mouse_up(230, 150);
+        assert_eq!(game.scene.dipoles[0].center, (250.0, 150.0));
+
+        game.commit_charge_entry(",0.6,90");
+        let dipole = game.scene.dipoles[0];
+        assert_eq!(dipole.charge, 3.0 * CHARGE_STEP);
+        let [positive, negative] = dipole.point_charges();
+        assert!(positive.distance_to((250.0, 180.0)) < 1e-9);
+        assert!(negative.distance_to((250.0, 120.0)) < 1e-9);
+
+        // The handle rotates and stretches it about its center
+        game.begin_drag(250, 180);
+        game.handle_drag(290, 150);
+        let [positive, negative] = game.scene.dipoles[0].point_charges();
+        assert!(positive.distance_to((290.0, 150.0)) < 1e-9);
+        assert!(negative.distance_to((210.0, 150.0)) < 1e-9);
+
+        game.commit_charge_entry("2,-1");
+        assert!(game.notice.as_ref().is_some_and(|notice| notice.error));
+        assert_eq!(game.scene.dipoles[0].separation, 80.0);
+
+        assert!(game.undo());
+        assert_eq!(game.scene.dipoles[0], dipole);
+        game.selected_dipole = Some(0);
+        game.handle_keydown(Keycode::Delete, Mod::NOMOD);
+        assert!(game.scene.dipoles.is_empty());
+    }
+
+    #[test]
+    fn model_changes_refresh_every_consumer_and_camera_moves_none() {
+        let mut game = Game {
+            scene_path: "scene.json".into(),
+            play_area: (800.0, 600.0),
+            ..Default::default()
+        };
+        let every = vec!["field tree", "moments", "title"];
+        assert_eq!(game.refresh_model_consumers(), every);
+        assert!(game.refresh_model_consumers().is_empty());
+        assert_eq!(game.title, "Electric Field Visualizer - scene.json");
+
+        game.handle_pan(40, -25);
+        game.handle_zoom(100, 100, 1);
+        assert!(game.refresh_model_consumers().is_empty());
+        assert_eq!(game.generation, 0);
+
+        game.handle_mouse_down(200, 150);
+        let scene_stale = game.consumers.scene != Some(game.generation);
+        assert!(scene_stale);
+        assert_eq!(game.refresh_model_consumers(), every);
+        assert_eq!(game.moments.total_charge, 0.0);
+        assert!(game.title.ends_with('*'));
+
+        game.current_selected_charge = CHARGE_STEP;
+        game.handle_mouse_down(300, 150);
+        assert_eq!(game.refresh_model_consumers(), every);
+        assert_eq!(game.moments.total_charge, CHARGE_STEP);
+
+        game.mark_saved();
+        assert_eq!(game.refresh_model_consumers(), vec!["title"]);
+        assert!(!game.title.ends_with('*'));
+    }
+
+    #[test]
+    fn field_queries_never_see_charges_from_before_a_change() {
+        let grid: Vec<ChargedParticle> = (0..80)
+            .map(|i| {
+                let (x, y) = (40.0 + 40.0 * (i % 10) as f64, 40.0 + 40.0 * (i / 10) as f64);
+                ChargedParticle::new(
+                    x,
+                    y,
+                    if i % 3 == 0 {
+                        -CHARGE_STEP
+                    } else {
+                        CHARGE_STEP
+                    },
+                )
+            })
+            .collect();
+        let mut game = Game {
+            scene: Scene {
+                particles: grid.clone(),
+                ..Default::default()
+            },
+            play_area: (480.0, 400.0),
+            ..Default::default()
+        };
+
+        // Compared against the exact sum, which the tree would only approximate
+        let assert_current = |game: &mut Game, change: &str| {
+            let mut exact = game.scene.clone();
+            exact.field_tree = None;
+            for point in [(20.0, 20.0), (60.0, 60.0), (250.0, 170.0), (470.0, 390.0)] {
+                assert_eq!(
+                    game.scene.get_field_strength(point.0, point.1),
+                    exact.get_field_strength(point.0, point.1),
+                    "stale field after {change}"
+                );
+            }
+
+            game.refresh_model_consumers();
+        };
+        let refill = |game: &mut Game| {
+            game.scene.particles = grid.clone();
+            game.model_changed();
+            game.refresh_model_consumers();
+            assert!(game.scene.field_tree.is_some());
+        };
+
+        refill(&mut game);
+        game.change_particle_charge(0, CHARGE_STEP);
+        assert_current(&mut game, "changing a charge");
+
+        assert!(!game.handle_double_click(40, 40, 0));
+        assert!(game.handle_double_click(40, 40, 10));
+        assert_current(&mut game, "flipping a charge");
+
+        game.toggle_multi_selection(80, 40);
+        game.handle_keydown(Keycode::E, Mod::NOMOD);
+        game.handle_batch_edit_keydown(Keycode::Num9, Mod::NOMOD);
+        game.handle_batch_edit_keydown(Keycode::Return, Mod::LCTRLMOD);
+        assert!(game.batch_edit.is_none());
+        assert_eq!(game.scene.particles[1].x, 9.0);
+        assert_current(&mut game, "a batch edit");
+
+        game.load_preset(Preset::Dipole);
+        assert_current(&mut game, "loading a preset");
+
+        refill(&mut game);
+        game.clear_scene();
+        assert_current(&mut game, "clearing the scene");
+    }
 }
@@ -1,7 +1,238 @@
-use std::process;
+use std::{
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process,
+};
+
+use electric_field_visualizer::{Config, Hooks, MAX_WINDOW_SIZE};
+
+const USAGE: &str = "\
+Usage: electric-field-visualizer [OPTIONS] [SCENE]
+
+Arguments:
+  [SCENE]            Scene file to load at startup and save to, defaults to scene.json
+
+Options:
+  --width <PIXELS>   Window width [default: 800]
+  --height <PIXELS>  Window height [default: 600]
+  --fullscreen       Start in fullscreen
+  --meters-per-pixel <METERS>
+                     Length of a pixel, which sets the scale of every displayed value
+                     [default: 0.01]
+  --fps <FPS>        Frame rate to aim for if the display doesn't support vsync [default: 60]
+  --line-growth <POINTS>
+                     Points added to each field line per frame when they're animated with A
+                     [default: 20]
+  --record-stride <FRAMES>
+                     Record one in every this many frames to GIFs started with R [default: 4]
+  --colorblind       Start with the colorblind-friendly palette, which also marks signs by shape
+  --verbose          Print feedback on every action to the terminal
+  --config <PATH>    Settings file to read instead of ~/.config/efield/config.toml
+  --dump-config      Print the settings that would be used as TOML and exit
+  --merge <SCENE>    Add the charges of SCENE to the loaded scene, and merge it in at the
+                     cursor with Ctrl+Shift+O
+  --merge-offset <X,Y>
+                     World pixels to move the --merge charges by at startup [default: 0,0]
+  --render <SCENE>   Draw SCENE to the PNG given by --out at the window size and exit, without
+                     opening a window
+  --out <FILE>       Where --render writes its image
+  --pipe-frames      Also write every frame to stdout as raw RGBA, e.g. for
+                     `ffmpeg -f rawvideo -pixel_format rgba -video_size 800x600 -i - out.mp4`.
+                     The window shouldn't be resized while piping, and everything else that
+                     would be printed goes to stderr instead
+  -h, --help         Print this help
+
+Every option can also be set in the settings file, along with the particle_radius,
+lines_per_charge, max_line_iters, comb_lines, prediction_horizon, prediction_tick,
+pause_in_background and [colors] keys. Options given here override it.";
+
+fn parse_size(name: &str, value: Option<String>) -> Result<u32, String> {
+    let value = value.ok_or(format!("{name} needs a value"))?;
+    let size: u32 = value
+        .parse()
+        .map_err(|_| format!("{name} must be a whole number of pixels, got `{value}`"))?;
+
+    if size == 0 || size > MAX_WINDOW_SIZE {
+        return Err(format!(
+            "{name} must be between 1 and {MAX_WINDOW_SIZE} pixels, got {size}"
+        ));
+    }
+
+    Ok(size)
+}
+
+fn parse_count(name: &str, value: Option<String>) -> Result<u32, String> {
+    let value = value.ok_or(format!("{name} needs a value"))?;
+
+    match value.parse::<u32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!(
+            "{name} must be a positive whole number, got `{value}`"
+        )),
+    }
+}
+
+fn parse_scale(value: Option<String>) -> Result<f64, String> {
+    let value = value.ok_or("--meters-per-pixel needs a value")?;
+
+    match value.parse::<f64>() {
+        Ok(scale) if scale > 0.0 && scale.is_finite() => Ok(scale),
+        _ => Err(format!(
+            "--meters-per-pixel must be a positive number, got `{value}`"
+        )),
+    }
+}
+
+fn parse_offset(value: Option<String>) -> Result<(f64, f64), String> {
+    let value = value.ok_or("--merge-offset needs a value")?;
+    let parsed = value
+        .split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse::<f64>().ok()?, y.trim().parse::<f64>().ok()?)));
+
+    match parsed {
+        Some((x, y)) if x.is_finite() && y.is_finite() => Ok((x, y)),
+        _ => Err(format!(
+            "--merge-offset must be two numbers like 100,-50, got `{value}`"
+        )),
+    }
+}
+
+enum Action {
+    Run(Config),
+    DumpConfig(Config),
+    Render(Config, PathBuf),
+    PipeFrames(Config),
+    Help,
+}
+
+// The settings file is read before the rest of the options, so they can override it. A missing
+// default settings file just means the defaults.
+fn load_config(args: &[String]) -> Result<Config, String> {
+    let path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => Some(PathBuf::from(
+            args.get(i + 1).ok_or("--config needs a value")?,
+        )),
+        None => Config::default_path().filter(|path| path.exists()),
+    };
+
+    match path {
+        Some(path) => Config::load(&path),
+        None => Ok(Config::default()),
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Action, String> {
+    // Help is still available with a broken settings file
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        return Ok(Action::Help);
+    }
+
+    let mut config = load_config(&args)?;
+    let mut dump_config = false;
+    let mut render = false;
+    let mut out = None;
+    let mut pipe_frames = false;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                args.next();
+            }
+            "--dump-config" => dump_config = true,
+            "--render" => {
+                if config.scene_path.is_some() {
+                    return Err("--render can't be given with another scene file".into());
+                }
+                config.scene_path =
+                    Some(PathBuf::from(args.next().ok_or("--render needs a value")?));
+                render = true;
+            }
+            "--merge" => {
+                config.merge_path = Some(PathBuf::from(args.next().ok_or("--merge needs a value")?))
+            }
+            "--merge-offset" => config.merge_offset = parse_offset(args.next())?,
+            "--out" => out = Some(PathBuf::from(args.next().ok_or("--out needs a value")?)),
+            "--width" => config.width = parse_size("--width", args.next())?,
+            "--height" => config.height = parse_size("--height", args.next())?,
+            "--fullscreen" => config.fullscreen = true,
+            "--meters-per-pixel" => config.meters_per_pixel = parse_scale(args.next())?,
+            "--fps" => config.target_fps = parse_count("--fps", args.next())?,
+            "--line-growth" => config.line_growth = parse_count("--line-growth", args.next())?,
+            "--record-stride" => {
+                config.record_stride = parse_count("--record-stride", args.next())?
+            }
+            "--pipe-frames" => pipe_frames = true,
+            "--verbose" => config.verbose = true,
+            "--colorblind" => config.colorblind = true,
+            _ if arg.starts_with('-') => return Err(format!("Unknown option `{arg}`")),
+            _ if config.scene_path.is_some() => {
+                return Err(format!("Only one scene file can be given, got `{arg}` too"))
+            }
+            _ => config.scene_path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    if render {
+        let out = out.ok_or("--render needs an --out file to write to")?;
+        return Ok(Action::Render(config, out));
+    } else if out.is_some() {
+        return Err("--out is only used with --render".into());
+    }
+
+    Ok(if dump_config {
+        Action::DumpConfig(config)
+    } else if pipe_frames {
+        Action::PipeFrames(config)
+    } else {
+        Action::Run(config)
+    })
+}
 
 fn main() {
-    if let Err(e) = electric_field_visualizer::run() {
+    let mut hooks = Hooks::default();
+    let config = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(Action::Run(config)) => config,
+        // Stops once whatever is reading the frames goes away
+        Ok(Action::PipeFrames(config)) => {
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            hooks.on_frame = Some(Box::new(move |pixels: &[u8], _, _| {
+                if let Err(e) = stdout.write_all(pixels).and_then(|()| stdout.flush()) {
+                    eprintln!("Stopped piping frames: {e}");
+                    process::exit(1);
+                }
+            }));
+            hooks.frames_on_stdout = true;
+            config
+        }
+        Ok(Action::DumpConfig(config)) => {
+            match config.to_toml() {
+                Ok(toml) => print!("{toml}"),
+                Err(e) => {
+                    eprintln!("Failed to write the settings as TOML: {e}");
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        Ok(Action::Render(config, out)) => {
+            if let Err(e) = electric_field_visualizer::render(config, &out) {
+                eprintln!("Failed to render to {}: {e}", out.display());
+                process::exit(1);
+            }
+            return;
+        }
+        Ok(Action::Help) => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = electric_field_visualizer::run_with_hooks(config, hooks) {
         eprintln!("Exited with fatal error: {e:?}");
         process::exit(1);
     }
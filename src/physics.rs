@@ -0,0 +1,3022 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::{BuildHasherDefault, Hasher},
+    io::{self, Write},
+};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const CHARGE_STEP: f64 = 1.602176634e-19;
+pub const COULUMBS_CONST: f64 = 8.9875517923e9;
+pub const VACUUM_PERMITTIVITY: f64 = 1.0 / (4.0 * std::f64::consts::PI * COULUMBS_CONST);
+// Length of a pixel unless configured otherwise, positions are in pixels and get converted to
+// meters for the physics
+pub const DEFAULT_METERS_PER_PIXEL: f64 = 1e-2;
+// Size of the tile repeated in periodic mode in world pixels, the play area of the default
+// window
+pub const DEFAULT_PERIOD: (f64, f64) = (720.0, 576.0);
+// Radius of a particle of one elementary charge unless configured otherwise. Others grow by a
+// fifth of it for every elementary charge, from four fifths of it when neutral up to three
+// times it.
+pub const PARTICLE_RADIUS: f64 = 10.0;
+const PARTICLE_RADIUS_PER_CHARGE: f64 = 0.2;
+const MAX_PARTICLE_RADIUS: f64 = 3.0;
+pub const MAX_LINE_ITERS: usize = 4096;
+// Point charges a line charge is split into unless it says otherwise
+pub const LINE_CHARGE_POINTS: usize = 32;
+// Thickness line charges are drawn with, field lines end this close to them
+pub const LINE_CHARGE_WIDTH: f64 = 6.0;
+// Distance between the charges of a newly placed dipole, in pixels
+pub const DEFAULT_DIPOLE_SEPARATION: f64 = 40.0;
+// Field line step length as a fraction of the distance to the nearest charge
+const LINE_STEP_SCALE: f64 = 0.1;
+const MIN_LINE_STEP: f64 = 0.25;
+const MAX_LINE_STEP: f64 = 4.0;
+// A field line that gets no further than this many pixels in this many steps, or comes back
+// to within about that distance of where it was more than that many steps ago, is going in
+// circles around a point where the field vanishes and is stopped
+const STAGNATION_STEPS: usize = 16;
+const STAGNATION_DISTANCE: f64 = 1.0;
+// Fields weaker than this are treated as zero when tracing, the direction of a vanishing field
+// is mostly rounding error
+const MIN_FIELD_STRENGTH: f64 = 1e-30;
+// Charges closer than this many pixels to a point count as being this far away for its field
+// and potential, so points right next to a charge don't overflow to infinity
+const MIN_FIELD_DISTANCE: f64 = 1.0;
+// Field lines drawn from a particle per elementary charge it carries, so line density tracks
+// the charge
+pub const DEFAULT_LINES_PER_CHARGE: f64 = 8.0;
+const MIN_LINES_PER_PARTICLE: usize = 4;
+const MAX_LINES_PER_PARTICLE: usize = 64;
+// Cap on the lines coming in from the edges of the bounds when the scene is net negative
+const MAX_BORDER_LINES: usize = 128;
+// Spacing in pixels of the seeds along the edges of the bounds for the lines of a uniform
+// external field, before those that head straight back out are dropped
+const EXTERNAL_FIELD_LINE_SPACING: f64 = 40.0;
+// Charges in each row of the parallel plates preset
+const PLATE_CHARGES: usize = 9;
+// Lines seeded along a new comb, and the most one can have
+pub const DEFAULT_COMB_LINES: usize = 9;
+pub const MAX_COMB_LINES: usize = 64;
+// Most field lines seeded from the charges and combs together, past which they're thinned out
+const MAX_FIELD_LINES: usize = 4096;
+const RING_CHARGES: usize = 8;
+// Number of contour levels of each sign, each half the potential of the previous one
+const EQUIPOTENTIAL_LEVELS: i32 = 8;
+// Electron mass scaled so charges move at a watchable pace over on-screen distances at the
+// default scale
+const PARTICLE_MASS: f64 = 9.1093837e-28;
+// The real one, and the radius of the ground state orbit of hydrogen in the Bohr model, which
+// the hydrogen preset uses
+pub const ELECTRON_MASS: f64 = 9.1093837015e-31;
+pub const BOHR_RADIUS: f64 = 5.29177210903e-11;
+const DYNAMICS_SUBSTEPS: usize = 8;
+// Steps an orbit started by start_bohr_orbit is integrated in at the least
+const STEPS_PER_ORBIT: f64 = 1000.0;
+// Particles closer than this only feel the force they would at this distance, so colliding
+// charges don't fling each other off to infinity
+const MIN_FORCE_DISTANCE: f64 = 2.0 * PARTICLE_RADIUS;
+// Node size to distance ratio below which the field tree approximates a node's charges
+pub const DEFAULT_FIELD_TREE_THETA: f64 = 0.3;
+// Below this many point charges summing the field directly is faster than building a tree
+const MIN_FIELD_TREE_SOURCES: usize = 64;
+const FIELD_TREE_LEAF_SIZE: usize = 8;
+const FIELD_TREE_MAX_DEPTH: usize = 32;
+
+// Charge of test charges, and the number of past positions kept in their trails
+pub const TEST_CHARGE: f64 = CHARGE_STEP;
+pub const TEST_CHARGE_TRAIL_LENGTH: usize = 240;
+
+// Top left and bottom right corners of a rectangle
+pub type Bounds = ((f64, f64), (f64, f64));
+
+// Potential level, start and end of one piece of an equipotential
+pub type ContourSegment = (f64, (f64, f64), (f64, f64));
+
+// Multipole moments of the scene about its centroid, truncated at the quadrupole
+#[derive(Default)]
+pub struct Multipole {
+    pub centroid: (f64, f64),
+    pub total_charge: f64,
+    pub dipole: (f64, f64),
+    // Traceless quadrupole tensor, only the in-plane components since every charge lies in it
+    pub quadrupole: [[f64; 2]; 2],
+}
+
+// A point charge. Scene files store it as an `[x, y, charge]` array, followed by whether it's
+// pinned and then its hue if it has either.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ParticleRepr", into = "ParticleRepr")]
+pub struct ChargedParticle {
+    pub x: f64,
+    pub y: f64,
+    pub charge: f64,
+    // Held in place in dynamics mode
+    pub pinned: bool,
+    // Which color of the palette its field lines get when they're colored by source. It's
+    // handed out once and kept, so removing a particle doesn't change anyone else's.
+    pub hue: Option<usize>,
+}
+
+impl ChargedParticle {
+    pub fn new(x: f64, y: f64, charge: f64) -> Self {
+        Self {
+            x,
+            y,
+            charge,
+            pinned: false,
+            hue: None,
+        }
+    }
+
+    pub fn pos(&self) -> (f64, f64) {
+        (self.x, self.y)
+    }
+
+    pub fn distance_to(&self, pos: (f64, f64)) -> f64 {
+        (pos.0 - self.x).hypot(pos.1 - self.y)
+    }
+}
+
+impl From<(f64, f64, f64)> for ChargedParticle {
+    fn from((x, y, charge): (f64, f64, f64)) -> Self {
+        Self::new(x, y, charge)
+    }
+}
+
+impl From<ChargedParticle> for (f64, f64, f64) {
+    fn from(particle: ChargedParticle) -> Self {
+        (particle.x, particle.y, particle.charge)
+    }
+}
+
+// Particles that aren't pinned and have no hue are saved without either, so older scene files
+// still read the same
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ParticleRepr {
+    Free(f64, f64, f64),
+    Pinned(f64, f64, f64, bool),
+    Colored(f64, f64, f64, bool, usize),
+}
+
+impl From<ParticleRepr> for ChargedParticle {
+    fn from(repr: ParticleRepr) -> Self {
+        match repr {
+            ParticleRepr::Free(x, y, charge) => Self::new(x, y, charge),
+            ParticleRepr::Pinned(x, y, charge, pinned) => Self {
+                pinned,
+                ..Self::new(x, y, charge)
+            },
+            ParticleRepr::Colored(x, y, charge, pinned, hue) => Self {
+                pinned,
+                hue: Some(hue),
+                ..Self::new(x, y, charge)
+            },
+        }
+    }
+}
+
+impl From<ChargedParticle> for ParticleRepr {
+    fn from(particle: ChargedParticle) -> Self {
+        let ChargedParticle {
+            x,
+            y,
+            charge,
+            pinned,
+            hue,
+        } = particle;
+
+        match hue {
+            Some(hue) => ParticleRepr::Colored(x, y, charge, pinned, hue),
+            None if pinned => ParticleRepr::Pinned(x, y, charge, true),
+            None => ParticleRepr::Free(x, y, charge),
+        }
+    }
+}
+
+// A traced field line, with the index of the particle it was seeded from if it was seeded from
+// one rather than a line charge, a dipole or the border, or of the comb it was seeded along
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldLine {
+    pub points: Vec<(f64, f64)>,
+    pub seed: Option<usize>,
+    pub comb: Option<usize>,
+}
+
+// A small positive charge pushed around by the field without contributing to it, along with
+// the positions it was at after each of its most recent steps
+#[derive(Clone, Debug)]
+pub struct TestCharge {
+    pub pos: (f64, f64),
+    pub velocity: (f64, f64),
+    pub trail: VecDeque<(f64, f64)>,
+}
+
+impl TestCharge {
+    pub fn new(pos: (f64, f64)) -> Self {
+        Self {
+            pos,
+            velocity: (0.0, 0.0),
+            trail: VecDeque::from([pos]),
+        }
+    }
+}
+
+// Textbook charge configurations that can be loaded in place of the current scene
+#[derive(Clone, Copy, Debug)]
+pub enum Preset {
+    Dipole,
+    // Two positive charges with a double negative charge between them
+    LinearQuadrupole,
+    // Two rows of charges, positive along the top and negative along the bottom
+    ParallelPlates,
+    Ring,
+    // A proton pinned in the middle and an electron orbiting it, see Scene::start_bohr_orbit
+    Hydrogen,
+}
+
+impl Preset {
+    // Particles of the preset laid out to fill `bounds`, so presets scale with the window
+    pub fn particles(self, bounds: Bounds) -> Vec<ChargedParticle> {
+        let ((left, top), (right, bottom)) = bounds;
+        let (width, height) = (right - left, bottom - top);
+        let center = (left + width / 2.0, top + height / 2.0);
+        // Fraction of the whole width or height, measured from the top left corner
+        let at = |x: f64, y: f64| (left + x * width, top + y * height);
+
+        match self {
+            Preset::Dipole => {
+                let ((x1, y1), (x2, y2)) = (at(0.35, 0.5), at(0.65, 0.5));
+                vec![
+                    ChargedParticle::new(x1, y1, CHARGE_STEP),
+                    ChargedParticle::new(x2, y2, -CHARGE_STEP),
+                ]
+            }
+            Preset::LinearQuadrupole => {
+                let ((x1, y1), (x2, y2)) = (at(0.3, 0.5), at(0.7, 0.5));
+                vec![
+                    ChargedParticle::new(x1, y1, CHARGE_STEP),
+                    ChargedParticle::new(center.0, center.1, -2.0 * CHARGE_STEP),
+                    ChargedParticle::new(x2, y2, CHARGE_STEP),
+                ]
+            }
+            Preset::ParallelPlates => (0..PLATE_CHARGES)
+                .flat_map(|i| {
+                    let x = 0.2 + 0.6 * i as f64 / (PLATE_CHARGES - 1) as f64;
+                    let (top_pos, bottom_pos) = (at(x, 0.35), at(x, 0.65));
+
+                    [
+                        ChargedParticle::new(top_pos.0, top_pos.1, CHARGE_STEP),
+                        ChargedParticle::new(bottom_pos.0, bottom_pos.1, -CHARGE_STEP),
+                    ]
+                })
+                .collect(),
+            Preset::Ring => {
+                let radius = 0.3 * width.min(height);
+
+                (0..RING_CHARGES)
+                    .map(|i| {
+                        let angle = i as f64 * 2.0 * std::f64::consts::PI / RING_CHARGES as f64;
+                        ChargedParticle::new(
+                            center.0 + radius * angle.cos(),
+                            center.1 + radius * angle.sin(),
+                            CHARGE_STEP,
+                        )
+                    })
+                    .collect()
+            }
+            Preset::Hydrogen => {
+                let radius = 0.3 * width.min(height);
+
+                vec![
+                    ChargedParticle {
+                        pinned: true,
+                        ..ChargedParticle::new(center.0, center.1, CHARGE_STEP)
+                    },
+                    ChargedParticle::new(center.0 + radius, center.1, -CHARGE_STEP),
+                ]
+            }
+        }
+    }
+}
+
+// A straight rod with its total charge spread evenly along it, which is approximated by
+// `points` point charges
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineCharge {
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+    pub charge: f64,
+    #[serde(default = "default_line_charge_points")]
+    pub points: usize,
+}
+
+fn default_line_charge_points() -> usize {
+    LINE_CHARGE_POINTS
+}
+
+impl LineCharge {
+    pub fn new(start: (f64, f64), end: (f64, f64), charge: f64) -> Self {
+        Self {
+            start,
+            end,
+            charge,
+            points: LINE_CHARGE_POINTS,
+        }
+    }
+
+    // Point charges the rod is made up of, at the centers of `points` equal pieces
+    pub fn point_charges(&self) -> impl Iterator<Item = ChargedParticle> + '_ {
+        let points = self.points.max(1);
+
+        (0..points).map(move |i| {
+            let t = (i as f64 + 0.5) / points as f64;
+            ChargedParticle::new(
+                self.start.0 + t * (self.end.0 - self.start.0),
+                self.start.1 + t * (self.end.1 - self.start.1),
+                self.charge / points as f64,
+            )
+        })
+    }
+
+    pub fn distance_to(&self, pos: (f64, f64)) -> f64 {
+        let dir = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let length_sq = dir.0 * dir.0 + dir.1 * dir.1;
+        let t = if length_sq > 0.0 {
+            (((pos.0 - self.start.0) * dir.0 + (pos.1 - self.start.1) * dir.1) / length_sq)
+                .clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        (pos.0 - self.start.0 - t * dir.0).hypot(pos.1 - self.start.1 - t * dir.1)
+    }
+
+    pub fn translate(&mut self, offset: (f64, f64)) {
+        self.start = (self.start.0 + offset.0, self.start.1 + offset.1);
+        self.end = (self.end.0 + offset.0, self.end.1 + offset.1);
+    }
+}
+
+// A segment seeding `lines` evenly spaced field lines along it, each traced both with and
+// against the field, to show the field crossing it. It has no charge of its own.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Comb {
+    pub start: (f64, f64),
+    pub end: (f64, f64),
+    #[serde(default = "default_comb_lines")]
+    pub lines: usize,
+}
+
+fn default_comb_lines() -> usize {
+    DEFAULT_COMB_LINES
+}
+
+impl Comb {
+    pub fn new(start: (f64, f64), end: (f64, f64), lines: usize) -> Self {
+        Self { start, end, lines }
+    }
+
+    // Where its lines start, at the centers of `lines` equal pieces like a line charge's points
+    pub fn seeds(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        let lines = self.lines.max(1);
+
+        (0..lines).map(move |i| {
+            let t = (i as f64 + 0.5) / lines as f64;
+            (
+                self.start.0 + t * (self.end.0 - self.start.0),
+                self.start.1 + t * (self.end.1 - self.start.1),
+            )
+        })
+    }
+
+    pub fn distance_to(&self, pos: (f64, f64)) -> f64 {
+        LineCharge::new(self.start, self.end, 0.0).distance_to(pos)
+    }
+
+    pub fn translate(&mut self, offset: (f64, f64)) {
+        self.start = (self.start.0 + offset.0, self.start.1 + offset.1);
+        self.end = (self.end.0 + offset.0, self.end.1 + offset.1);
+    }
+}
+
+// A pair of equal and opposite point charges `separation` pixels apart about `center`, with the
+// positive one `angle` radians clockwise from the x axis on screen. It's moved, rotated and
+// saved as one object, the field only sees its two charges.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Dipole {
+    pub center: (f64, f64),
+    // Magnitude of each of the two charges
+    pub charge: f64,
+    pub separation: f64,
+    pub angle: f64,
+}
+
+impl Dipole {
+    pub fn new(center: (f64, f64), charge: f64, separation: f64, angle: f64) -> Self {
+        Self {
+            center,
+            charge: charge.abs(),
+            separation,
+            angle,
+        }
+    }
+
+    // The positive charge, then the negative one
+    pub fn point_charges(&self) -> [ChargedParticle; 2] {
+        let half = (
+            self.separation / 2.0 * self.angle.cos(),
+            self.separation / 2.0 * self.angle.sin(),
+        );
+
+        [
+            ChargedParticle::new(self.center.0 + half.0, self.center.1 + half.1, self.charge),
+            ChargedParticle::new(self.center.0 - half.0, self.center.1 - half.1, -self.charge),
+        ]
+    }
+
+    // Points the positive charge at `pos`, which rotates and stretches the dipole about its
+    // center
+    pub fn set_positive_end(&mut self, pos: (f64, f64)) {
+        let rel = (pos.0 - self.center.0, pos.1 - self.center.1);
+
+        self.angle = rel.1.atan2(rel.0);
+        self.separation = 2.0 * rel.0.hypot(rel.1);
+    }
+
+    // Dipole moment p = qd from the negative charge to the positive one, in C m given the
+    // length of a pixel
+    pub fn moment(&self, meters_per_pixel: f64) -> (f64, f64) {
+        let length = self.charge * self.separation * meters_per_pixel;
+
+        (length * self.angle.cos(), length * self.angle.sin())
+    }
+
+    // Torque p x E in N m a uniform `field` puts on the dipole, positive turning it clockwise
+    // on screen since y points down
+    pub fn torque(&self, field: (f64, f64), meters_per_pixel: f64) -> f64 {
+        let p = self.moment(meters_per_pixel);
+
+        p.0 * field.1 - p.1 * field.0
+    }
+
+    // Potential energy -p.E in J of the dipole in a uniform `field`
+    pub fn energy(&self, field: (f64, f64), meters_per_pixel: f64) -> f64 {
+        let p = self.moment(meters_per_pixel);
+
+        -(p.0 * field.0 + p.1 * field.1)
+    }
+
+    pub fn translate(&mut self, offset: (f64, f64)) {
+        self.center = (self.center.0 + offset.0, self.center.1 + offset.1);
+    }
+}
+
+// One semi-implicit Euler step of `dt` seconds, wrapping back into the tile when there's a
+// `period`
+fn euler_step(
+    pos: &mut (f64, f64),
+    velocity: &mut (f64, f64),
+    acceleration: (f64, f64),
+    dt: f64,
+    period: Option<(f64, f64)>,
+) {
+    velocity.0 += acceleration.0 * dt;
+    velocity.1 += acceleration.1 * dt;
+
+    pos.0 += velocity.0 * dt;
+    pos.1 += velocity.1 * dt;
+
+    if let Some(period) = period {
+        pos.0 = pos.0.rem_euclid(period.0);
+        pos.1 = pos.1.rem_euclid(period.1);
+    }
+}
+
+// Barnes-Hut quadtree over the point charges of a scene. Far enough away, the charges in a node
+// are replaced by their multipole expansion about the node's center of charge up to the
+// quadrupole, which makes evaluating the field O(log N) instead of O(N).
+#[derive(Clone)]
+pub struct FieldTree {
+    nodes: Vec<FieldTreeNode>,
+    sources: Vec<ChargedParticle>,
+    theta: f64,
+}
+
+#[derive(Clone)]
+struct FieldTreeNode {
+    // Center and half the side of the square the node covers
+    center: (f64, f64),
+    half_size: f64,
+    // Weighted by the magnitude of each charge, so it stays inside the node even when the
+    // charges cancel out
+    charge_center: (f64, f64),
+    total_charge: f64,
+    dipole: (f64, f64),
+    // xx, xy and yy components of the traceless quadrupole tensor, the rest are zero or follow
+    // from these since every charge lies in the plane
+    quadrupole: (f64, f64, f64),
+    // Index of the first of four consecutive children, or None for a leaf
+    children: Option<usize>,
+    // Range of `sources` inside the node
+    sources: (usize, usize),
+}
+
+enum TreeContribution<'a> {
+    Exact(&'a ChargedParticle),
+    Approximate(&'a FieldTreeNode),
+}
+
+impl FieldTree {
+    // `theta` is the largest ratio of node size to distance at which a node's charges are
+    // approximated. Smaller is slower and more accurate, 0 gives the exact field.
+    pub fn new(sources: Vec<ChargedParticle>, theta: f64) -> Self {
+        let (min, max) = sources.iter().fold(
+            (
+                (f64::INFINITY, f64::INFINITY),
+                (f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min, max), source| {
+                (
+                    (min.0.min(source.x), min.1.min(source.y)),
+                    (max.0.max(source.x), max.1.max(source.y)),
+                )
+            },
+        );
+
+        let mut tree = Self {
+            nodes: vec![],
+            sources,
+            theta,
+        };
+
+        if !tree.sources.is_empty() {
+            let center = ((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0);
+            let half_size = ((max.0 - min.0).max(max.1 - min.1) / 2.0).max(f64::EPSILON);
+            tree.push_node(center, half_size, (0, tree.sources.len()));
+            tree.build(0, 0);
+        }
+
+        tree
+    }
+
+    fn push_node(&mut self, center: (f64, f64), half_size: f64, sources: (usize, usize)) {
+        self.nodes.push(FieldTreeNode {
+            center,
+            half_size,
+            charge_center: center,
+            total_charge: 0.0,
+            dipole: (0.0, 0.0),
+            quadrupole: (0.0, 0.0, 0.0),
+            children: None,
+            sources,
+        });
+    }
+
+    // Fills in the moments of the node at `index` and splits it into quadrants if it holds
+    // too many charges
+    fn build(&mut self, index: usize, depth: usize) {
+        let FieldTreeNode {
+            center,
+            half_size,
+            sources,
+            ..
+        } = self.nodes[index];
+        let inside = &self.sources[sources.0..sources.1];
+
+        let weight: f64 = inside.iter().map(|source| source.charge.abs()).sum();
+        let charge_center = if weight > 0.0 {
+            inside.iter().fold((0.0, 0.0), |sum, source| {
+                (
+                    sum.0 + source.charge.abs() * source.x / weight,
+                    sum.1 + source.charge.abs() * source.y / weight,
+                )
+            })
+        } else {
+            center
+        };
+
+        let node = &mut self.nodes[index];
+        node.charge_center = charge_center;
+        node.total_charge = inside.iter().map(|source| source.charge).sum();
+        node.dipole = inside.iter().fold((0.0, 0.0), |dipole, source| {
+            (
+                dipole.0 + source.charge * (source.x - charge_center.0),
+                dipole.1 + source.charge * (source.y - charge_center.1),
+            )
+        });
+        node.quadrupole = inside.iter().fold((0.0, 0.0, 0.0), |quadrupole, source| {
+            let s = (source.x - charge_center.0, source.y - charge_center.1);
+            let s2 = s.0 * s.0 + s.1 * s.1;
+            (
+                quadrupole.0 + source.charge * (3.0 * s.0 * s.0 - s2),
+                quadrupole.1 + source.charge * 3.0 * s.0 * s.1,
+                quadrupole.2 + source.charge * (3.0 * s.1 * s.1 - s2),
+            )
+        });
+
+        // Charges stacked on top of each other can't be split up, the depth limit stops those
+        if sources.1 - sources.0 <= FIELD_TREE_LEAF_SIZE || depth >= FIELD_TREE_MAX_DEPTH {
+            return;
+        }
+
+        // Sorted by quadrant so each child covers a contiguous range
+        let quadrant = |source: &ChargedParticle| {
+            (source.x >= center.0) as usize + 2 * (source.y >= center.1) as usize
+        };
+        self.sources[sources.0..sources.1].sort_unstable_by_key(quadrant);
+
+        let first_child = self.nodes.len();
+        self.nodes[index].children = Some(first_child);
+
+        let mut start = sources.0;
+        let child_half = half_size / 2.0;
+        for (i, (dx, dy)) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .into_iter()
+            .enumerate()
+        {
+            let count = self.sources[start..sources.1]
+                .iter()
+                .take_while(|source| quadrant(source) == i)
+                .count();
+            let child_center = (center.0 + dx * child_half, center.1 + dy * child_half);
+            self.push_node(child_center, child_half, (start, start + count));
+            start += count;
+        }
+
+        for child in first_child..first_child + 4 {
+            self.build(child, depth + 1);
+        }
+    }
+
+    // Calls `visitor` with every source that has to be summed exactly and every node that's far
+    // enough from (`x`, `y`) to be approximated
+    fn visit(&self, x: f64, y: f64, mut visitor: impl FnMut(TreeContribution)) {
+        // Each level leaves at most three siblings waiting, so this can't overflow
+        let mut stack = [0; 3 * FIELD_TREE_MAX_DEPTH + 4];
+        let mut len = 1;
+
+        while len > 0 {
+            len -= 1;
+            let node = &self.nodes[stack[len]];
+            if node.sources.0 == node.sources.1 {
+                continue;
+            }
+
+            let distance = (x - node.charge_center.0).hypot(y - node.charge_center.1);
+            if 2.0 * node.half_size < self.theta * distance {
+                visitor(TreeContribution::Approximate(node));
+                continue;
+            }
+
+            match node.children {
+                Some(first) => {
+                    for (slot, child) in stack[len..len + 4].iter_mut().zip(first..) {
+                        *slot = child;
+                    }
+                    len += 4;
+                }
+                None => self.sources[node.sources.0..node.sources.1]
+                    .iter()
+                    .for_each(|source| visitor(TreeContribution::Exact(source))),
+            }
+        }
+    }
+
+    // Distance from `pos` to the closest charge, skipping every node further away than the
+    // closest charge found so far
+    pub fn nearest_distance(&self, pos: (f64, f64)) -> f64 {
+        let mut nearest = f64::INFINITY;
+        let mut stack = [0; 3 * FIELD_TREE_MAX_DEPTH + 4];
+        let mut len = usize::from(!self.nodes.is_empty());
+
+        while len > 0 {
+            len -= 1;
+            let node = &self.nodes[stack[len]];
+
+            let outside = (
+                ((pos.0 - node.center.0).abs() - node.half_size).max(0.0),
+                ((pos.1 - node.center.1).abs() - node.half_size).max(0.0),
+            );
+            if node.sources.0 == node.sources.1 || outside.0.hypot(outside.1) >= nearest {
+                continue;
+            }
+
+            match node.children {
+                Some(first) => {
+                    for (slot, child) in stack[len..len + 4].iter_mut().zip(first..) {
+                        *slot = child;
+                    }
+                    len += 4;
+                }
+                None => {
+                    for source in self.sources[node.sources.0..node.sources.1].iter() {
+                        nearest = nearest.min(source.distance_to(pos));
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+
+    // Field at (`x`, `y`) in N/C, with positions in pixels of `meters_per_pixel` meters
+    pub fn field_at(&self, x: f64, y: f64, meters_per_pixel: f64) -> (f64, f64) {
+        let mut total = (0.0, 0.0);
+
+        self.visit(x, y, |contribution| match contribution {
+            TreeContribution::Exact(source) => {
+                let d = (x - source.x, y - source.y);
+                let r = d.0.hypot(d.1);
+                if r > 0.0 {
+                    let clamped = r.max(MIN_FIELD_DISTANCE);
+                    let scale = source.charge / (clamped * clamped * r);
+                    total.0 += scale * d.0;
+                    total.1 += scale * d.1;
+                }
+            }
+            TreeContribution::Approximate(node) => {
+                // Gradient of the terms of the potential below, relative to the center of
+                // charge
+                let d = (x - node.charge_center.0, y - node.charge_center.1);
+                let r2 = d.0 * d.0 + d.1 * d.1;
+                let r3 = r2 * r2.sqrt();
+                let r5 = r3 * r2;
+                let (p, (qxx, qxy, qyy)) = (node.dipole, node.quadrupole);
+                let q_d = (qxx * d.0 + qxy * d.1, qxy * d.0 + qyy * d.1);
+
+                let radial = node.total_charge / r3
+                    + 3.0 * (d.0 * p.0 + d.1 * p.1) / r5
+                    + 2.5 * (d.0 * q_d.0 + d.1 * q_d.1) / (r5 * r2);
+
+                total.0 += radial * d.0 - p.0 / r3 - q_d.0 / r5;
+                total.1 += radial * d.1 - p.1 / r3 - q_d.1 / r5;
+            }
+        });
+
+        let scale = COULUMBS_CONST / (meters_per_pixel * meters_per_pixel);
+        (total.0 * scale, total.1 * scale)
+    }
+
+    // Potential at (`x`, `y`) in volts
+    pub fn potential_at(&self, x: f64, y: f64, meters_per_pixel: f64) -> f64 {
+        let mut total = 0.0;
+
+        self.visit(x, y, |contribution| match contribution {
+            TreeContribution::Exact(source) => {
+                let r = (x - source.x).hypot(y - source.y);
+                if r > 0.0 {
+                    total += source.charge / r.max(MIN_FIELD_DISTANCE);
+                }
+            }
+            TreeContribution::Approximate(node) => {
+                // Q / r + d·p / r³ + d·Q·d / 2r⁵
+                let d = (x - node.charge_center.0, y - node.charge_center.1);
+                let r2 = d.0 * d.0 + d.1 * d.1;
+                let r = r2.sqrt();
+                let (p, (qxx, qxy, qyy)) = (node.dipole, node.quadrupole);
+                let d_q_d = qxx * d.0 * d.0 + 2.0 * qxy * d.0 * d.1 + qyy * d.1 * d.1;
+
+                total += node.total_charge / r
+                    + (d.0 * p.0 + d.1 * p.1) / (r2 * r)
+                    + d_q_d / (2.0 * r2 * r2 * r);
+            }
+        });
+
+        total * COULUMBS_CONST / meters_per_pixel
+    }
+}
+
+// Cheap hasher for the grid cells a field line has visited, the default one is overkill for a
+// pair of small integers and made the cell lookups cost more than the field evaluation
+#[derive(Default)]
+struct CellHasher(u64);
+
+impl Hasher for CellHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write_u64(value as u32 as u64);
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0 = (self.0.rotate_left(5) ^ value).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+}
+
+// Infinite grounded conducting plane through a horizontal line at some y or a vertical line at
+// some x. Charges on each side see the mirror images of the charges on their side with the
+// opposite sign, and none of the charges on the other side.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GroundPlane {
+    Horizontal(f64),
+    Vertical(f64),
+}
+
+impl GroundPlane {
+    // Signed distance of `pos` from the plane, which tells the sides apart
+    pub fn offset(self, pos: (f64, f64)) -> f64 {
+        match self {
+            GroundPlane::Horizontal(y) => pos.1 - y,
+            GroundPlane::Vertical(x) => pos.0 - x,
+        }
+    }
+
+    // Closest point on the plane to `pos`
+    pub fn project(self, pos: (f64, f64)) -> (f64, f64) {
+        match self {
+            GroundPlane::Horizontal(y) => (pos.0, y),
+            GroundPlane::Vertical(x) => (x, pos.1),
+        }
+    }
+
+    fn image(self, particle: ChargedParticle) -> ChargedParticle {
+        let ChargedParticle { x, y, charge, .. } = particle;
+
+        match self {
+            GroundPlane::Horizontal(plane_y) => ChargedParticle::new(x, 2.0 * plane_y - y, -charge),
+            GroundPlane::Vertical(plane_x) => ChargedParticle::new(2.0 * plane_x - x, y, -charge),
+        }
+    }
+}
+
+// Point and line charges plus the settings that affect the field they produce. Only the
+// charges are saved to scene files.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Scene {
+    // Only mutable through the methods below from outside the crate, so velocities stay in sync
+    pub(crate) particles: Vec<ChargedParticle>,
+    // Missing from scene files saved before line charges existed
+    #[serde(default)]
+    pub lines: Vec<LineCharge>,
+    #[serde(default)]
+    pub dipoles: Vec<Dipole>,
+    #[serde(default)]
+    pub combs: Vec<Comb>,
+    // Velocities of the particles in dynamics mode, filled in lazily as particles get added
+    #[serde(skip)]
+    pub velocities: Vec<(f64, f64)>,
+    #[serde(skip)]
+    pub periodic: bool,
+    // Size of the tile repeated in periodic mode, in world pixels. The tile is anchored at the
+    // world origin, so it spans (0, 0) to `period` whatever the view or window size, and it's
+    // saved with the scene since it changes the physics.
+    #[serde(default = "default_period")]
+    pub period: (f64, f64),
+    #[serde(skip, default = "default_meters_per_pixel")]
+    pub meters_per_pixel: f64,
+    // Mass of every particle and test charge in dynamics mode, in kg
+    #[serde(skip, default = "default_particle_mass")]
+    pub particle_mass: f64,
+    // Simulated seconds that pass in each second of dynamics on screen
+    #[serde(skip, default = "default_time_scale")]
+    pub time_scale: f64,
+    // Longest step dynamics take in seconds, so a long frame is split into more than
+    // DYNAMICS_SUBSTEPS steps instead of into longer ones
+    #[serde(skip)]
+    pub max_timestep: Option<f64>,
+    // Uniform background field in N/C added on top of the charges' field
+    #[serde(skip)]
+    pub external_field: (f64, f64),
+    #[serde(skip)]
+    pub ground_plane: Option<GroundPlane>,
+    // Accuracy of the field tree, see FieldTree::new
+    #[serde(skip, default = "default_field_tree_theta")]
+    pub field_tree_theta: f64,
+    // Radius of a particle of one elementary charge in pixels, see particle_radius
+    #[serde(skip, default = "default_particle_radius")]
+    pub particle_radius: f64,
+    // Field lines seeded per elementary charge, and the most steps each one is traced for
+    #[serde(skip, default = "default_lines_per_charge")]
+    pub lines_per_charge: f64,
+    #[serde(skip, default = "default_max_line_iters")]
+    pub max_line_iters: usize,
+    // Used for the field and potential instead of summing every charge when it's been built.
+    // It's a snapshot of the charges, so it has to be rebuilt whenever they change.
+    #[serde(skip)]
+    pub(crate) field_tree: Option<FieldTree>,
+}
+
+fn default_field_tree_theta() -> f64 {
+    DEFAULT_FIELD_TREE_THETA
+}
+
+fn default_period() -> (f64, f64) {
+    DEFAULT_PERIOD
+}
+
+fn default_meters_per_pixel() -> f64 {
+    DEFAULT_METERS_PER_PIXEL
+}
+
+fn default_particle_mass() -> f64 {
+    PARTICLE_MASS
+}
+
+fn default_time_scale() -> f64 {
+    1.0
+}
+
+fn default_particle_radius() -> f64 {
+    PARTICLE_RADIUS
+}
+
+fn default_lines_per_charge() -> f64 {
+    DEFAULT_LINES_PER_CHARGE
+}
+
+fn default_max_line_iters() -> usize {
+    MAX_LINE_ITERS
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            particles: vec![],
+            lines: vec![],
+            dipoles: vec![],
+            combs: vec![],
+            velocities: vec![],
+            periodic: false,
+            period: DEFAULT_PERIOD,
+            meters_per_pixel: DEFAULT_METERS_PER_PIXEL,
+            particle_mass: PARTICLE_MASS,
+            time_scale: 1.0,
+            max_timestep: None,
+            external_field: (0.0, 0.0),
+            ground_plane: None,
+            field_tree_theta: DEFAULT_FIELD_TREE_THETA,
+            particle_radius: PARTICLE_RADIUS,
+            lines_per_charge: DEFAULT_LINES_PER_CHARGE,
+            max_line_iters: MAX_LINE_ITERS,
+            field_tree: None,
+        }
+    }
+}
+
+impl Scene {
+    pub fn particles(&self) -> &[ChargedParticle] {
+        &self.particles
+    }
+
+    // Radius `particle` is drawn with and can be picked within, in pixels, which grows with its
+    // charge
+    pub fn particle_radius(&self, particle: &ChargedParticle) -> f64 {
+        let charges = particle.charge.abs() / CHARGE_STEP;
+        let step = PARTICLE_RADIUS_PER_CHARGE * self.particle_radius;
+
+        (self.particle_radius + step * (charges - 1.0))
+            .min(MAX_PARTICLE_RADIUS * self.particle_radius)
+    }
+
+    // Number of field lines to seed around a particle with `charge`
+    pub fn field_line_count(&self, charge: f64) -> usize {
+        ((charge.abs() / CHARGE_STEP * self.lines_per_charge).round() as usize)
+            .clamp(MIN_LINES_PER_PARTICLE, MAX_LINES_PER_PARTICLE)
+    }
+
+    // Builds the field tree for the current charges if there are enough of them for it to pay
+    // off. Periodic and ground plane images aren't part of the tree, so those scenes are always
+    // summed directly.
+    pub fn rebuild_field_tree(&mut self) {
+        let sources: Vec<ChargedParticle> = self.sources().collect();
+
+        self.field_tree = (sources.len() >= MIN_FIELD_TREE_SOURCES
+            && !self.periodic
+            && self.ground_plane.is_none())
+        .then(|| FieldTree::new(sources, self.field_tree_theta));
+    }
+
+    // Adds a particle at rest, returning its index
+    pub fn add_particle(&mut self, particle: ChargedParticle) -> usize {
+        self.field_tree = None;
+        self.particles.push(particle);
+        self.particles.len() - 1
+    }
+
+    // Closest particle `particle` would be put down on, whose radius its center is within
+    pub fn get_merge_target(&self, particle: &ChargedParticle) -> Option<usize> {
+        self.particles
+            .iter()
+            .enumerate()
+            .map(|(i, target)| (i, target.distance_to(particle.pos()), target))
+            .filter(|(_, distance, target)| *distance <= self.particle_radius(target))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _, _)| i)
+    }
+
+    // Adds the charge of `particle` to the closest particle it was put down on,
+    // returning that particle's index, or None if none is that close and nothing changed
+    pub fn merge_particle(&mut self, particle: ChargedParticle) -> Option<usize> {
+        let index = self.get_merge_target(&particle)?;
+
+        self.particles[index].charge += particle.charge;
+        self.field_tree = None;
+        Some(index)
+    }
+
+    pub fn remove_particle(&mut self, index: usize) -> ChargedParticle {
+        self.field_tree = None;
+        if index < self.velocities.len() {
+            self.velocities.remove(index);
+        }
+
+        self.particles.remove(index)
+    }
+
+    // Every point charge acting on the field, the particles first so their indices match, then
+    // the pieces of the line charges and the charges of the dipoles
+    fn sources(&self) -> impl Iterator<Item = ChargedParticle> + '_ {
+        self.particles
+            .iter()
+            .copied()
+            .chain(self.lines.iter().flat_map(|line| line.point_charges()))
+            .chain(self.dipole_charges())
+    }
+
+    fn dipole_charges(&self) -> impl Iterator<Item = ChargedParticle> + '_ {
+        self.dipoles
+            .iter()
+            .flat_map(|dipole| dipole.point_charges())
+    }
+
+    // The particles and the charges of the dipoles, everything drawn as a disc that field lines
+    // start and end on
+    pub fn point_charges(&self) -> impl Iterator<Item = ChargedParticle> + '_ {
+        self.particles.iter().copied().chain(self.dipole_charges())
+    }
+
+    // The sources acting on the field at (`x`, `y`) with their indices, plus the image charges
+    // of the ground plane with no index
+    fn field_sources(
+        &self,
+        x: f64,
+        y: f64,
+    ) -> impl Iterator<Item = (Option<usize>, ChargedParticle)> + '_ {
+        let plane = self.ground_plane;
+
+        self.sources()
+            .enumerate()
+            .flat_map(move |(i, source)| match plane {
+                None => [Some((Some(i), source)), None],
+                Some(plane) if plane.offset(source.pos()) * plane.offset((x, y)) < 0.0 => {
+                    [None, None]
+                }
+                Some(plane) => [Some((Some(i), source)), Some((None, plane.image(source)))],
+            })
+            .flatten()
+    }
+
+    // Returns the positions a particle at (`part_x`, `part_y`) acts from when seen from (`x`,
+    // `y`). Without periodic boundaries this is just the particle itself, otherwise it's the
+    // 3x3 block of periodic images around the image closest to the query point.
+    fn get_images(
+        &self,
+        part_x: f64,
+        part_y: f64,
+        x: f64,
+        y: f64,
+    ) -> impl Iterator<Item = (f64, f64)> {
+        let (width, height) = self.period;
+
+        let (range, nearest) = if self.periodic {
+            let offset = (part_x - x, part_y - y);
+            (
+                -1..=1,
+                (
+                    x + offset.0 - width * (offset.0 / width).round(),
+                    y + offset.1 - height * (offset.1 / height).round(),
+                ),
+            )
+        } else {
+            (0..=0, (part_x, part_y))
+        };
+
+        range.clone().flat_map(move |i| {
+            range
+                .clone()
+                .map(move |j| (nearest.0 + i as f64 * width, nearest.1 + j as f64 * height))
+        })
+    }
+
+    pub fn get_field_strength(&self, x: f64, y: f64) -> (f64, f64) {
+        match self.field_tree.as_ref() {
+            Some(tree) => {
+                let field = tree.field_at(x, y, self.meters_per_pixel);
+                (
+                    field.0 + self.external_field.0,
+                    field.1 + self.external_field.1,
+                )
+            }
+            None => self.get_field_strength_excluding(x, y, None, 0.0),
+        }
+    }
+
+    // Electric field at (`x`, `y`) in N/C
+    pub fn field_at(&self, x: f64, y: f64) -> (f64, f64) {
+        self.get_field_strength(x, y)
+    }
+
+    // Field from every particle but `excluded` in N/C, with distances below `min_distance`
+    // pixels, or MIN_FIELD_DISTANCE if that's larger, treated as that distance
+    pub fn get_field_strength_excluding(
+        &self,
+        x: f64,
+        y: f64,
+        excluded: Option<usize>,
+        min_distance: f64,
+    ) -> (f64, f64) {
+        let mut total_strength = self.external_field;
+
+        for (i, source) in self.field_sources(x, y) {
+            if excluded.is_some() && i == excluded {
+                continue;
+            }
+
+            for (image_x, image_y) in self.get_images(source.x, source.y, x, y) {
+                let direct_vec = (x - image_x, y - image_y);
+                let direct_mag = direct_vec.0.hypot(direct_vec.1);
+
+                // Coincident particles push each other in no particular direction
+                if direct_mag == 0.0 {
+                    continue;
+                }
+
+                let distance =
+                    direct_mag.max(min_distance).max(MIN_FIELD_DISTANCE) * self.meters_per_pixel;
+                let force_mag = COULUMBS_CONST * source.charge / (distance * distance);
+
+                total_strength.0 += force_mag * direct_vec.0 / direct_mag;
+                total_strength.1 += force_mag * direct_vec.1 / direct_mag;
+            }
+        }
+
+        total_strength
+    }
+
+    // Potential at (`x`, `y`) in volts, zero far away from every charge and at the origin for
+    // the external field. A charge sitting exactly at the point is left out like it is for the
+    // field.
+    pub fn get_potential(&self, x: f64, y: f64) -> f64 {
+        let external =
+            -(self.external_field.0 * x + self.external_field.1 * y) * self.meters_per_pixel;
+
+        if let Some(tree) = self.field_tree.as_ref() {
+            return external + tree.potential_at(x, y, self.meters_per_pixel);
+        }
+
+        let mut total_potential = external;
+
+        for (_, source) in self.field_sources(x, y) {
+            for (image_x, image_y) in self.get_images(source.x, source.y, x, y) {
+                let distance = (x - image_x).hypot(y - image_y);
+
+                if distance > 0.0 {
+                    total_potential += COULUMBS_CONST * source.charge
+                        / (distance.max(MIN_FIELD_DISTANCE) * self.meters_per_pixel);
+                }
+            }
+        }
+
+        total_potential
+    }
+
+    // Net force on each particle from everything else in the scene in N, in the same order as
+    // `particles`. Coincident particles don't push each other.
+    pub fn get_net_forces(&self) -> Vec<(f64, f64)> {
+        self.particles
+            .iter()
+            .enumerate()
+            .map(|(i, particle)| {
+                let field = self.get_field_strength_excluding(particle.x, particle.y, Some(i), 0.0);
+                (particle.charge * field.0, particle.charge * field.1)
+            })
+            .collect()
+    }
+
+    // Scales the scene to atoms for the hydrogen preset, with particle 0 the nucleus and particle
+    // 1 the electron: the distance between them becomes a Bohr radius, the particles get the
+    // electron's real mass and the electron starts on the circular orbit around the nucleus.
+    // Returns the period of the orbit in seconds, and steps dynamics at least STEPS_PER_ORBIT
+    // times an orbit. It's a classical orbit, which in reality would radiate away, but radiation
+    // isn't modelled either.
+    pub fn start_bohr_orbit(&mut self) -> f64 {
+        let (nucleus, electron) = (self.particles[0], self.particles[1]);
+        let radius = electron.distance_to(nucleus.pos());
+        self.meters_per_pixel = BOHR_RADIUS / radius;
+        self.particle_mass = ELECTRON_MASS;
+
+        // Coulomb attraction is the centripetal force, in pixels per second
+        let attraction = COULUMBS_CONST * (nucleus.charge * electron.charge).abs() / BOHR_RADIUS;
+        let speed = (attraction / ELECTRON_MASS).sqrt() / self.meters_per_pixel;
+        let (dx, dy) = (electron.x - nucleus.x, electron.y - nucleus.y);
+        self.velocities = vec![(0.0, 0.0); self.particles.len()];
+        self.velocities[1] = (-dy / radius * speed, dx / radius * speed);
+
+        let period = 2.0 * std::f64::consts::PI * radius / speed;
+        self.max_timestep = Some(period / STEPS_PER_ORBIT);
+        period
+    }
+
+    // Steps of dynamics a `dt` second advance is split into, DYNAMICS_SUBSTEPS unless that
+    // makes them longer than `max_timestep`
+    fn get_substeps(&self, dt: f64) -> usize {
+        let needed = self
+            .max_timestep
+            .map_or(0.0, |max_timestep| (dt / max_timestep).ceil());
+
+        DYNAMICS_SUBSTEPS.max(needed as usize)
+    }
+
+    // Advances dynamics mode by `dt` seconds with semi-implicit Euler steps. Velocities are in
+    // pixels per second. Pinned particles stay put and keep pushing the others around.
+    pub fn step_dynamics(&mut self, dt: f64) {
+        // Everything moves, and the forces need the exact field with the particle itself left
+        // out anyway
+        self.field_tree = None;
+        self.velocities.resize(self.particles.len(), (0.0, 0.0));
+
+        let substeps = self.get_substeps(dt);
+        let sub_dt = dt / substeps as f64;
+        let period = self.periodic.then_some(self.period);
+
+        for _ in 0..substeps {
+            let accelerations: Vec<(f64, f64)> = (0..self.particles.len())
+                .map(|i| self.get_acceleration(i, self.particles[i].pos()))
+                .collect();
+
+            for ((particle, velocity), acceleration) in self
+                .particles
+                .iter_mut()
+                .zip(self.velocities.iter_mut())
+                .zip(accelerations)
+            {
+                if particle.pinned {
+                    *velocity = (0.0, 0.0);
+                    continue;
+                }
+
+                let mut pos = particle.pos();
+                euler_step(&mut pos, velocity, acceleration, sub_dt, period);
+                (particle.x, particle.y) = pos;
+            }
+        }
+    }
+
+    // Acceleration in pixels per second squared particle `index` would have at `pos`, from every
+    // charge but itself
+    fn get_acceleration(&self, index: usize, pos: (f64, f64)) -> (f64, f64) {
+        let charge = self.particles[index].charge;
+        let field =
+            self.get_field_strength_excluding(pos.0, pos.1, Some(index), MIN_FORCE_DISTANCE);
+
+        (
+            charge * field.0 / self.particle_mass / self.meters_per_pixel,
+            charge * field.1 / self.particle_mass / self.meters_per_pixel,
+        )
+    }
+
+    // Where particle `index` would be after each of `steps` steps of `dt` seconds if it were let
+    // go at its current velocity while every other charge stayed put, starting with where it is
+    // now. The steps are the same ones dynamics mode takes, so alone in a scene of fixed charges
+    // it follows the same path. It stops once it runs into another charge, and pinned particles
+    // don't go anywhere.
+    pub fn predict_trajectory(&self, index: usize, dt: f64, steps: usize) -> Vec<(f64, f64)> {
+        let mut pos = self.particles[index].pos();
+        if self.particles[index].pinned {
+            return vec![pos];
+        }
+
+        let mut velocity = self.velocities.get(index).copied().unwrap_or((0.0, 0.0));
+        let substeps = self.get_substeps(dt);
+        let sub_dt = dt / substeps as f64;
+        let period = self.periodic.then_some(self.period);
+        let mut path = vec![pos];
+
+        for _ in 0..steps {
+            for _ in 0..substeps {
+                let acceleration = self.get_acceleration(index, pos);
+                euler_step(&mut pos, &mut velocity, acceleration, sub_dt, period);
+            }
+            path.push(pos);
+
+            // Particles come first in the point charges, so `index` is the particle itself
+            let collided = self.point_charges().enumerate().any(|(i, other)| {
+                i != index && other.distance_to(pos) <= self.particle_radius(&other)
+            }) || self
+                .lines
+                .iter()
+                .any(|line| line.distance_to(pos) <= LINE_CHARGE_WIDTH);
+            if collided {
+                break;
+            }
+        }
+
+        path
+    }
+
+    // Advances a test charge by `dt` seconds the same way dynamics mode moves particles, returning
+    // false once it has run into a particle or a line charge
+    pub fn step_test_charge(&self, test: &mut TestCharge, dt: f64) -> bool {
+        let substeps = self.get_substeps(dt);
+        let sub_dt = dt / substeps as f64;
+        let scale = TEST_CHARGE / self.particle_mass / self.meters_per_pixel;
+
+        for _ in 0..substeps {
+            let field = self.get_field_strength(test.pos.0, test.pos.1);
+
+            test.velocity.0 += scale * field.0 * sub_dt;
+            test.velocity.1 += scale * field.1 * sub_dt;
+            test.pos.0 += test.velocity.0 * sub_dt;
+            test.pos.1 += test.velocity.1 * sub_dt;
+
+            if self
+                .point_charges()
+                .any(|particle| particle.distance_to(test.pos) <= self.particle_radius(&particle))
+                || self
+                    .lines
+                    .iter()
+                    .any(|line| line.distance_to(test.pos) <= LINE_CHARGE_WIDTH)
+            {
+                return false;
+            }
+        }
+
+        test.trail.push_back(test.pos);
+        if test.trail.len() > TEST_CHARGE_TRAIL_LENGTH {
+            test.trail.pop_front();
+        }
+
+        true
+    }
+
+    pub fn get_multipole_moments(&self) -> Multipole {
+        let count = self.sources().count().max(1) as f64;
+        let centroid = self.sources().fold((0.0, 0.0), |acc, source| {
+            (acc.0 + source.x / count, acc.1 + source.y / count)
+        });
+
+        let mut moments = Multipole {
+            centroid,
+            total_charge: 0.0,
+            dipole: (0.0, 0.0),
+            quadrupole: [[0.0; 2]; 2],
+        };
+
+        for ChargedParticle { x, y, charge, .. } in self.sources() {
+            let rel = [x - centroid.0, y - centroid.1];
+            let rel_sq = rel[0] * rel[0] + rel[1] * rel[1];
+
+            moments.total_charge += charge;
+            moments.dipole.0 += charge * rel[0];
+            moments.dipole.1 += charge * rel[1];
+
+            for i in 0..2 {
+                for j in 0..2 {
+                    let delta = if i == j { rel_sq } else { 0.0 };
+                    moments.quadrupole[i][j] += charge * (3.0 * rel[i] * rel[j] - delta);
+                }
+            }
+        }
+
+        moments
+    }
+
+    // Field of the multipole expansion truncated after `order` (0 = monopole, 1 = dipole, 2 =
+    // quadrupole). Only a good approximation well outside the charges.
+    pub fn get_multipole_field(&self, x: f64, y: f64, order: usize) -> (f64, f64) {
+        self.get_expansion_field(&self.get_multipole_moments(), x, y, order)
+    }
+
+    // Same as get_multipole_field with the moments already worked out, for sampling the
+    // expansion at many points
+    pub fn get_expansion_field(
+        &self,
+        moments: &Multipole,
+        x: f64,
+        y: f64,
+        order: usize,
+    ) -> (f64, f64) {
+        let rel = [x - moments.centroid.0, y - moments.centroid.1];
+        let dist = rel[0].hypot(rel[1]);
+        let mut field = [0.0; 2];
+
+        let monopole = moments.total_charge / dist.powi(3);
+        for k in 0..2 {
+            field[k] += monopole * rel[k];
+        }
+
+        if order >= 1 {
+            let dipole = [moments.dipole.0, moments.dipole.1];
+            let p_dot_r = dipole[0] * rel[0] + dipole[1] * rel[1];
+
+            for k in 0..2 {
+                field[k] += (3.0 * p_dot_r * rel[k] / (dist * dist) - dipole[k]) / dist.powi(3);
+            }
+        }
+
+        if order >= 2 {
+            let quad = moments.quadrupole;
+            let q_r = [
+                quad[0][0] * rel[0] + quad[0][1] * rel[1],
+                quad[1][0] * rel[0] + quad[1][1] * rel[1],
+            ];
+            let r_q_r = rel[0] * q_r[0] + rel[1] * q_r[1];
+
+            for k in 0..2 {
+                field[k] += 2.5 * r_q_r * rel[k] / dist.powi(7) - q_r[k] / dist.powi(5);
+            }
+        }
+
+        // The external field is exact at every order, so it doesn't show up in the error
+        let scale = COULUMBS_CONST / self.meters_per_pixel.powi(2);
+        (
+            scale * field[0] + self.external_field.0,
+            scale * field[1] + self.external_field.1,
+        )
+    }
+
+    // Samples the potential on a `columns` x `rows` grid of cells starting at `origin` and
+    // extracts iso-lines at logarithmically spaced levels of both signs with marching squares.
+    // Cells containing a particle are skipped since the potential is singular there. Each
+    // segment comes with the level it belongs to.
+    pub fn get_equipotential_segments(
+        &self,
+        origin: (f64, f64),
+        cell_size: f64,
+        columns: usize,
+        rows: usize,
+    ) -> Vec<ContourSegment> {
+        let samples: Vec<Vec<f64>> = (0..=rows)
+            .map(|j| {
+                (0..=columns)
+                    .map(|i| {
+                        self.get_potential(
+                            origin.0 + i as f64 * cell_size,
+                            origin.1 + j as f64 * cell_size,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let top = samples
+            .iter()
+            .flatten()
+            .filter(|v| v.is_finite())
+            .fold(0.0, |max: f64, v| max.max(v.abs()));
+        if top == 0.0 {
+            return vec![];
+        }
+
+        let levels: Vec<f64> = (1..=EQUIPOTENTIAL_LEVELS)
+            .map(|k| top * 0.5f64.powi(k))
+            .flat_map(|level| [level, -level])
+            .collect();
+
+        let mut segments = vec![];
+
+        for j in 0..rows {
+            for i in 0..columns {
+                let (x, y) = (
+                    origin.0 + i as f64 * cell_size,
+                    origin.1 + j as f64 * cell_size,
+                );
+
+                if self.sources().any(|source| {
+                    (x..=x + cell_size).contains(&source.x)
+                        && (y..=y + cell_size).contains(&source.y)
+                }) {
+                    continue;
+                }
+
+                // Corners go clockwise from the top left, edge `e` joins corners `e` and `e + 1`
+                let corners = [
+                    (x, y),
+                    (x + cell_size, y),
+                    (x + cell_size, y + cell_size),
+                    (x, y + cell_size),
+                ];
+                let values = [
+                    samples[j][i],
+                    samples[j][i + 1],
+                    samples[j + 1][i + 1],
+                    samples[j + 1][i],
+                ];
+                if values.iter().any(|v| !v.is_finite()) {
+                    continue;
+                }
+
+                for level in levels.iter() {
+                    let above = values.map(|v| v >= *level);
+
+                    let crossings: Vec<(f64, f64)> = (0..4)
+                        .filter(|e| above[*e] != above[(e + 1) % 4])
+                        .map(|e| {
+                            let (a, b) = (e, (e + 1) % 4);
+                            let t = (level - values[a]) / (values[b] - values[a]);
+
+                            (
+                                corners[a].0 + t * (corners[b].0 - corners[a].0),
+                                corners[a].1 + t * (corners[b].1 - corners[a].1),
+                            )
+                        })
+                        .collect();
+
+                    match crossings.len() {
+                        2 => segments.push((*level, crossings[0], crossings[1])),
+                        // Saddle cell, use the average of the corners to decide whether the
+                        // corners above the level are connected through the middle
+                        4 => {
+                            let center_above = values.iter().sum::<f64>() / 4.0 >= *level;
+
+                            if above[0] == center_above {
+                                segments.push((*level, crossings[0], crossings[1]));
+                                segments.push((*level, crossings[2], crossings[3]));
+                            } else {
+                                segments.push((*level, crossings[3], crossings[0]));
+                                segments.push((*level, crossings[1], crossings[2]));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        segments
+    }
+
+    // Unit vector along the field (or against it for a negative `direction`), if the field
+    // isn't vanishingly small
+    pub fn get_field_direction(&self, pos: (f64, f64), direction: f64) -> Option<(f64, f64)> {
+        let field_strength = self.get_field_strength(pos.0, pos.1);
+        let field_strength_mag = field_strength.0.hypot(field_strength.1);
+
+        if field_strength_mag > MIN_FIELD_STRENGTH && field_strength_mag.is_finite() {
+            Some((
+                direction * field_strength.0 / field_strength_mag,
+                direction * field_strength.1 / field_strength_mag,
+            ))
+        } else {
+            None
+        }
+    }
+
+    // Steps get shorter near charges where the field bends sharply, and longer far away
+    fn get_line_step_size(&self, pos: (f64, f64)) -> f64 {
+        let nearest = match self.field_tree.as_ref() {
+            Some(tree) => tree.nearest_distance(pos),
+            None => self
+                .sources()
+                .map(|source| source.distance_to(pos))
+                .fold(f64::INFINITY, f64::min),
+        };
+
+        (nearest * LINE_STEP_SCALE).clamp(MIN_LINE_STEP, MAX_LINE_STEP)
+    }
+
+    // Classical RK4 step of length `step` along the normalized field direction
+    fn rk4_step(&self, pos: (f64, f64), direction: f64, step: f64) -> Option<(f64, f64)> {
+        let k1 = self.get_field_direction(pos, direction)?;
+        let k2 = self.get_field_direction(
+            (pos.0 + step / 2.0 * k1.0, pos.1 + step / 2.0 * k1.1),
+            direction,
+        )?;
+        let k3 = self.get_field_direction(
+            (pos.0 + step / 2.0 * k2.0, pos.1 + step / 2.0 * k2.1),
+            direction,
+        )?;
+        let k4 = self.get_field_direction((pos.0 + step * k3.0, pos.1 + step * k3.1), direction)?;
+
+        Some((
+            pos.0 + step / 6.0 * (k1.0 + 2.0 * k2.0 + 2.0 * k3.0 + k4.0),
+            pos.1 + step / 6.0 * (k1.1 + 2.0 * k2.1 + 2.0 * k3.1 + k4.1),
+        ))
+    }
+
+    // Traces a field line from `start` for at most `max_iters` steps, following the field for a
+    // positive `direction` and going against it for a negative one, until it runs into a
+    // particle of the opposite sign, leaves `bounds`, reaches a point where the field vanishes
+    // or stalls going back and forth or around in circles. In periodic mode the line is split
+    // into several polylines wherever it wraps around, so no segment gets drawn across the whole
+    // screen.
+    pub fn trace_field_line(
+        &self,
+        start: (f64, f64),
+        direction: f64,
+        max_iters: usize,
+        bounds: Option<Bounds>,
+    ) -> Vec<Vec<(f64, f64)>> {
+        let mut line_segments = vec![];
+        let mut line_points = vec![start];
+        let mut current_pos = start;
+
+        // The last STAGNATION_STEPS positions, and the STAGNATION_DISTANCE sized cells of every
+        // position before those
+        let mut recent = VecDeque::from([start]);
+        let mut visited: HashSet<(i32, i32), BuildHasherDefault<CellHasher>> = HashSet::default();
+        let cell = |pos: (f64, f64)| {
+            (
+                (pos.0 / STAGNATION_DISTANCE).floor() as i32,
+                (pos.1 / STAGNATION_DISTANCE).floor() as i32,
+            )
+        };
+
+        for _ in 0..max_iters {
+            let previous = current_pos;
+            let step = self.get_line_step_size(current_pos);
+            // A vanishing or blown up field leaves nowhere sensible to go. Past the ground plane
+            // there's no field at all, so a step that fails that close to it ends on it.
+            current_pos = match self.rk4_step(current_pos, direction, step) {
+                Some(pos) if pos.0.is_finite() && pos.1.is_finite() => pos,
+                _ => {
+                    if let Some(plane) = self.ground_plane {
+                        if plane.offset(current_pos).abs() <= step {
+                            line_points.push(plane.project(current_pos));
+                        }
+                    }
+                    break;
+                }
+            };
+
+            // Lines end where they reach the ground plane, on the induced surface charge
+            if let Some(plane) = self.ground_plane {
+                let (before, after) = (plane.offset(previous), plane.offset(current_pos));
+
+                if before != 0.0 && before * after <= 0.0 {
+                    let t = before / (before - after);
+                    line_points.push((
+                        previous.0 + t * (current_pos.0 - previous.0),
+                        previous.1 + t * (current_pos.1 - previous.1),
+                    ));
+                    break;
+                }
+            }
+
+            if recent.len() == STAGNATION_STEPS {
+                let oldest = recent.pop_front().unwrap_or(current_pos);
+                visited.insert(cell(oldest));
+
+                let (i, j) = cell(current_pos);
+                let revisited =
+                    (-1..=1).any(|di| (-1..=1).any(|dj| visited.contains(&(i + di, j + dj))));
+                let moved = (current_pos.0 - oldest.0).hypot(current_pos.1 - oldest.1);
+
+                if revisited || moved < STAGNATION_DISTANCE {
+                    break;
+                }
+            }
+            recent.push_back(current_pos);
+
+            if self.periodic && self.wrap_position(&mut current_pos) {
+                line_segments.push(std::mem::take(&mut line_points));
+            }
+
+            if let Some((top_left, bottom_right)) = bounds {
+                if !(top_left.0..=bottom_right.0).contains(&current_pos.0)
+                    || !(top_left.1..=bottom_right.1).contains(&current_pos.1)
+                {
+                    line_points.push(current_pos);
+                    break;
+                }
+            }
+
+            if self
+                .point_charges()
+                .filter(|particle| particle.charge * direction < 0.0)
+                .any(|particle| {
+                    particle.distance_to(current_pos) <= self.particle_radius(&particle) * 1.1
+                })
+                || self
+                    .lines
+                    .iter()
+                    .filter(|line| line.charge * direction < 0.0)
+                    .any(|line| line.distance_to(current_pos) <= LINE_CHARGE_WIDTH)
+            {
+                break;
+            }
+
+            line_points.push(current_pos);
+        }
+
+        line_segments.push(line_points);
+        line_segments
+    }
+
+    // Evenly spaced points along the edges of `bounds`, as many as the net negative charge of the
+    // scene calls for plus more for the external field. Those lines physically come in from
+    // infinity, so none are needed when the positive charges can account for all of them and
+    // there's no external field.
+    fn get_border_seeds(&self, bounds: Bounds) -> Vec<(f64, f64)> {
+        let ((left, top), (right, bottom)) = bounds;
+        let (width, height) = (right - left, bottom - top);
+
+        let net_charge = self.particles.iter().map(|p| p.charge).sum::<f64>()
+            + self.lines.iter().map(|line| line.charge).sum::<f64>();
+        let mut count = if net_charge < 0.0 {
+            ((-net_charge / CHARGE_STEP * self.lines_per_charge).round() as usize)
+                .max(MIN_LINES_PER_PARTICLE)
+        } else {
+            0
+        };
+        if self.external_field != (0.0, 0.0) {
+            count += (2.0 * (width + height) / EXTERNAL_FIELD_LINE_SPACING) as usize;
+        }
+        let count = count.min(MAX_BORDER_LINES);
+
+        // Walks the perimeter clockwise from the top left corner, just inside it so the seeds
+        // don't count as having left the bounds
+        let inset = MIN_LINE_STEP;
+
+        (0..count)
+            .map(|i| {
+                let mut along = (i as f64 + 0.5) / count as f64 * 2.0 * (width + height);
+
+                if along < width {
+                    return (left + along, top + inset);
+                }
+                along -= width;
+                if along < height {
+                    return (right - inset, top + along);
+                }
+                along -= height;
+                if along < width {
+                    return (right - along, bottom - inset);
+                }
+                along -= width;
+                (left + inset, bottom - along)
+            })
+            .collect()
+    }
+
+    // Traces every field line of the scene, seeding as many lines around each charged particle
+    // or along each charged rod as its charge calls for. Lines from negative charges are traced
+    // against the field, so they end on positive charges instead. In a net negative scene or with
+    // an external field some lines are also seeded along the edges of `bounds` and traced
+    // inwards, those that head straight back out are dropped. Each comb seeds its lines both
+    // ways. Past MAX_FIELD_LINES the seeds of the charges and combs are thinned out evenly, so a
+    // dense comb takes lines from everything else rather than adding to them. Lines are
+    // independent of each other, so they're traced in parallel.
+    pub fn trace_field_lines(&self, max_iters: usize, bounds: Option<Bounds>) -> Vec<FieldLine> {
+        // Each seed comes with the line it starts, still without points. Particles come first
+        // in the point charges, then the dipoles' charges.
+        let mut seeds: Vec<((f64, f64), f64, FieldLine)> = self
+            .point_charges()
+            .enumerate()
+            .filter(|(_, particle)| particle.charge != 0.0)
+            .flat_map(|(index, particle)| {
+                let ChargedParticle { x, y, charge, .. } = particle;
+                let line_count = self.field_line_count(charge);
+                // Just outside the drawn particle
+                let seed_radius = self.particle_radius(&particle) * 1.1;
+                let line = FieldLine {
+                    seed: (index < self.particles.len()).then_some(index),
+                    ..Default::default()
+                };
+
+                (0..line_count).map(move |i| {
+                    let starting_angle = i as f64 * 2.0 * std::f64::consts::PI / line_count as f64;
+
+                    (
+                        (
+                            x + seed_radius * starting_angle.cos(),
+                            y + seed_radius * starting_angle.sin(),
+                        ),
+                        charge.signum(),
+                        line.clone(),
+                    )
+                })
+            })
+            .chain(
+                self.lines
+                    .iter()
+                    .filter(|line| line.charge != 0.0)
+                    .flat_map(|line| {
+                        let dir = (line.end.0 - line.start.0, line.end.1 - line.start.1);
+                        let length = dir.0.hypot(dir.1).max(f64::EPSILON);
+                        let normal = (-dir.1 / length, dir.0 / length);
+                        let per_side = self.field_line_count(line.charge).div_ceil(2);
+
+                        // Evenly spaced along the rod, just off both of its sides
+                        (0..per_side).flat_map(move |i| {
+                            let t = (i as f64 + 0.5) / per_side as f64;
+                            let on_line = (line.start.0 + t * dir.0, line.start.1 + t * dir.1);
+
+                            [1.0, -1.0].map(|side| {
+                                (
+                                    (
+                                        on_line.0 + side * LINE_CHARGE_WIDTH * 1.1 * normal.0,
+                                        on_line.1 + side * LINE_CHARGE_WIDTH * 1.1 * normal.1,
+                                    ),
+                                    line.charge.signum(),
+                                    FieldLine::default(),
+                                )
+                            })
+                        })
+                    }),
+            )
+            .chain(self.combs.iter().enumerate().flat_map(|(index, comb)| {
+                let line = FieldLine {
+                    comb: Some(index),
+                    ..Default::default()
+                };
+
+                comb.seeds()
+                    .flat_map(move |start| [1.0, -1.0].map(|direction| (start, direction)))
+                    .map(move |(start, direction)| (start, direction, line.clone()))
+            }))
+            .collect();
+
+        if seeds.len() > MAX_FIELD_LINES {
+            let stride = seeds.len() as f64 / MAX_FIELD_LINES as f64;
+            seeds = (0..MAX_FIELD_LINES)
+                .map(|i| seeds[(i as f64 * stride) as usize].clone())
+                .collect();
+        }
+
+        let mut lines: Vec<FieldLine> = seeds
+            .par_iter()
+            .flat_map_iter(|(start, direction, line)| {
+                self.trace_field_line(*start, *direction, max_iters, bounds)
+                    .into_iter()
+                    .map(move |points| FieldLine {
+                        points,
+                        ..line.clone()
+                    })
+            })
+            .collect();
+
+        // Without bounds there are no edges to seed from, and in periodic mode there's no
+        // outside for lines to come in from
+        if let Some(bounds) = bounds.filter(|_| !self.periodic) {
+            lines.par_extend(
+                self.get_border_seeds(bounds)
+                    .par_iter()
+                    .map(|start| self.trace_field_line(*start, 1.0, max_iters, Some(bounds)))
+                    .filter(|segments| segments.iter().map(Vec::len).sum::<usize>() > 2)
+                    .flatten_iter()
+                    .map(|points| FieldLine {
+                        points,
+                        ..Default::default()
+                    }),
+            );
+        }
+
+        lines
+    }
+
+    // Writes the field and potential at the centers of a `columns` x `rows` grid of cells over
+    // `bounds` as CSV with an `x,y,Ex,Ey,|E|,V` header. Points inside a particle have their
+    // field and potential left empty, since they're dominated by the singularity there.
+    pub fn write_field_csv(
+        &self,
+        mut writer: impl Write,
+        bounds: Bounds,
+        columns: usize,
+        rows: usize,
+    ) -> io::Result<()> {
+        let ((left, top), (right, bottom)) = bounds;
+        let cell_size = (
+            (right - left) / columns as f64,
+            (bottom - top) / rows as f64,
+        );
+
+        writeln!(writer, "x,y,Ex,Ey,|E|,V")?;
+
+        for j in 0..rows {
+            for i in 0..columns {
+                let (x, y) = (
+                    left + (i as f64 + 0.5) * cell_size.0,
+                    top + (j as f64 + 0.5) * cell_size.1,
+                );
+
+                if self
+                    .particles
+                    .iter()
+                    .any(|particle| particle.distance_to((x, y)) <= self.particle_radius(particle))
+                {
+                    writeln!(writer, "{x},{y},,,,")?;
+                    continue;
+                }
+
+                let (x_comp, y_comp) = self.get_field_strength(x, y);
+                writeln!(
+                    writer,
+                    "{x},{y},{x_comp:e},{y_comp:e},{:e},{:e}",
+                    x_comp.hypot(y_comp),
+                    self.get_potential(x, y)
+                )?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    // Outward flux through the closed surface made by extruding `rect` infinitely far out of
+    // the plane, from `samples_per_side` midpoint samples along each side. The in-plane field
+    // falls off as 1/r², so its flux through just the perimeter isn't q/ε₀, but integrating
+    // each charge's field over the height of the surface analytically leaves a 2kq/r field
+    // along the perimeter whose flux is. Periodic images are left out.
+    pub fn get_gauss_flux(&self, rect: Bounds, samples_per_side: usize) -> f64 {
+        let ((left, top), (right, bottom)) = rect;
+        // Start, end and outward normal of each side
+        let sides = [
+            ((left, top), (right, top), (0.0, -1.0)),
+            ((right, top), (right, bottom), (1.0, 0.0)),
+            ((right, bottom), (left, bottom), (0.0, 1.0)),
+            ((left, bottom), (left, top), (-1.0, 0.0)),
+        ];
+        let samples = samples_per_side.max(1);
+        let mut flux = 0.0;
+
+        for (start, end, normal) in sides {
+            let step = (end.0 - start.0).hypot(end.1 - start.1) / samples as f64;
+
+            for i in 0..samples {
+                let t = (i as f64 + 0.5) / samples as f64;
+                let pos = (
+                    start.0 + t * (end.0 - start.0),
+                    start.1 + t * (end.1 - start.1),
+                );
+
+                for source in self.sources() {
+                    let rel = (pos.0 - source.x, pos.1 - source.y);
+                    let dist_sq = rel.0 * rel.0 + rel.1 * rel.1;
+
+                    if dist_sq > 0.0 {
+                        flux += 2.0
+                            * COULUMBS_CONST
+                            * source.charge
+                            * (rel.0 * normal.0 + rel.1 * normal.1)
+                            / dist_sq
+                            * step;
+                    }
+                }
+            }
+        }
+
+        flux
+    }
+
+    // Total charge inside `rect`, counting charges on its edges as inside
+    pub fn get_enclosed_charge(&self, rect: Bounds) -> f64 {
+        let ((left, top), (right, bottom)) = rect;
+
+        self.sources()
+            .filter(|source| {
+                (left..=right).contains(&source.x) && (top..=bottom).contains(&source.y)
+            })
+            .map(|source| source.charge)
+            .sum()
+    }
+
+    // Wraps a position back into the periodic tile, returning whether it crossed an edge
+    pub fn wrap_position(&self, pos: &mut (f64, f64)) -> bool {
+        let wrapped = (
+            pos.0.rem_euclid(self.period.0),
+            pos.1.rem_euclid(self.period.1),
+        );
+        let crossed = wrapped != *pos;
+
+        *pos = wrapped;
+        crossed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DYNAMICS_TIMESTEP: f64 = 0.016;
+
+    // Tests work at a meter per pixel so expected values can use pixel distances directly
+    fn scene(particles: Vec<(f64, f64, f64)>) -> Scene {
+        Scene {
+            particles: particles.into_iter().map(ChargedParticle::from).collect(),
+            meters_per_pixel: 1.0,
+            ..Default::default()
+        }
+    }
+
+    fn periodic_scene(particles: Vec<(f64, f64, f64)>) -> Scene {
+        Scene {
+            particles: particles.into_iter().map(ChargedParticle::from).collect(),
+            meters_per_pixel: 1.0,
+            periodic: true,
+            period: (720.0, 600.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn scene_can_be_built_through_its_public_api() {
+        let mut scene = Scene::default();
+        let index = scene.add_particle(ChargedParticle::new(0.0, 0.0, CHARGE_STEP));
+        scene.add_particle(ChargedParticle::new(100.0, 0.0, -CHARGE_STEP));
+
+        assert_eq!(index, 0);
+        assert_eq!(scene.particles().len(), 2);
+        assert_eq!(
+            scene.field_at(50.0, 0.0),
+            scene.get_field_strength(50.0, 0.0)
+        );
+        assert!(scene.field_at(50.0, 0.0).0 > 0.0);
+
+        assert_eq!(scene.remove_particle(1).charge, -CHARGE_STEP);
+        assert_eq!(
+            scene.particles(),
+            &[ChargedParticle::new(0.0, 0.0, CHARGE_STEP)]
+        );
+    }
+
+    #[test]
+    fn field_of_two_charges_is_superposition() {
+        let first = (120.0, 80.0, 2.0 * CHARGE_STEP);
+        let second = (300.0, 410.0, -3.0 * CHARGE_STEP);
+        let both = scene(vec![first, second]);
+
+        for (x, y) in [(0.0, 0.0), (200.0, 250.0), (-75.5, 640.0)] {
+            let a = scene(vec![first]).get_field_strength(x, y);
+            let b = scene(vec![second]).get_field_strength(x, y);
+            let total = both.get_field_strength(x, y);
+
+            assert!(
+                (total.0 - (a.0 + b.0)).hypot(total.1 - (a.1 + b.1))
+                    < 1e-9 * total.0.hypot(total.1)
+            );
+            assert!(
+                (both.get_potential(x, y)
+                    - scene(vec![first]).get_potential(x, y)
+                    - scene(vec![second]).get_potential(x, y))
+                .abs()
+                    < 1e-9 * both.get_potential(x, y).abs()
+            );
+        }
+    }
+
+    #[test]
+    fn point_charge_field_falls_off_as_inverse_square() {
+        let single = scene(vec![(0.0, 0.0, CHARGE_STEP)]);
+
+        let near = single.get_field_strength(50.0, 0.0);
+        let far = single.get_field_strength(100.0, 0.0);
+        assert!((near.0 / far.0 - 4.0).abs() < 1e-12);
+        assert_eq!((near.1, far.1), (0.0, 0.0));
+
+        let diagonal = single.get_field_strength(30.0, 40.0);
+        assert!((diagonal.0.hypot(diagonal.1) - near.0).abs() < 1e-12 * near.0);
+        assert!((diagonal.1 / diagonal.0 - 4.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn field_uses_meters_per_pixel() {
+        let mut scene = Scene::default();
+        scene.add_particle(ChargedParticle::new(0.0, 0.0, CHARGE_STEP));
+
+        // A meter away at the default scale
+        let one_meter = 1.0 / DEFAULT_METERS_PER_PIXEL;
+        let field = scene.get_field_strength(one_meter, 0.0);
+        assert_eq!(field, (COULUMBS_CONST * CHARGE_STEP, 0.0));
+        assert!((field.0 - 1.44e-9).abs() < 0.01e-9);
+        assert_eq!(
+            scene.get_potential(0.0, one_meter),
+            COULUMBS_CONST * CHARGE_STEP
+        );
+
+        scene.meters_per_pixel = 2.0 * DEFAULT_METERS_PER_PIXEL;
+        assert_eq!(
+            scene.get_field_strength(one_meter, 0.0).0,
+            COULUMBS_CONST * CHARGE_STEP / 4.0
+        );
+    }
+
+    #[test]
+    fn point_charge_potential_is_kq_over_r() {
+        let single = scene(vec![(100.0, 100.0, -2.0 * CHARGE_STEP)]);
+        let expected = COULUMBS_CONST * -2.0 * CHARGE_STEP / 50.0;
+
+        assert!((single.get_potential(130.0, 140.0) - expected).abs() < 1e-12 * expected.abs());
+        assert_eq!(single.get_potential(100.0, 100.0), 0.0);
+    }
+
+    #[test]
+    fn dipole_potential_is_antisymmetric_about_bisector() {
+        let dipole = scene(vec![
+            (200.0, 300.0, CHARGE_STEP),
+            (400.0, 300.0, -CHARGE_STEP),
+        ]);
+
+        for (x, y) in [(250.0, 300.0), (120.0, 50.0), (299.0, 420.0)] {
+            let v = dipole.get_potential(x, y);
+            assert!(v > 0.0);
+            assert!((v + dipole.get_potential(600.0 - x, y)).abs() < 1e-9 * v);
+        }
+
+        for y in [-100.0, 300.0, 575.0] {
+            assert!(dipole.get_potential(300.0, y).abs() < 1e-15);
+        }
+    }
+
+    #[test]
+    fn dipole_lines_are_mirror_symmetric() {
+        let dipole = scene(vec![
+            (200.0, 300.0, CHARGE_STEP),
+            (400.0, 300.0, -CHARGE_STEP),
+        ]);
+
+        for angle in [0.3, 1.0, 2.5] {
+            let offset = (11.0 * f64::cos(angle), 11.0 * f64::sin(angle));
+            let above = dipole
+                .trace_field_line(
+                    (200.0 + offset.0, 300.0 - offset.1),
+                    1.0,
+                    MAX_LINE_ITERS,
+                    None,
+                )
+                .concat();
+            let below = dipole
+                .trace_field_line(
+                    (200.0 + offset.0, 300.0 + offset.1),
+                    1.0,
+                    MAX_LINE_ITERS,
+                    None,
+                )
+                .concat();
+
+            // Mirrored across the dipole axis
+            assert_eq!(above.len(), below.len());
+            for (a, b) in above.iter().zip(below.iter()) {
+                assert!((a.0 - b.0).abs() < 1e-6 && (a.1 + b.1 - 600.0).abs() < 1e-6);
+            }
+
+            // Mirrored across the perpendicular bisector, tracing back against the field from
+            // the negative charge
+            let mirrored = dipole
+                .trace_field_line(
+                    (400.0 - offset.0, 300.0 - offset.1),
+                    -1.0,
+                    MAX_LINE_ITERS,
+                    None,
+                )
+                .concat();
+
+            assert_eq!(above.len(), mirrored.len());
+            for (a, b) in above.iter().zip(mirrored.iter()) {
+                assert!((a.0 + b.0 - 600.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn radius_grows_with_charge() {
+        let scene = Scene::default();
+        let radius = |charge: f64| scene.particle_radius(&ChargedParticle::new(0.0, 0.0, charge));
+
+        assert_eq!(radius(CHARGE_STEP), PARTICLE_RADIUS);
+        assert_eq!(radius(-3.0 * CHARGE_STEP), PARTICLE_RADIUS + 4.0);
+        assert_eq!(radius(0.0), PARTICLE_RADIUS - 2.0);
+        assert_eq!(radius(100.0 * CHARGE_STEP), 30.0);
+    }
+
+    #[test]
+    fn line_count_scales_with_charge() {
+        let scene = Scene::default();
+
+        assert_eq!(scene.field_line_count(CHARGE_STEP), 8);
+        assert_eq!(scene.field_line_count(2.0 * CHARGE_STEP), 16);
+        assert_eq!(
+            scene.field_line_count(-CHARGE_STEP),
+            scene.field_line_count(CHARGE_STEP)
+        );
+        assert_eq!(scene.field_line_count(0.1 * CHARGE_STEP), 4);
+        assert_eq!(scene.field_line_count(100.0 * CHARGE_STEP), 64);
+    }
+
+    #[test]
+    fn parallel_tracing_matches_single_lines() {
+        let dipole = scene(vec![
+            (200.0, 300.0, 2.0 * CHARGE_STEP),
+            (400.0, 300.0, -CHARGE_STEP),
+        ]);
+        let lines = dipole.trace_field_lines(MAX_LINE_ITERS, None);
+
+        // Lines start just outside each particle, which is bigger the more charge it has
+        let seeds: Vec<f64> = dipole
+            .particles
+            .iter()
+            .map(|particle| particle.x + 1.1 * dipole.particle_radius(particle))
+            .collect();
+        assert_eq!(lines.len(), 16 + 8);
+        assert_eq!(
+            lines[0].points,
+            dipole.trace_field_line((seeds[0], 300.0), 1.0, MAX_LINE_ITERS, None)[0]
+        );
+        assert_eq!(
+            lines[16].points,
+            dipole.trace_field_line((seeds[1], 300.0), -1.0, MAX_LINE_ITERS, None)[0]
+        );
+        assert!(lines[..16].iter().all(|line| line.seed == Some(0)));
+        assert!(lines[16..].iter().all(|line| line.seed == Some(1)));
+    }
+
+    #[test]
+    fn net_negative_scenes_get_lines_from_the_border() {
+        let bounds = ((-200.0, -200.0), (200.0, 200.0));
+        let negative = scene(vec![(0.0, 0.0, -3.0 * CHARGE_STEP)]);
+        let lines = negative.trace_field_lines(MAX_LINE_ITERS, Some(bounds));
+        let border_lines = &lines[negative.field_line_count(-3.0 * CHARGE_STEP)..];
+
+        assert_eq!(border_lines.len(), 24);
+        for line in border_lines {
+            let end = line.points.last().unwrap();
+            assert!(
+                end.0.hypot(end.1)
+                    <= negative.particle_radius(&negative.particles[0]) * 1.1 + MAX_LINE_STEP
+            );
+        }
+
+        let neutral = scene(vec![
+            (-50.0, 0.0, -3.0 * CHARGE_STEP),
+            (50.0, 0.0, 3.0 * CHARGE_STEP),
+        ]);
+        assert_eq!(
+            neutral
+                .trace_field_lines(MAX_LINE_ITERS, Some(bounds))
+                .len(),
+            48
+        );
+    }
+
+    #[test]
+    fn net_forces_follow_coulombs_law() {
+        let scene = scene(vec![
+            (0.0, 0.0, 2.0 * CHARGE_STEP),
+            (100.0, 0.0, -CHARGE_STEP),
+            (100.0, 0.0, CHARGE_STEP),
+            (0.0, 50.0, 0.0),
+        ]);
+        let forces = scene.get_net_forces();
+
+        // The two charges on top of each other leave each other out, so they each only feel the
+        // one at the origin, which feels nothing overall
+        let r = 100.0 * scene.meters_per_pixel;
+        let magnitude = 2.0 * COULUMBS_CONST * CHARGE_STEP * CHARGE_STEP / (r * r);
+        assert!((forces[1].0 + magnitude).abs() < 1e-12 * magnitude);
+        assert!((forces[2].0 - magnitude).abs() < 1e-12 * magnitude);
+        assert!(forces[0].0.abs() < 1e-12 * magnitude);
+        assert_eq!(forces[3], (0.0, 0.0));
+    }
+
+    #[test]
+    fn placing_on_a_particle_merges_the_charges() {
+        let mut merged = scene(vec![(100.0, 100.0, 2.0 * CHARGE_STEP)]);
+        let combined = scene(vec![(100.0, 100.0, 5.0 * CHARGE_STEP)]);
+
+        assert_eq!(
+            merged.merge_particle(ChargedParticle::new(103.0, 98.0, 3.0 * CHARGE_STEP)),
+            Some(0)
+        );
+        assert_eq!(merged.particles().len(), 1);
+        assert_eq!(
+            merged.field_at(160.0, 140.0),
+            combined.field_at(160.0, 140.0)
+        );
+
+        let far = ChargedParticle::new(100.0 + 2.0 * PARTICLE_RADIUS, 100.0, CHARGE_STEP);
+        assert_eq!(merged.merge_particle(far), None);
+        assert_eq!(merged.particles().len(), 1);
+    }
+
+    // Deterministic stand-in for random numbers in [0, 1), so failures are reproducible
+    fn pseudo_random(state: &mut u64) -> f64 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    #[test]
+    fn field_tree_matches_direct_sum() {
+        let mut state = 1;
+        let mut scene = scene(
+            (0..500)
+                .map(|_| {
+                    let x = 720.0 * pseudo_random(&mut state);
+                    let y = 600.0 * pseudo_random(&mut state);
+                    let charge = (10.0 * pseudo_random(&mut state)).round() - 5.0;
+                    (x, y, charge * CHARGE_STEP)
+                })
+                .collect(),
+        );
+
+        let points: Vec<(f64, f64)> = (0..200)
+            .map(|_| {
+                (
+                    800.0 * pseudo_random(&mut state) - 40.0,
+                    680.0 * pseudo_random(&mut state) - 40.0,
+                )
+            })
+            .collect();
+        let exact: Vec<((f64, f64), f64)> = points
+            .iter()
+            .map(|&(x, y)| (scene.get_field_strength(x, y), scene.get_potential(x, y)))
+            .collect();
+
+        // The potential cancels out to almost nothing in places, so its error is compared to
+        // its typical size instead
+        let mean_potential = exact.iter().map(|(_, v)| v.abs()).sum::<f64>() / exact.len() as f64;
+
+        scene.rebuild_field_tree();
+        assert!(scene.field_tree.is_some());
+
+        for (&(x, y), (field, potential)) in points.iter().zip(exact) {
+            let approx = scene.get_field_strength(x, y);
+            let error = (approx.0 - field.0).hypot(approx.1 - field.1);
+            assert!(
+                error <= 0.01 * field.0.hypot(field.1),
+                "{error} at ({x}, {y})"
+            );
+
+            let approx = scene.get_potential(x, y);
+            let error = (approx - potential).abs();
+            assert!(error <= 0.01 * mean_potential, "{error} at ({x}, {y})");
+        }
+
+        // Any change to the charges drops the tree instead of leaving it stale
+        scene.add_particle(ChargedParticle::new(0.0, 0.0, CHARGE_STEP));
+        assert!(scene.field_tree.is_none());
+    }
+
+    #[test]
+    fn field_is_finite_everywhere() {
+        let mut state = 2;
+        let mut scene = scene(vec![
+            (100.0, 100.0, CHARGE_STEP),
+            (100.0, 100.0, -2.0 * CHARGE_STEP),
+            (300.0, 200.0, 5.0 * CHARGE_STEP),
+        ]);
+
+        let mut points = vec![
+            (100.0, 100.0),
+            (300.0, 200.0),
+            (300.0 + 1e-300, 200.0),
+            (100.0, 100.0 - f64::MIN_POSITIVE),
+        ];
+        points.extend((0..100).map(|_| {
+            (
+                400.0 * pseudo_random(&mut state),
+                300.0 * pseudo_random(&mut state),
+            )
+        }));
+
+        let check = |scene: &Scene| {
+            for &(x, y) in points.iter() {
+                let field = scene.get_field_strength(x, y);
+                assert!(field.0.is_finite() && field.1.is_finite(), "({x}, {y})");
+                assert!(scene.get_potential(x, y).is_finite(), "({x}, {y})");
+            }
+
+            for line in scene.trace_field_lines(256, None) {
+                assert!(line
+                    .points
+                    .iter()
+                    .all(|pos| pos.0.is_finite() && pos.1.is_finite()));
+            }
+        };
+
+        check(&scene);
+
+        // The tree sums nearby charges itself, so it needs the same guard
+        for i in 0..MIN_FIELD_TREE_SOURCES {
+            scene.add_particle(ChargedParticle::new(10.0 * i as f64, 300.0, CHARGE_STEP));
+        }
+        scene.rebuild_field_tree();
+        assert!(scene.field_tree.is_some());
+        check(&scene);
+    }
+
+    #[test]
+    fn lines_into_a_null_point_stop_there() {
+        let scene = scene(vec![
+            (200.0, 300.0, CHARGE_STEP),
+            (400.0, 300.0, CHARGE_STEP),
+        ]);
+
+        let line = &scene.trace_field_line((211.0, 300.0), 1.0, MAX_LINE_ITERS, None)[0];
+        let end = line.last().unwrap();
+        assert!(line.len() < 200, "{} points", line.len());
+        assert!((end.0 - 300.0).abs() < 2.0 && (end.1 - 300.0).abs() < 1e-9);
+
+        // Lines that don't stall are left alone
+        let line = &scene.trace_field_line((189.0, 300.0), 1.0, MAX_LINE_ITERS, None)[0];
+        assert_eq!(line.len(), MAX_LINE_ITERS + 1);
+    }
+
+    #[test]
+    fn external_field_adds_to_field_and_potential() {
+        let mut scene = Scene {
+            particles: vec![ChargedParticle::new(0.0, 0.0, CHARGE_STEP)],
+            ..Default::default()
+        };
+        // Cancels the charge's field a meter upstream of it
+        let strength = COULUMBS_CONST * CHARGE_STEP;
+        scene.external_field = (strength, 0.0);
+        let stagnation_x = -1.0 / scene.meters_per_pixel;
+
+        let field = scene.get_field_strength(stagnation_x, 0.0);
+        assert!(field.0.hypot(field.1) < 1e-9 * strength);
+
+        // E = -∇V, including the external field's -E·r term
+        let h = 0.01;
+        for (x, y) in [(30.0, 40.0), (-150.0, 20.0), (80.0, -120.0)] {
+            let field = scene.get_field_strength(x, y);
+            let gradient = (
+                (scene.get_potential(x + h, y) - scene.get_potential(x - h, y))
+                    / (2.0 * h * scene.meters_per_pixel),
+                (scene.get_potential(x, y + h) - scene.get_potential(x, y - h))
+                    / (2.0 * h * scene.meters_per_pixel),
+            );
+            assert!((field.0 + gradient.0).hypot(field.1 + gradient.1) < 1e-6 * strength);
+        }
+
+        // The line heading upstream ends at the stagnation point instead of scribbling there
+        let line = &scene.trace_field_line((-11.0, 0.0), 1.0, MAX_LINE_ITERS, None)[0];
+        let end = line.last().unwrap();
+        assert!(line.len() < MAX_LINE_ITERS / 4);
+        assert!((end.0 - stagnation_x).abs() < 2.0);
+    }
+
+    #[test]
+    fn ground_plane_is_at_zero_potential() {
+        let mut scene = scene(vec![
+            (100.0, 50.0, 3.0 * CHARGE_STEP),
+            (-40.0, 120.0, -CHARGE_STEP),
+            (60.0, -80.0, 2.0 * CHARGE_STEP),
+        ]);
+        scene.ground_plane = Some(GroundPlane::Horizontal(0.0));
+
+        let scale = COULUMBS_CONST * CHARGE_STEP / 50.0;
+        for x in [-300.0, -50.0, 0.0, 75.0, 400.0] {
+            assert!(scene.get_potential(x, 0.0).abs() < 1e-12 * scale);
+
+            // The field only has a component normal to the plane at its surface
+            let field = scene.get_field_strength(x, 1e-9);
+            assert!(field.0.abs() < 1e-6 * field.1.abs());
+        }
+
+        // Either side only sees its own charges, so removing the one below doesn't change the
+        // field above
+        let above = scene.get_field_strength(30.0, 70.0);
+        scene.remove_particle(2);
+        assert_eq!(scene.get_field_strength(30.0, 70.0), above);
+
+        // Lines from the positive charge end on the plane, the negative charge or far away (it
+        // still has net charge above the plane), and none get through to the other side
+        let lines = scene.trace_field_lines(MAX_LINE_ITERS, None);
+        for line in &lines[..scene.field_line_count(3.0 * CHARGE_STEP)] {
+            let end = line.points.last().unwrap();
+            let on_negative = (end.0 + 40.0).hypot(end.1 - 120.0) < 2.0 * PARTICLE_RADIUS;
+            let escaped = end.0.hypot(end.1) > 1000.0;
+            assert!(end.1.abs() < 1e-9 || on_negative || escaped, "{end:?}");
+            assert!(line.points.iter().all(|pos| pos.1 >= -1e-9));
+        }
+    }
+
+    #[test]
+    fn presets_fit_in_bounds() {
+        let bounds = ((-100.0, 50.0), (620.0, 650.0));
+
+        for preset in [
+            Preset::Dipole,
+            Preset::LinearQuadrupole,
+            Preset::ParallelPlates,
+            Preset::Ring,
+            Preset::Hydrogen,
+        ] {
+            let particles = preset.particles(bounds);
+            assert!(!particles.is_empty());
+            assert!(particles
+                .iter()
+                .all(|p| (-100.0..=620.0).contains(&p.x) && (50.0..=650.0).contains(&p.y)));
+        }
+
+        let total_charge = |preset: Preset| -> f64 {
+            preset
+                .particles(bounds)
+                .iter()
+                .map(|particle| particle.charge)
+                .sum()
+        };
+        assert_eq!(total_charge(Preset::Dipole), 0.0);
+        assert_eq!(total_charge(Preset::LinearQuadrupole), 0.0);
+        assert_eq!(total_charge(Preset::ParallelPlates), 0.0);
+        assert_eq!(total_charge(Preset::Hydrogen), 0.0);
+        assert_eq!(
+            Preset::ParallelPlates.particles(bounds).len(),
+            2 * PLATE_CHARGES
+        );
+        assert_eq!(Preset::Ring.particles(bounds).len(), RING_CHARGES);
+    }
+
+    #[test]
+    fn comb_across_a_dipole_splits_its_lines_between_the_charges() {
+        let (positive, negative) = ((-100.0, 0.0), (100.0, 0.0));
+        let scene = Scene {
+            particles: vec![
+                ChargedParticle::new(positive.0, positive.1, CHARGE_STEP),
+                ChargedParticle::new(negative.0, negative.1, -CHARGE_STEP),
+            ],
+            combs: vec![Comb::new((0.0, -60.0), (0.0, 60.0), 5)],
+            ..Default::default()
+        };
+        let bounds = ((-2000.0, -2000.0), (2000.0, 2000.0));
+        let lines: Vec<FieldLine> = scene
+            .trace_field_lines(MAX_LINE_ITERS, Some(bounds))
+            .into_iter()
+            .filter(|line| line.comb == Some(0))
+            .collect();
+        assert_eq!(lines.len(), 10);
+
+        // Half the lines follow the field over to the negative charge on the right, the other
+        // half go back against it to the positive one on the left
+        let ends_on = |line: &FieldLine, charge: (f64, f64)| {
+            let end = line.points.last().unwrap();
+            (end.0 - charge.0).hypot(end.1 - charge.1) < 2.0 * PARTICLE_RADIUS
+        };
+        let right = lines.iter().filter(|line| ends_on(line, negative)).count();
+        let left = lines.iter().filter(|line| ends_on(line, positive)).count();
+        assert_eq!((left, right), (5, 5));
+
+        // Each pair starts from the same point on the comb
+        let seeds: Vec<(f64, f64)> = Comb::new((0.0, -60.0), (0.0, 60.0), 5).seeds().collect();
+        for (pair, seed) in lines.chunks(2).zip(seeds) {
+            assert!(pair.iter().all(|line| line.points[0] == seed));
+            assert!(ends_on(&pair[0], negative) && ends_on(&pair[1], positive));
+        }
+    }
+
+    #[test]
+    fn field_lines_stay_within_the_line_budget() {
+        let mut scene = Scene {
+            particles: vec![ChargedParticle::new(0.0, 0.0, CHARGE_STEP)],
+            ..Default::default()
+        };
+        let charge_lines = scene.trace_field_lines(10, None).len();
+        scene.combs = (0..MAX_FIELD_LINES / MAX_COMB_LINES)
+            .map(|i| Comb::new((i as f64, 50.0), (i as f64, 150.0), MAX_COMB_LINES))
+            .collect();
+
+        let lines = scene.trace_field_lines(10, None);
+        assert_eq!(lines.len(), MAX_FIELD_LINES);
+        // The particle keeps its share of what's left for it
+        let particle_lines = lines.iter().filter(|line| line.seed == Some(0)).count();
+        assert!(particle_lines > 0 && particle_lines < charge_lines);
+    }
+
+    #[test]
+    fn bohr_orbit_stays_circular_for_several_periods() {
+        let bounds = ((0.0, 0.0), (800.0, 600.0));
+        let nucleus = (400.0, 300.0);
+        let radius = 180.0;
+        let turn = 2.0 * std::f64::consts::PI;
+
+        // From an orbit a frame at 60 fps, down to one where frames stall at a tenth of a second
+        for steps_per_orbit in [180, 30] {
+            let mut scene = Scene {
+                particles: Preset::Hydrogen.particles(bounds),
+                ..Default::default()
+            };
+            let period = scene.start_bohr_orbit();
+            assert!((period - 1.5198e-16).abs() < 1e-20, "{period}");
+            assert_eq!(scene.meters_per_pixel * radius, BOHR_RADIUS);
+
+            let mut last_angle = 0.0;
+            let mut swept = 0.0;
+            for _ in 0..5 * steps_per_orbit {
+                scene.step_dynamics(period / steps_per_orbit as f64);
+
+                let (x, y) = scene.particles[1].pos();
+                let distance = (x - nucleus.0).hypot(y - nucleus.1);
+                assert!((distance / radius - 1.0).abs() < 0.01, "{distance}");
+
+                let angle = (y - nucleus.1).atan2(x - nucleus.0);
+                swept += (angle - last_angle + 1.5 * turn).rem_euclid(turn) - turn / 2.0;
+                last_angle = angle;
+            }
+
+            assert_eq!(scene.particles[0].pos(), nucleus);
+            assert!((swept / turn - 5.0).abs() < 0.02, "{swept}");
+        }
+    }
+
+    #[test]
+    fn field_between_opposite_plates_is_nearly_uniform() {
+        let plates = Scene {
+            lines: vec![
+                LineCharge::new((100.0, 250.0), (500.0, 250.0), 40.0 * CHARGE_STEP),
+                LineCharge::new((100.0, 350.0), (500.0, 350.0), -40.0 * CHARGE_STEP),
+            ],
+            ..Default::default()
+        };
+
+        let center = plates.get_field_strength(300.0, 300.0);
+        assert!(center.1 > 0.0 && center.0.abs() < 1e-9 * center.1);
+
+        for (x, y) in [
+            (250.0, 300.0),
+            (350.0, 290.0),
+            (300.0, 310.0),
+            (220.0, 305.0),
+        ] {
+            let field = plates.get_field_strength(x, y);
+            assert!((field.0 - center.0).hypot(field.1 - center.1) < 0.1 * center.1);
+        }
+
+        // Lines leaving the positive plate toward the other one end on it
+        let line = plates
+            .trace_field_line((300.0, 256.6), 1.0, MAX_LINE_ITERS, None)
+            .concat();
+        let end = *line.last().unwrap();
+        assert!(plates.lines[1].distance_to(end) <= LINE_CHARGE_WIDTH + MAX_LINE_STEP);
+    }
+
+    #[test]
+    fn line_charge_pieces_add_up_to_its_charge() {
+        let line = LineCharge::new((0.0, 0.0), (100.0, 0.0), 3.0 * CHARGE_STEP);
+        let pieces: Vec<ChargedParticle> = line.point_charges().collect();
+
+        assert_eq!(pieces.len(), LINE_CHARGE_POINTS);
+        assert!((pieces.iter().map(|p| p.charge).sum::<f64>() - 3.0 * CHARGE_STEP).abs() < 1e-30);
+        assert_eq!(line.distance_to((50.0, 20.0)), 20.0);
+        assert_eq!(line.distance_to((-30.0, 40.0)), 50.0);
+    }
+
+    #[test]
+    fn dipole_charges_track_rotation_and_separation() {
+        let mut dipole = Dipole::new((100.0, 50.0), -2.0 * CHARGE_STEP, 40.0, 0.0);
+        assert_eq!(
+            dipole.point_charges(),
+            [
+                ChargedParticle::new(120.0, 50.0, 2.0 * CHARGE_STEP),
+                ChargedParticle::new(80.0, 50.0, -2.0 * CHARGE_STEP),
+            ]
+        );
+
+        dipole.set_positive_end((100.0, 80.0));
+        assert_eq!(dipole.separation, 60.0);
+        let [positive, negative] = dipole.point_charges();
+        assert!(positive.distance_to((100.0, 80.0)) < 1e-12);
+        assert!(negative.distance_to((100.0, 20.0)) < 1e-12);
+
+        // Pointing down the screen in a field to the right, it's turned anticlockwise towards
+        // the field and sits halfway up in energy
+        let p = 2.0 * CHARGE_STEP * 60.0;
+        assert!((dipole.torque((10.0, 0.0), 1.0) + 10.0 * p).abs() < 1e-12 * p);
+        assert!(dipole.energy((10.0, 0.0), 1.0).abs() < 1e-12 * p);
+        assert!((dipole.energy((0.0, 10.0), 1.0) + 10.0 * p).abs() < 1e-12 * p);
+
+        // The field only sees the two charges
+        let with_dipole = Scene {
+            dipoles: vec![dipole],
+            meters_per_pixel: 1.0,
+            ..Default::default()
+        };
+        let with_charges = scene(vec![positive.into(), negative.into()]);
+        assert_eq!(
+            with_dipole.get_field_strength(300.0, 200.0),
+            with_charges.get_field_strength(300.0, 200.0)
+        );
+    }
+
+    #[test]
+    fn predicted_trajectory_matches_dynamics_around_fixed_charges() {
+        let mut live = scene(vec![(300.0, 200.0, -CHARGE_STEP)]);
+        live.meters_per_pixel = DEFAULT_METERS_PER_PIXEL;
+        live.lines.push(LineCharge::new(
+            (100.0, 100.0),
+            (100.0, 300.0),
+            20.0 * CHARGE_STEP,
+        ));
+        live.dipoles
+            .push(Dipole::new((300.0, 400.0), CHARGE_STEP, 40.0, 0.3));
+
+        let steps = 30;
+        let predicted = live.predict_trajectory(0, DYNAMICS_TIMESTEP, steps);
+        assert_eq!(predicted.len(), steps + 1);
+        assert_eq!(predicted[0], (300.0, 200.0));
+
+        // Lines and dipoles don't move in dynamics mode, so the particle is alone among fixed
+        // charges there too
+        for expected in predicted.iter().skip(1) {
+            live.step_dynamics(DYNAMICS_TIMESTEP);
+            assert_eq!(live.particles[0].pos(), *expected);
+        }
+        assert!(predicted[steps].0 < 300.0 - 1.0);
+    }
+
+    #[test]
+    fn field_csv_matches_single_charge() {
+        let single = scene(vec![(50.0, 50.0, CHARGE_STEP)]);
+        let mut csv = vec![];
+        single
+            .write_field_csv(&mut csv, ((0.0, 0.0), (100.0, 100.0)), 10, 10)
+            .unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("x,y,Ex,Ey,|E|,V"));
+
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows.len(), 100);
+
+        for row in rows {
+            let (x, y): (f64, f64) = (row[0].parse().unwrap(), row[1].parse().unwrap());
+            let r = (x - 50.0).hypot(y - 50.0);
+
+            if r <= PARTICLE_RADIUS {
+                assert!(row[2..].iter().all(|field| field.is_empty()));
+                continue;
+            }
+
+            let magnitude: f64 = row[4].parse().unwrap();
+            let potential: f64 = row[5].parse().unwrap();
+            let expected = COULUMBS_CONST * CHARGE_STEP / (r * r);
+
+            assert!((magnitude - expected).abs() < 1e-12 * expected);
+            assert!((potential - COULUMBS_CONST * CHARGE_STEP / r).abs() < 1e-12 * potential);
+
+            let ex: f64 = row[2].parse().unwrap();
+            assert!((ex - expected * (x - 50.0) / r).abs() < 1e-12 * expected);
+        }
+    }
+
+    #[test]
+    fn lines_stop_at_bounds() {
+        let single = scene(vec![(100.0, 100.0, CHARGE_STEP)]);
+        let bounds = ((0.0, 0.0), (720.0, 600.0));
+
+        for angle in [0.0, 1.0, 2.0, 3.0, 4.0, 5.0] {
+            let start = (
+                100.0 + 11.0 * f64::cos(angle),
+                100.0 + 11.0 * f64::sin(angle),
+            );
+            let line = single
+                .trace_field_line(start, 1.0, MAX_LINE_ITERS, Some(bounds))
+                .concat();
+
+            assert!(line.len() < 500);
+
+            // The last point is the first one outside, everything before it is inside
+            let (last, inside) = line.split_last().unwrap();
+            assert!(!(0.0..=720.0).contains(&last.0) || !(0.0..=600.0).contains(&last.1));
+            assert!(inside
+                .iter()
+                .all(|(x, y)| (0.0..=720.0).contains(x) && (0.0..=600.0).contains(y)));
+        }
+    }
+
+    #[test]
+    fn vanishing_field_has_no_direction() {
+        // The field cancels exactly halfway between two equal charges
+        let pair = scene(vec![
+            (100.0, 300.0, CHARGE_STEP),
+            (300.0, 300.0, CHARGE_STEP),
+        ]);
+
+        assert_eq!(pair.get_field_direction((200.0, 300.0), 1.0), None);
+        assert_eq!(scene(vec![]).get_field_direction((200.0, 300.0), 1.0), None);
+    }
+
+    #[test]
+    fn periodic_field_is_continuous_across_boundary() {
+        let scene = periodic_scene(vec![
+            (100.0, 200.0, CHARGE_STEP),
+            (500.0, 450.0, -2.0 * CHARGE_STEP),
+        ]);
+
+        for y in [0.0, 137.0, 300.0, 599.0] {
+            let left = scene.get_field_strength(1e-6, y);
+            let right = scene.get_field_strength(720.0 - 1e-6, y);
+            let scale = left.0.hypot(left.1);
+
+            assert!((left.0 - right.0).hypot(left.1 - right.1) < 1e-6 * scale);
+        }
+
+        for x in [0.0, 250.0, 719.0] {
+            let top = scene.get_field_strength(x, 1e-6);
+            let bottom = scene.get_field_strength(x, 600.0 - 1e-6);
+            let scale = top.0.hypot(top.1);
+
+            assert!((top.0 - bottom.0).hypot(top.1 - bottom.1) < 1e-6 * scale);
+        }
+    }
+
+    #[test]
+    fn multipole_field_converges_to_exact_field_far_away() {
+        let scene = scene(vec![
+            (300.0, 310.0, 3.0 * CHARGE_STEP),
+            (340.0, 290.0, -CHARGE_STEP),
+            (320.0, 340.0, -4.0 * CHARGE_STEP),
+            (280.0, 280.0, CHARGE_STEP),
+        ]);
+
+        for angle in [0.3, 1.7, 4.0] {
+            let (x, y) = (
+                310.0 + 5000.0 * f64::cos(angle),
+                305.0 + 5000.0 * f64::sin(angle),
+            );
+            let exact = scene.get_field_strength(x, y);
+
+            let errors: Vec<f64> = (0..=2)
+                .map(|order| {
+                    let approx = scene.get_multipole_field(x, y, order);
+                    (approx.0 - exact.0).hypot(approx.1 - exact.1) / exact.0.hypot(exact.1)
+                })
+                .collect();
+
+            assert!(errors[0] > errors[1] && errors[1] > errors[2]);
+            assert!(errors[2] < 1e-3);
+        }
+    }
+
+    #[test]
+    fn multipole_moments_of_dipole() {
+        let scene = scene(vec![(100.0, 0.0, CHARGE_STEP), (-100.0, 0.0, -CHARGE_STEP)]);
+        let moments = scene.get_multipole_moments();
+
+        assert_eq!(moments.centroid, (0.0, 0.0));
+        assert_eq!(moments.total_charge, 0.0);
+        assert!((moments.dipole.0 - 200.0 * CHARGE_STEP).abs() < 1e-30);
+        assert_eq!(moments.dipole.1, 0.0);
+    }
+
+    #[test]
+    fn rk4_dipole_line_matches_fine_euler_trace() {
+        let scene = scene(vec![
+            (200.0, 300.0, CHARGE_STEP),
+            (400.0, 300.0, -CHARGE_STEP),
+        ]);
+
+        for angle in [0.4, -0.8, 1.2] {
+            let start = (
+                200.0 + 11.0 * f64::cos(angle),
+                300.0 + 11.0 * f64::sin(angle),
+            );
+
+            // Reference trace with tiny Euler steps, run until it gets as close to the
+            // negative charge as the RK4 line's end
+            let rk4_line = scene
+                .trace_field_line(start, 1.0, MAX_LINE_ITERS, None)
+                .concat();
+            let rk4_end = *rk4_line.last().unwrap();
+            let end_dist = (rk4_end.0 - 400.0).hypot(rk4_end.1 - 300.0);
+
+            let mut euler_pos = start;
+            while (euler_pos.0 - 400.0).hypot(euler_pos.1 - 300.0) > end_dist {
+                let dir = scene.get_field_direction(euler_pos, 1.0).unwrap();
+                euler_pos = (euler_pos.0 + 0.01 * dir.0, euler_pos.1 + 0.01 * dir.1);
+            }
+
+            assert!((euler_pos.0 - rk4_end.0).hypot(euler_pos.1 - rk4_end.1) < 0.1);
+            assert!(rk4_line.len() < 1000);
+        }
+    }
+
+    #[test]
+    fn single_charge_equipotentials_are_circles() {
+        let scene = scene(vec![(363.0, 301.0, 2.0 * CHARGE_STEP)]);
+        let cell_size = 8.0;
+
+        let segments = scene.get_equipotential_segments((0.0, 0.0), cell_size, 91, 76);
+        assert!(!segments.is_empty());
+
+        // Every contour point should sit on the circle of radius kq/V for its level, up to the
+        // error of interpolating linearly within a grid cell. That error is only small a few
+        // cells away from the charge, where 1/r is close to linear across a cell.
+        for (level, start, end) in segments {
+            let expected_radius = COULUMBS_CONST * 2.0 * CHARGE_STEP / level;
+            assert!(level > 0.0);
+
+            if expected_radius < 3.0 * cell_size {
+                continue;
+            }
+
+            for (x, y) in [start, end] {
+                let radius = (x - 363.0).hypot(y - 301.0);
+                assert!((radius - expected_radius).abs() < 0.05 * expected_radius + 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn dynamics_attracts_opposite_charges_without_nan() {
+        let mut scene = scene(vec![
+            (300.0, 300.0, CHARGE_STEP),
+            (340.0, 300.0, -CHARGE_STEP),
+        ]);
+        scene.meters_per_pixel = DEFAULT_METERS_PER_PIXEL;
+
+        scene.step_dynamics(DYNAMICS_TIMESTEP);
+        assert!(scene.particles[0].x > 300.0 && scene.particles[1].x < 340.0);
+        assert!(scene.velocities[0].0 > 0.0 && scene.velocities[1].0 < 0.0);
+
+        // Let them run through each other, the clamped force keeps everything finite
+        for _ in 0..600 {
+            scene.step_dynamics(DYNAMICS_TIMESTEP);
+        }
+        assert!(scene
+            .particles
+            .iter()
+            .all(|particle| particle.x.is_finite() && particle.y.is_finite()));
+    }
+
+    #[test]
+    fn pinned_particles_hold_still_and_keep_their_flag_in_files() {
+        let mut scene = scene(vec![
+            (300.0, 300.0, CHARGE_STEP),
+            (340.0, 300.0, -CHARGE_STEP),
+        ]);
+        scene.meters_per_pixel = DEFAULT_METERS_PER_PIXEL;
+        scene.particles[0].pinned = true;
+
+        scene.step_dynamics(DYNAMICS_TIMESTEP);
+        assert_eq!(scene.particles[0].pos(), (300.0, 300.0));
+        assert!(scene.particles[1].x < 340.0);
+        assert_eq!(scene.predict_trajectory(0, DYNAMICS_TIMESTEP, 10).len(), 1);
+
+        let json = serde_json::to_string(&scene.particles).unwrap();
+        assert!(json.starts_with("[[300.0,300.0,") && json.contains(",true],["));
+        let loaded: Vec<ChargedParticle> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, scene.particles);
+    }
+
+    #[test]
+    fn gauss_flux_matches_enclosed_charge() {
+        let mut scene = scene(vec![
+            (100.0, 100.0, 2.0 * CHARGE_STEP),
+            (130.0, 170.0, -CHARGE_STEP),
+            (400.0, 100.0, 5.0 * CHARGE_STEP),
+        ]);
+        scene
+            .lines
+            .push(LineCharge::new((60.0, 80.0), (60.0, 180.0), CHARGE_STEP));
+        let rect = ((50.0, 50.0), (200.0, 200.0));
+
+        let enclosed = scene.get_enclosed_charge(rect);
+        assert!((enclosed - 2.0 * CHARGE_STEP).abs() < 1e-30);
+
+        let expected = enclosed / VACUUM_PERMITTIVITY;
+        assert!((scene.get_gauss_flux(rect, 300) - expected).abs() < 1e-3 * expected);
+
+        // Only charges outside, so everything that flows in flows back out
+        let empty = ((250.0, 50.0), (350.0, 200.0));
+        assert_eq!(scene.get_enclosed_charge(empty), 0.0);
+        assert!(scene.get_gauss_flux(empty, 300).abs() < 1e-3 * expected);
+    }
+
+    #[test]
+    fn test_charge_is_pushed_away_and_pulled_in() {
+        // At the default scale the mass is tuned for
+        let mut scene = scene(vec![(0.0, 0.0, CHARGE_STEP), (400.0, 0.0, -CHARGE_STEP)]);
+        scene.meters_per_pixel = DEFAULT_METERS_PER_PIXEL;
+        let field_before = scene.get_field_strength(100.0, 50.0);
+
+        let mut test = TestCharge::new((100.0, 0.0));
+        assert!(scene.step_test_charge(&mut test, DYNAMICS_TIMESTEP));
+        assert!(test.pos.0 > 100.0 && test.velocity.0 > 0.0);
+        assert_eq!(test.trail.len(), 2);
+        assert_eq!(scene.get_field_strength(100.0, 50.0), field_before);
+
+        // It ends up on the negative charge, and the trail stays bounded on the way
+        let mut steps = 1;
+        while scene.step_test_charge(&mut test, DYNAMICS_TIMESTEP) {
+            steps += 1;
+            assert!(steps < 10_000);
+        }
+        assert!((test.pos.0 - 400.0).hypot(test.pos.1) <= PARTICLE_RADIUS);
+        assert!(test.trail.len() <= TEST_CHARGE_TRAIL_LENGTH);
+    }
+
+    #[test]
+    fn wrap_position_reenters_from_opposite_edge() {
+        let scene = periodic_scene(vec![]);
+
+        let mut pos = (721.0, -2.0);
+        assert!(scene.wrap_position(&mut pos));
+        assert!((pos.0 - 1.0).abs() < 1e-9 && (pos.1 - 598.0).abs() < 1e-9);
+
+        let mut pos = (10.0, 10.0);
+        assert!(!scene.wrap_position(&mut pos));
+    }
+}